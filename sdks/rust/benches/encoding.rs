@@ -0,0 +1,67 @@
+//! Compares export payload size and encoding time for each protocol
+//!
+//! Run with `cargo bench --bench encoding`.
+
+use opentelemetry::trace::{Span as _, TracerProvider as _};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+use opentelemetry_sdk::trace::TracerProvider;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use untrace::{Config, LLMSpanOptions, Protocol, UntraceTracer};
+
+const BATCH_SIZE: usize = 512;
+const ITERATIONS: u32 = 200;
+
+/// Record a batch of LLM spans shaped like typical production traffic
+fn sample_batch() -> Vec<SpanData> {
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let tracer = UntraceTracer::with_config(
+        opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("bench"))),
+        std::sync::Arc::new(Config::new("bench-key".to_string())),
+    );
+
+    for i in 0..BATCH_SIZE {
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            prompt_tokens: Some(200 + i as u32),
+            completion_tokens: Some(80),
+            temperature: Some(0.7),
+            request_id: Some(format!("chatcmpl-{:08}", i)),
+            usage_reason: Some("stop".to_string()),
+            ..Default::default()
+        };
+        tracer.start_llm_span("llm-chat", options).end();
+    }
+    provider.force_flush();
+
+    exporter.get_finished_spans().unwrap()
+}
+
+fn main() {
+    let batch = sample_batch();
+
+    for protocol in [Protocol::HttpProtobuf, Protocol::HttpJson] {
+        let size = protocol.encode(batch.clone()).unwrap().len();
+
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..ITERATIONS {
+            let spans = batch.clone();
+            let start = Instant::now();
+            black_box(protocol.encode(black_box(spans)).unwrap());
+            elapsed += start.elapsed();
+        }
+
+        println!(
+            "{:<14} {:>9} bytes for {} spans, {:?} per batch",
+            protocol.to_string(),
+            size,
+            BATCH_SIZE,
+            elapsed / ITERATIONS
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Compares allocations per span for map-based and borrowed span attributes
+//!
+//! Run with `cargo bench --bench span_attributes`.
+
+use opentelemetry::trace::{Span as _, TracerProvider as _};
+use opentelemetry_sdk::trace::TracerProvider;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use untrace::{AttributeValue, Config, SpanOptions, UntraceTracer};
+
+const ITERATIONS: usize = 10_000;
+
+/// System allocator that counts allocations
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run `f` for every iteration and report allocations and time per call
+fn measure(label: &str, mut f: impl FnMut()) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "{:<10} {:>6.1} allocations per span, {:?} per span",
+        label,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed / ITERATIONS as u32
+    );
+}
+
+fn main() {
+    let provider = TracerProvider::builder().build();
+    let tracer = UntraceTracer::with_config(
+        opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("bench"))),
+        std::sync::Arc::new(Config::new("bench-key".to_string())),
+    );
+
+    let provider_name = AttributeValue::from("openai");
+    let model = AttributeValue::from("gpt-4o-mini");
+    let route = AttributeValue::from("/v1/chat");
+
+    measure("map", || {
+        let attributes = HashMap::from([
+            ("llm.provider".to_string(), "openai".to_string()),
+            ("llm.model".to_string(), "gpt-4o-mini".to_string()),
+            ("app.route".to_string(), "/v1/chat".to_string()),
+        ]);
+        let mut span = tracer.start_span_with_options(SpanOptions {
+            name: "request".to_string(),
+            attributes,
+            ..Default::default()
+        });
+        span.end();
+        black_box(span);
+    });
+
+    measure("borrowed", || {
+        let mut span = tracer.start_span_borrowed(
+            "request",
+            &[
+                ("llm.provider", &provider_name),
+                ("llm.model", &model),
+                ("app.route", &route),
+            ],
+        );
+        span.end();
+        black_box(span);
+    });
+}
@@ -0,0 +1,91 @@
+//! Build script for the Untrace SDK
+//!
+//! Reads the versions of optional provider SDK crates from the lockfile so
+//! they can be recorded on spans. Cargo does not expose the versions of
+//! dependencies to the crates using them, so the lockfile of the build is
+//! the only place to find them.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Optional provider SDK crates and the variables their versions are exported as
+const PROVIDER_SDKS: &[(&str, &str, &str)] = &[(
+    "CARGO_FEATURE_ASYNC_OPENAI",
+    "async-openai",
+    "UNTRACE_ASYNC_OPENAI_VERSION",
+)];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let enabled: Vec<_> = PROVIDER_SDKS
+        .iter()
+        .filter(|(feature, _, _)| env::var_os(feature).is_some())
+        .collect();
+    if enabled.is_empty() {
+        return;
+    }
+
+    let Some(lockfile) = find_lockfile() else {
+        return;
+    };
+    println!("cargo:rerun-if-changed={}", lockfile.display());
+    let Ok(contents) = fs::read_to_string(&lockfile) else {
+        return;
+    };
+
+    for (_, package, variable) in enabled {
+        if let Some(version) = locked_version(&contents, package) {
+            println!("cargo:rustc-env={}={}", variable, version);
+        }
+    }
+}
+
+/// Find the lockfile of the build, next to the target directory or above the manifest
+fn find_lockfile() -> Option<PathBuf> {
+    ["OUT_DIR", "CARGO_MANIFEST_DIR"]
+        .iter()
+        .filter_map(env::var_os)
+        .flat_map(|dir| {
+            Path::new(&dir)
+                .ancestors()
+                .map(|ancestor| ancestor.join("Cargo.lock"))
+                .collect::<Vec<_>>()
+        })
+        .find(|path| path.is_file())
+}
+
+/// Get the version of a dependency of this crate from a lockfile
+///
+/// This crate's own entry names the dependency with its version when
+/// several versions are locked, e.g. `"async-openai 0.28.3"`, and by name
+/// alone when only one is.
+fn locked_version(lockfile: &str, package: &str) -> Option<String> {
+    let entries: Vec<&str> = lockfile.split("[[package]]").collect();
+    let field = |entry: &str, key: &str| {
+        entry.lines().find_map(|line| {
+            let value = line.trim().strip_prefix(key)?.trim_start().strip_prefix('=')?;
+            Some(value.trim().trim_matches('"').to_string())
+        })
+    };
+
+    let name = env::var("CARGO_PKG_NAME").ok()?;
+    let version = env::var("CARGO_PKG_VERSION").ok()?;
+    let own = entries.iter().find(|entry| {
+        field(entry, "name").as_deref() == Some(&name)
+            && field(entry, "version").as_deref() == Some(&version)
+    })?;
+    let dependency = own
+        .lines()
+        .map(|line| line.trim().trim_end_matches(',').trim_matches('"'))
+        .find(|dependency| dependency.split(' ').next() == Some(package))?;
+    if let Some(version) = dependency.split(' ').nth(1) {
+        return Some(version.to_string());
+    }
+
+    entries
+        .iter()
+        .find(|entry| field(entry, "name").as_deref() == Some(package))
+        .and_then(|entry| field(entry, "version"))
+}
@@ -57,7 +57,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         prompt: 0.01,
         completion: 0.02,
         total: 0.03,
-        currency: "USD".to_string(),
+        currency: untrace::Currency::Usd,
         model: "gpt-4".to_string(),
         provider: "openai".to_string(),
     };
@@ -25,12 +25,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create a workflow
     let mut metadata = HashMap::new();
-    metadata.insert("user_id".to_string(), "user123".to_string());
-    metadata.insert("session_id".to_string(), "session456".to_string());
+    metadata.insert("user_id".to_string(), "user123".into());
+    metadata.insert("session_id".to_string(), "session456".into());
 
     let workflow_options = WorkflowOptions {
         user_id: Some("user123".to_string()),
         session_id: Some("session456".to_string()),
+        conversation_id: None,
         version: Some("1.0.0".to_string()),
         parent_id: None,
         metadata,
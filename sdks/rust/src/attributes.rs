@@ -3,11 +3,22 @@
 use opentelemetry::{Key, KeyValue};
 use std::collections::HashMap;
 
+/// Namespaces of the attributes set by the SDK
+pub const NAMESPACES: &[&str] = &[
+    "llm",
+    "vector_db",
+    "retrieval",
+    "framework",
+    "workflow",
+    "panic",
+    "feedback",
+    "untrace",
+];
+
 /// LLM-specific attributes
 pub mod llm {
-    use super::*;
-
     pub const PROVIDER: &str = "llm.provider";
+    pub const PROVIDER_SDK_VERSION: &str = "llm.provider.sdk_version";
     pub const MODEL: &str = "llm.model";
     pub const OPERATION: &str = "llm.operation";
     pub const PROMPT_TOKENS: &str = "llm.prompt_tokens";
@@ -17,9 +28,13 @@ pub mod llm {
     pub const TOP_P: &str = "llm.top_p";
     pub const MAX_TOKENS: &str = "llm.max_tokens";
     pub const STREAM: &str = "llm.stream";
+    pub const STREAM_CHUNK_COUNT: &str = "llm.stream.chunk_count";
+    pub const STREAM_BYTES: &str = "llm.stream.bytes";
     pub const TOOLS: &str = "llm.tools";
     pub const TOOL_CALLS: &str = "llm.tool_calls";
     pub const DURATION_MS: &str = "llm.duration_ms";
+    pub const TOKENS_PER_SECOND: &str = "llm.tokens_per_second";
+    pub const COMPLETION_RATIO: &str = "llm.completion_ratio";
     pub const COST_PROMPT: &str = "llm.cost_prompt";
     pub const COST_COMPLETION: &str = "llm.cost_completion";
     pub const COST_TOTAL: &str = "llm.cost_total";
@@ -27,12 +42,24 @@ pub mod llm {
     pub const ERROR_TYPE: &str = "llm.error_type";
     pub const REQUEST_ID: &str = "llm.request_id";
     pub const USAGE_REASON: &str = "llm.usage_reason";
+    pub const HTTP_STATUS_CODE: &str = "llm.http.status_code";
+    pub const PROMPT: &str = "llm.prompt";
+    pub const COMPLETION: &str = "llm.completion";
+    pub const IMAGE_COUNT: &str = "llm.image.count";
+    pub const IMAGE_SIZE: &str = "llm.image.size";
+    pub const AUDIO_DURATION_SECONDS: &str = "llm.audio.duration_seconds";
+    pub const AUDIO_FORMAT: &str = "llm.audio.format";
+    pub const AZURE_DEPLOYMENT: &str = "llm.azure.deployment";
+    pub const AZURE_API_VERSION: &str = "llm.azure.api_version";
+    pub const MODERATION_FLAGGED: &str = "llm.moderation.flagged";
+    pub const MODERATION_CATEGORY: &str = "llm.moderation.category";
+    pub const CACHE_HIT: &str = "llm.cache.hit";
+    pub const CONVERSATION_ID: &str = "llm.conversation.id";
+    pub const CONVERSATION_MESSAGE_INDEX: &str = "llm.conversation.message_index";
 }
 
 /// Vector database attributes
 pub mod vector_db {
-    use super::*;
-
     pub const PROVIDER: &str = "vector_db.provider";
     pub const COLLECTION: &str = "vector_db.collection";
     pub const OPERATION: &str = "vector_db.operation";
@@ -45,10 +72,17 @@ pub mod vector_db {
     pub const METADATA: &str = "vector_db.metadata";
 }
 
+/// Retrieval (RAG) attributes
+pub mod retrieval {
+    pub const INDEX: &str = "retrieval.index";
+    pub const QUERY: &str = "retrieval.query";
+    pub const TOP_K: &str = "retrieval.top_k";
+    pub const RETRIEVED_COUNT: &str = "retrieval.retrieved_count";
+    pub const SCORES: &str = "retrieval.scores";
+}
+
 /// Framework attributes
 pub mod framework {
-    use super::*;
-
     pub const NAME: &str = "framework.name";
     pub const VERSION: &str = "framework.version";
     pub const TYPE: &str = "framework.type";
@@ -64,13 +98,12 @@ pub mod framework {
 
 /// Workflow attributes
 pub mod workflow {
-    use super::*;
-
     pub const ID: &str = "workflow.id";
     pub const NAME: &str = "workflow.name";
     pub const RUN_ID: &str = "workflow.run_id";
     pub const USER_ID: &str = "workflow.user_id";
     pub const SESSION_ID: &str = "workflow.session_id";
+    pub const CONVERSATION_ID: &str = "workflow.conversation_id";
     pub const VERSION: &str = "workflow.version";
     pub const PARENT_ID: &str = "workflow.parent_id";
     pub const STATUS: &str = "workflow.status";
@@ -78,6 +111,33 @@ pub mod workflow {
     pub const ERROR: &str = "workflow.error";
     pub const ERROR_TYPE: &str = "workflow.error_type";
     pub const METADATA: &str = "workflow.metadata";
+    pub const LLM_CALLS: &str = "workflow.llm_calls";
+    pub const OPERATIONS: &str = "workflow.operations";
+    pub const PROMPT_TOKENS: &str = "workflow.prompt_tokens";
+    pub const COMPLETION_TOKENS: &str = "workflow.completion_tokens";
+    pub const TOTAL_TOKENS: &str = "workflow.total_tokens";
+    pub const COST_TOTAL: &str = "workflow.cost_total";
+}
+
+/// Panic attributes
+pub mod panic {
+    pub const MESSAGE: &str = "panic.message";
+    pub const LOCATION: &str = "panic.location";
+    pub const THREAD: &str = "panic.thread";
+}
+
+/// User feedback attributes
+pub mod feedback {
+    pub const SCORE: &str = "feedback.score";
+    pub const LABEL: &str = "feedback.label";
+    pub const COMMENT: &str = "feedback.comment";
+    pub const TRACE_ID: &str = "feedback.trace_id";
+    pub const SPAN_ID: &str = "feedback.span_id";
+}
+
+/// Attributes recorded by the SDK itself
+pub mod sdk {
+    pub const DROPPED_EVENTS: &str = "untrace.dropped_events";
 }
 
 /// Create LLM attributes from a map
@@ -147,39 +207,285 @@ pub fn merge_attributes(
 /// Helper functions for creating common attribute values
 pub mod helpers {
     use super::*;
+    use std::borrow::Cow;
+    use std::collections::HashSet;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    /// OpenTelemetry semantic convention namespaces, never prefixed
+    const RESERVED_NAMESPACES: &[&str] = &[
+        "service.",
+        "telemetry.",
+        "otel.",
+        "deployment.",
+        "host.",
+        "process.",
+        "thread.",
+        "code.",
+        "exception.",
+        "error.",
+        "http.",
+        "url.",
+        "server.",
+        "client.",
+        "network.",
+        "net.",
+        "peer.",
+        "user_agent.",
+        "rpc.",
+        "db.",
+        "messaging.",
+        "gen_ai.",
+        "cloud.",
+        "container.",
+        "k8s.",
+    ];
+
+    /// Marker appended to truncated attribute values
+    pub const TRUNCATION_MARKER: &str = "…";
+
+    /// SDK attribute keys, interned so spans don't allocate a copy of them
+    const INTERNED_KEYS: &[&str] = &[
+        llm::PROVIDER,
+        llm::PROVIDER_SDK_VERSION,
+        llm::MODEL,
+        llm::OPERATION,
+        llm::PROMPT_TOKENS,
+        llm::COMPLETION_TOKENS,
+        llm::TOTAL_TOKENS,
+        llm::TEMPERATURE,
+        llm::TOP_P,
+        llm::MAX_TOKENS,
+        llm::STREAM,
+        llm::STREAM_CHUNK_COUNT,
+        llm::STREAM_BYTES,
+        llm::TOOLS,
+        llm::TOOL_CALLS,
+        llm::DURATION_MS,
+        llm::TOKENS_PER_SECOND,
+        llm::COMPLETION_RATIO,
+        llm::COST_PROMPT,
+        llm::COST_COMPLETION,
+        llm::COST_TOTAL,
+        llm::ERROR,
+        llm::ERROR_TYPE,
+        llm::REQUEST_ID,
+        llm::USAGE_REASON,
+        llm::HTTP_STATUS_CODE,
+        llm::PROMPT,
+        llm::COMPLETION,
+        llm::IMAGE_COUNT,
+        llm::IMAGE_SIZE,
+        llm::AUDIO_DURATION_SECONDS,
+        llm::AUDIO_FORMAT,
+        llm::AZURE_DEPLOYMENT,
+        llm::AZURE_API_VERSION,
+        llm::MODERATION_FLAGGED,
+        llm::MODERATION_CATEGORY,
+        llm::CACHE_HIT,
+        llm::CONVERSATION_ID,
+        llm::CONVERSATION_MESSAGE_INDEX,
+        vector_db::PROVIDER,
+        vector_db::COLLECTION,
+        vector_db::OPERATION,
+        vector_db::DIMENSIONS,
+        vector_db::VECTOR_COUNT,
+        vector_db::QUERY_VECTOR_COUNT,
+        vector_db::RESULT_COUNT,
+        vector_db::SIMILARITY_THRESHOLD,
+        vector_db::FILTER,
+        vector_db::METADATA,
+        retrieval::INDEX,
+        retrieval::QUERY,
+        retrieval::TOP_K,
+        retrieval::RETRIEVED_COUNT,
+        retrieval::SCORES,
+        framework::NAME,
+        framework::VERSION,
+        framework::TYPE,
+        framework::OPERATION,
+        framework::COMPONENT,
+        framework::METHOD,
+        framework::ROUTE,
+        framework::STATUS_CODE,
+        framework::DURATION_MS,
+        framework::ERROR,
+        framework::ERROR_TYPE,
+        workflow::ID,
+        workflow::NAME,
+        workflow::RUN_ID,
+        workflow::USER_ID,
+        workflow::SESSION_ID,
+        workflow::CONVERSATION_ID,
+        workflow::VERSION,
+        workflow::PARENT_ID,
+        workflow::STATUS,
+        workflow::DURATION_MS,
+        workflow::ERROR,
+        workflow::ERROR_TYPE,
+        workflow::METADATA,
+        workflow::LLM_CALLS,
+        workflow::OPERATIONS,
+        workflow::PROMPT_TOKENS,
+        workflow::COMPLETION_TOKENS,
+        workflow::TOTAL_TOKENS,
+        workflow::COST_TOTAL,
+        panic::MESSAGE,
+        panic::LOCATION,
+        panic::THREAD,
+        feedback::SCORE,
+        feedback::LABEL,
+        feedback::COMMENT,
+        feedback::TRACE_ID,
+        feedback::SPAN_ID,
+        sdk::DROPPED_EVENTS,
+    ];
+
+    /// Lookup set for [`INTERNED_KEYS`]
+    static INTERNED: OnceLock<HashSet<&'static str>> = OnceLock::new();
+
+    /// Get the key for an attribute name
+    ///
+    /// The SDK's own attribute keys are returned as static keys without
+    /// allocating. Any other name is copied into a new key.
+    pub fn key(name: &str) -> Key {
+        let interned = INTERNED.get_or_init(|| INTERNED_KEYS.iter().copied().collect());
+        match interned.get(name) {
+            Some(name) => Key::from_static_str(name),
+            None => Key::new(name.to_string()),
+        }
+    }
 
     /// Create a string attribute
     pub fn string(key: &str, value: &str) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value.to_string())
+        KeyValue::new(self::key(key), value.to_string())
     }
 
     /// Create an integer attribute
     pub fn int(key: &str, value: i64) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value)
+        KeyValue::new(self::key(key), value)
     }
 
     /// Create a float attribute
     pub fn float(key: &str, value: f64) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value)
+        KeyValue::new(self::key(key), value)
     }
 
     /// Create a boolean attribute
     pub fn bool(key: &str, value: bool) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value)
+        KeyValue::new(self::key(key), value)
     }
 
     /// Create a string slice attribute
     pub fn string_slice(key: &str, value: Vec<String>) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value.join(","))
+        KeyValue::new(self::key(key), value.join(","))
     }
 
     /// Create an integer slice attribute
     pub fn int_slice(key: &str, value: Vec<i64>) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","))
+        KeyValue::new(self::key(key), value.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","))
     }
 
     /// Create a float slice attribute
     pub fn float_slice(key: &str, value: Vec<f64>) -> KeyValue {
-        KeyValue::new(Key::new(key.to_string()), value.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","))
+        KeyValue::new(self::key(key), value.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(","))
+    }
+
+    /// Create a duration attribute in milliseconds
+    ///
+    /// The key is suffixed with `_ms` unless it already ends with it.
+    pub fn duration_ms(key: &str, value: Duration) -> KeyValue {
+        int(&with_suffix(key, "_ms"), value.as_millis() as i64)
+    }
+
+    /// Create a size attribute in bytes
+    ///
+    /// The key is suffixed with `_bytes` unless it already ends with it.
+    pub fn bytes(key: &str, value: u64) -> KeyValue {
+        int(&with_suffix(key, "_bytes"), value as i64)
+    }
+
+    /// Truncate a value to at most `max_len` bytes, ending with [`TRUNCATION_MARKER`]
+    ///
+    /// Cuts on a character boundary, so multi-byte characters such as emoji
+    /// are never split. Values that already fit are returned unchanged.
+    pub fn truncate(value: &str, max_len: usize) -> Cow<'_, str> {
+        if value.len() <= max_len {
+            return Cow::Borrowed(value);
+        }
+
+        let (budget, marker) = if max_len >= TRUNCATION_MARKER.len() {
+            (max_len - TRUNCATION_MARKER.len(), TRUNCATION_MARKER)
+        } else {
+            (max_len, "")
+        };
+        let mut end = budget;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        Cow::Owned(format!("{}{}", &value[..end], marker))
+    }
+
+    /// Check whether a key belongs to an OpenTelemetry semantic convention namespace
+    pub fn is_otel_reserved(key: &str) -> bool {
+        RESERVED_NAMESPACES
+            .iter()
+            .any(|namespace| key.starts_with(namespace))
+    }
+
+    /// Namespace a key as `{prefix}.{key}`
+    ///
+    /// OpenTelemetry-reserved keys and keys that already carry the prefix are
+    /// returned unchanged.
+    pub fn with_prefix<'a>(prefix: &str, key: &'a str) -> Cow<'a, str> {
+        let prefix = prefix.trim_end_matches('.');
+        let already_prefixed = key
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('.'));
+        if prefix.is_empty() || already_prefixed || is_otel_reserved(key) {
+            Cow::Borrowed(key)
+        } else {
+            Cow::Owned(format!("{}.{}", prefix, key))
+        }
+    }
+
+    /// Replace `{key}` placeholders in a template with attribute values
+    ///
+    /// Keys match with or without an attribute prefix. Placeholders naming
+    /// an attribute that isn't set, and unclosed braces, are left as written.
+    pub fn render_template(template: &str, attributes: &[KeyValue]) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let Some(close) = after.find('}') else {
+                rest = &rest[open..];
+                break;
+            };
+
+            let name = &after[..close];
+            match attributes.iter().rev().find(|kv| matches_key(kv.key.as_str(), name)) {
+                Some(kv) if !name.is_empty() => rendered.push_str(&kv.value.as_str()),
+                _ => rendered.push_str(&rest[open..open + close + 2]),
+            }
+            rest = &after[close + 1..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    /// Check whether `key` is the standard key `name`, with or without a prefix
+    pub fn matches_key(key: &str, name: &str) -> bool {
+        key.strip_suffix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+    }
+
+    fn with_suffix(key: &str, suffix: &str) -> String {
+        if key.ends_with(suffix) {
+            key.to_string()
+        } else {
+            format!("{}{}", key, suffix)
+        }
     }
 }
@@ -4,6 +4,8 @@ use crate::context::UntraceContext;
 use crate::error::UntraceResult;
 use crate::metrics::UntraceMetrics;
 use crate::tracer::UntraceTracer;
+#[cfg(feature = "logs")]
+use opentelemetry::KeyValue;
 use std::sync::Arc;
 
 /// Untrace client interface
@@ -12,6 +14,8 @@ pub struct UntraceClient {
     tracer: Arc<UntraceTracer>,
     metrics: Arc<UntraceMetrics>,
     context: Arc<UntraceContext>,
+    #[cfg(feature = "logs")]
+    logger: Option<Arc<opentelemetry_sdk::logs::Logger>>,
 }
 
 impl UntraceClient {
@@ -25,9 +29,60 @@ impl UntraceClient {
             tracer,
             metrics,
             context,
+            #[cfg(feature = "logs")]
+            logger: None,
         }
     }
 
+    /// Emit LLM inputs and outputs through the given logger
+    #[cfg(feature = "logs")]
+    pub fn with_logger(mut self, logger: opentelemetry_sdk::logs::Logger) -> Self {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    /// Record an LLM call's prompt and completion as a log record
+    ///
+    /// The record's body holds `prompt` and `completion`, its `event.name`
+    /// is [`LLM_IO_EVENT`](crate::logs::LLM_IO_EVENT) and it carries the
+    /// active span's trace and span IDs. Nothing is emitted if no logger is
+    /// set or the body isn't sampled for capture.
+    #[cfg(feature = "logs")]
+    pub fn log_llm_io(&self, prompt: &str, completion: &str, attributes: Vec<KeyValue>) {
+        use opentelemetry::logs::{AnyValue, LogRecord, Logger as _, Severity};
+        use opentelemetry::Key;
+
+        let Some(logger) = &self.logger else {
+            return;
+        };
+        if !self.tracer.should_capture_body() {
+            return;
+        }
+
+        let body = [("prompt", prompt), ("completion", completion)]
+            .into_iter()
+            .map(|(key, value)| (Key::from(key), AnyValue::from(value.to_string())))
+            .collect();
+        let mut record_attributes = vec![(
+            Key::from("event.name"),
+            AnyValue::from(crate::logs::LLM_IO_EVENT),
+        )];
+        record_attributes.extend(
+            attributes
+                .into_iter()
+                .map(|kv| (kv.key, AnyValue::from(kv.value))),
+        );
+
+        logger.emit(
+            LogRecord::builder()
+                .with_context(&opentelemetry::Context::current())
+                .with_severity_number(Severity::Info)
+                .with_body(AnyValue::Map(body))
+                .with_attributes(record_attributes)
+                .build(),
+        );
+    }
+
     /// Get the tracer
     pub fn tracer(&self) -> &Arc<UntraceTracer> {
         &self.tracer
@@ -65,6 +120,8 @@ impl Clone for UntraceClient {
             tracer: Arc::clone(&self.tracer),
             metrics: Arc::clone(&self.metrics),
             context: Arc::clone(&self.context),
+            #[cfg(feature = "logs")]
+            logger: self.logger.clone(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+//! Time sources for the Untrace SDK
+//!
+//! Production code always uses [`SystemClock`]. With the `testing` feature
+//! enabled, a [`MockClock`] can be injected into workflows and metrics to
+//! make durations deterministic.
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::time::Instant;
+
+#[cfg(feature = "testing")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "testing")]
+use std::time::Duration;
+
+/// Source of monotonic and wall-clock time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current monotonic time, used for measuring durations
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time, used for timestamps
+    fn utc_now(&self) -> DateTime<Utc>;
+}
+
+/// Clock backed by the operating system
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock handle stored by workflows and metrics
+#[cfg(not(feature = "testing"))]
+pub(crate) type SharedClock = SystemClock;
+
+/// Clock handle stored by workflows and metrics
+#[cfg(feature = "testing")]
+pub(crate) type SharedClock = Arc<dyn Clock>;
+
+/// Get the default clock handle
+pub(crate) fn default_clock() -> SharedClock {
+    #[cfg(not(feature = "testing"))]
+    {
+        SystemClock
+    }
+    #[cfg(feature = "testing")]
+    {
+        Arc::new(SystemClock)
+    }
+}
+
+/// Manually controlled clock for deterministic tests
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    state: Arc<Mutex<MockClockState>>,
+}
+
+#[cfg(feature = "testing")]
+#[derive(Debug)]
+struct MockClockState {
+    elapsed: Duration,
+    utc: DateTime<Utc>,
+}
+
+#[cfg(feature = "testing")]
+impl MockClock {
+    /// Create a new mock clock frozen at the current time
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            state: Arc::new(Mutex::new(MockClockState {
+                elapsed: Duration::ZERO,
+                utc: Utc::now(),
+            })),
+        }
+    }
+
+    /// Move both the monotonic and wall-clock time forward
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.elapsed += duration;
+        state.utc +=
+            chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+    }
+
+    /// Set the wall-clock time without affecting monotonic time
+    ///
+    /// Useful for simulating the system clock jumping backward.
+    pub fn set_utc(&self, utc: DateTime<Utc>) {
+        self.state.lock().unwrap().utc = utc;
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.state.lock().unwrap().elapsed
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().utc
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Clock for Arc<dyn Clock> {
+    fn now(&self) -> Instant {
+        self.as_ref().now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.as_ref().utc_now()
+    }
+}
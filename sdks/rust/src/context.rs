@@ -1,33 +1,320 @@
 //! Context management for the Untrace SDK
 
-use crate::error::UntraceResult;
-use crate::types::{Workflow, WorkflowOptions};
+use crate::error::{UntraceError, UntraceResult};
+use crate::types::{AttributeValue, Workflow, WorkflowOptions};
+use opentelemetry::trace::{
+    FutureExt, SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::{Context, ContextGuard};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+/// Get the active OpenTelemetry context for manual propagation
+pub fn current() -> Context {
+    Context::current()
+}
+
+/// Get the active span's context, if it is valid
+fn current_span_context() -> Option<SpanContext> {
+    let cx = Context::current();
+    let span_context = cx.span().span_context().clone();
+    span_context.is_valid().then_some(span_context)
+}
+
+/// Get the hex trace ID of the active span, for correlating logs with traces
+pub fn current_trace_id() -> Option<String> {
+    current_span_context().map(|span_context| span_context.trace_id().to_string())
+}
+
+/// Get the hex span ID of the active span, for correlating logs with traces
+pub fn current_span_id() -> Option<String> {
+    current_span_context().map(|span_context| span_context.span_id().to_string())
+}
+
+/// Record the active span's IDs on a `tracing` span
+///
+/// The span must declare `trace_id` and `span_id` fields, which are left
+/// empty if no OpenTelemetry span is active:
+///
+/// ```
+/// let span = tracing::info_span!(
+///     "handle_request",
+///     trace_id = tracing::field::Empty,
+///     span_id = tracing::field::Empty,
+/// );
+/// untrace::context::record_ids(&span);
+/// ```
+pub fn record_ids(span: &tracing::Span) {
+    if let Some(span_context) = current_span_context() {
+        span.record("trace_id", span_context.trace_id().to_string());
+        span.record("span_id", span_context.span_id().to_string());
+    }
+}
+
+/// Spawn a tokio task that runs within the current OpenTelemetry context
+///
+/// Spans created inside the task become children of the span that was active
+/// when `spawn_traced` was called, instead of orphan roots.
+pub fn spawn_traced<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.with_context(current()))
+}
+
+/// Capture the active OpenTelemetry context to attach on another thread
+///
+/// For thread pools that don't propagate context themselves:
+///
+/// ```
+/// let snapshot = untrace::context::capture();
+/// std::thread::spawn(move || {
+///     let _guard = snapshot.attach();
+///     // Spans started here are children of the span active at capture
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+pub fn capture() -> ContextSnapshot {
+    ContextSnapshot {
+        context: Context::current(),
+    }
+}
+
+/// OpenTelemetry context captured by [`capture`]
+#[derive(Debug, Clone)]
+pub struct ContextSnapshot {
+    context: Context,
+}
+
+impl ContextSnapshot {
+    /// Make the captured context active on this thread until the guard is dropped
+    ///
+    /// The thread's previous context is restored when the guard drops.
+    pub fn attach(&self) -> ContextGuard {
+        self.context.clone().attach()
+    }
+
+    /// Get the captured context
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+/// Span context that can be sent across process boundaries
+///
+/// Formats as a W3C `traceparent` value, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, and serializes
+/// as that string so it can travel through message queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerializableSpanContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    pub trace_flags: TraceFlags,
+}
+
+impl SerializableSpanContext {
+    /// Convert into a remote span context that can be used as a parent
+    pub fn to_span_context(&self) -> SpanContext {
+        SpanContext::new(
+            self.trace_id,
+            self.span_id,
+            self.trace_flags,
+            true,
+            TraceState::default(),
+        )
+    }
+}
+
+impl From<&SpanContext> for SerializableSpanContext {
+    fn from(span_context: &SpanContext) -> Self {
+        Self {
+            trace_id: span_context.trace_id(),
+            span_id: span_context.span_id(),
+            trace_flags: span_context.trace_flags(),
+        }
+    }
+}
+
+impl fmt::Display for SerializableSpanContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            self.trace_flags.to_u8()
+        )
+    }
+}
+
+impl FromStr for SerializableSpanContext {
+    type Err = UntraceError;
+
+    fn from_str(value: &str) -> UntraceResult<Self> {
+        let invalid = || UntraceError::validation(format!("Invalid traceparent {:?}", value));
+
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return Err(invalid());
+        };
+
+        let is_hex = |part: &str, len: usize| {
+            part.len() == len && part.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        if version != "00" || !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return Err(invalid());
+        }
+
+        let trace_id = TraceId::from_hex(trace_id).map_err(|_| invalid())?;
+        let span_id = SpanId::from_hex(span_id).map_err(|_| invalid())?;
+        let flags = u8::from_str_radix(flags, 16).map_err(|_| invalid())?;
+        if trace_id == TraceId::INVALID || span_id == SpanId::INVALID {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            trace_id,
+            span_id,
+            trace_flags: TraceFlags::new(flags),
+        })
+    }
+}
+
+impl Serialize for SerializableSpanContext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SerializableSpanContext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Format of generated workflow run IDs
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RunIdFormat {
+    /// Random UUID, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    #[default]
+    Uuid,
+    /// ULID, which sorts by creation time, e.g. `01ARZ3NDEKTSV4RRFFQ69G5FAV`
+    Ulid,
+    /// ULID after a prefix and `_`, e.g. `run_01ARZ3NDEKTSV4RRFFQ69G5FAV`
+    Prefixed(String),
+}
+
+impl RunIdFormat {
+    /// Generate a run ID in this format
+    pub fn generate(&self) -> String {
+        match self {
+            RunIdFormat::Uuid => Uuid::new_v4().to_string(),
+            RunIdFormat::Ulid => ulid(),
+            RunIdFormat::Prefixed(prefix) => format!("{}_{}", prefix, ulid()),
+        }
+    }
+}
+
+impl fmt::Display for RunIdFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunIdFormat::Uuid => write!(f, "uuid"),
+            RunIdFormat::Ulid => write!(f, "ulid"),
+            RunIdFormat::Prefixed(prefix) => write!(f, "prefixed:{}", prefix),
+        }
+    }
+}
+
+impl FromStr for RunIdFormat {
+    type Err = UntraceError;
+
+    /// Parses `uuid`, `ulid` or `prefixed:<prefix>`
+    fn from_str(value: &str) -> UntraceResult<Self> {
+        let value = value.trim();
+        match value.split_once(':') {
+            Some((name, prefix)) if name.eq_ignore_ascii_case("prefixed") => {
+                Ok(RunIdFormat::Prefixed(prefix.trim().to_string()))
+            }
+            _ if value.eq_ignore_ascii_case("uuid") => Ok(RunIdFormat::Uuid),
+            _ if value.eq_ignore_ascii_case("ulid") => Ok(RunIdFormat::Ulid),
+            _ => Err(UntraceError::validation(format!(
+                "Unknown run ID format {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+impl Serialize for RunIdFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RunIdFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Crockford base32 digits used by ULIDs
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generate a ULID: 48 bits of Unix time in milliseconds and 80 random bits
+fn ulid() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    let value = (millis & ((1 << 48) - 1)) << 80 | rand::random::<u128>() >> 48;
+    (0..26)
+        .rev()
+        .map(|digit| ULID_ALPHABET[(value >> (digit * 5)) as usize & 31] as char)
+        .collect()
+}
+
 /// Untrace context manager
+///
+/// Keeps a stack of active workflows. The most recently started one that
+/// hasn't ended is the current workflow.
 #[derive(Debug)]
 pub struct UntraceContext {
-    current_workflow: Arc<RwLock<Option<Workflow>>>,
+    workflows: Arc<RwLock<Vec<Workflow>>>,
+    run_id_format: RunIdFormat,
 }
 
 impl UntraceContext {
     /// Create a new context manager
     pub fn new() -> Self {
+        Self::with_run_id_format(RunIdFormat::default())
+    }
+
+    /// Create a new context manager that generates run IDs in the given format
+    pub fn with_run_id_format(run_id_format: RunIdFormat) -> Self {
         Self {
-            current_workflow: Arc::new(RwLock::new(None)),
+            workflows: Arc::new(RwLock::new(Vec::new())),
+            run_id_format,
         }
     }
 
-    /// Start a new workflow
+    /// Start a new workflow, which becomes the current workflow until it ends
     pub fn start_workflow(&self, name: String, run_id: String, options: WorkflowOptions) -> UntraceResult<Workflow> {
         let workflow = Workflow::new(name, run_id, options);
 
-        // Store the current workflow
+        // Push the workflow onto the stack
         {
-            let mut current = self.current_workflow.write().unwrap();
-            *current = Some(workflow.clone());
+            let mut workflows = self.workflows.write().unwrap();
+            workflows.push(workflow.clone());
         }
 
         Ok(workflow)
@@ -35,44 +322,116 @@ impl UntraceContext {
 
     /// Get the current workflow
     pub fn get_current_workflow(&self) -> Option<Workflow> {
-        let current = self.current_workflow.read().unwrap();
-        current.clone()
+        let workflows = self.workflows.read().unwrap();
+        workflows.last().cloned()
     }
 
-    /// End the current workflow
+    /// End the current workflow, making the one started before it current again
     pub fn end_current_workflow(&self) -> UntraceResult<()> {
-        let mut current = self.current_workflow.write().unwrap();
-        *current = None;
+        let mut workflows = self.workflows.write().unwrap();
+        workflows.pop();
         Ok(())
     }
 
+    /// End the workflow with the given run ID and return it
+    ///
+    /// The workflow is removed wherever it is in the stack, so concurrent
+    /// workflows can end in any order. Returns an error if no active workflow
+    /// has the run ID.
+    pub fn end_workflow(&self, run_id: &str) -> UntraceResult<Workflow> {
+        let mut workflows = self.workflows.write().unwrap();
+        let index = workflows
+            .iter()
+            .rposition(|workflow| workflow.run_id == run_id)
+            .ok_or_else(|| {
+                UntraceError::validation(format!("No active workflow with run ID {:?}", run_id))
+            })?;
+        Ok(workflows.remove(index))
+    }
+
     /// Set an attribute on the current workflow
-    pub fn set_attribute(&self, _key: String, _value: String) -> UntraceResult<()> {
-        // Note: This is a simplified implementation
-        // In a real implementation, you'd need to update the workflow in place
-        // or use a different data structure that allows mutation
-        tracing::warn!("Setting attributes on workflows is not fully implemented yet");
+    ///
+    /// The attribute is stored in the workflow metadata. Keys must be
+    /// non-empty and free of whitespace.
+    pub fn set_attribute(
+        &self,
+        key: String,
+        value: impl Into<AttributeValue>,
+    ) -> UntraceResult<()> {
+        validate_attribute_key(&key)?;
+
+        let mut workflows = self.workflows.write().unwrap();
+        let workflow = workflows
+            .last_mut()
+            .ok_or_else(|| UntraceError::validation("No workflow is currently active"))?;
+        workflow.metadata.insert(key, value.into());
         Ok(())
     }
 
     /// Set multiple attributes on the current workflow
-    pub fn set_attributes(&self, attributes: HashMap<String, String>) -> UntraceResult<()> {
-        for (key, value) in attributes {
-            self.set_attribute(key, value)?;
+    ///
+    /// Every entry is attempted. Valid attributes are applied even if others
+    /// fail, and the returned error lists the keys that could not be set.
+    pub fn set_attributes<V: Into<AttributeValue>>(
+        &self,
+        attributes: HashMap<String, V>,
+    ) -> UntraceResult<()> {
+        if self.workflows.read().unwrap().is_empty() {
+            return Err(UntraceError::validation("No workflow is currently active"));
         }
-        Ok(())
+
+        let mut failed: Vec<String> = attributes
+            .into_iter()
+            .filter_map(|(key, value)| self.set_attribute(key.clone(), value).err().map(|_| key))
+            .collect();
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        failed.sort();
+        let keys: Vec<String> = failed.iter().map(|key| format!("{:?}", key)).collect();
+        Err(UntraceError::validation(format!(
+            "Failed to set attributes: {}",
+            keys.join(", ")
+        )))
     }
 
-    /// Generate a new run ID
+    /// Generate a new run ID in the configured format
     pub fn generate_run_id(&self) -> String {
-        Uuid::new_v4().to_string()
+        self.run_id_format.generate()
+    }
+
+    /// Generate a new run ID as a ULID after `prefix` and `_`
+    ///
+    /// For example `run_01ARZ3NDEKTSV4RRFFQ69G5FAV` for the prefix `run`.
+    pub fn generate_run_id_with_prefix(&self, prefix: &str) -> String {
+        RunIdFormat::Prefixed(prefix.to_string()).generate()
+    }
+}
+
+/// Check that an attribute key can be recorded
+fn validate_attribute_key(key: &str) -> UntraceResult<()> {
+    if key.is_empty() || key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(UntraceError::validation(format!(
+            "Invalid attribute key {:?}",
+            key
+        )));
+    }
+    Ok(())
+}
+
+impl Default for UntraceContext {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Clone for UntraceContext {
     fn clone(&self) -> Self {
         Self {
-            current_workflow: Arc::clone(&self.current_workflow),
+            workflows: Arc::clone(&self.workflows),
+            run_id_format: self.run_id_format.clone(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+//! Model pricing and cost calculation for the Untrace SDK
+
+use crate::error::{UntraceError, UntraceResult};
+use crate::types::{Cost, Currency};
+use std::collections::HashMap;
+
+/// Price of a model in USD per 1,000 tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub prompt_per_1k: f64,
+    pub completion_per_1k: f64,
+}
+
+impl ModelPricing {
+    /// Create a new pricing entry
+    pub fn new(prompt_per_1k: f64, completion_per_1k: f64) -> Self {
+        Self {
+            prompt_per_1k,
+            completion_per_1k,
+        }
+    }
+}
+
+/// Lookup table of model prices used to compute span costs
+#[derive(Debug, Clone)]
+pub struct CostTable {
+    models: HashMap<String, ModelPricing>,
+}
+
+impl CostTable {
+    /// Create an empty cost table
+    pub fn empty() -> Self {
+        Self {
+            models: HashMap::new(),
+        }
+    }
+
+    /// Set the pricing for a model
+    pub fn insert(&mut self, model: impl Into<String>, pricing: ModelPricing) {
+        self.models.insert(model.into(), pricing);
+    }
+
+    /// Get the pricing for a model
+    ///
+    /// Falls back to the longest known model name that prefixes `model`, so
+    /// dated snapshots such as `gpt-4-0613` use the `gpt-4` price.
+    pub fn get(&self, model: &str) -> Option<ModelPricing> {
+        if let Some(pricing) = self.models.get(model) {
+            return Some(*pricing);
+        }
+
+        self.models
+            .iter()
+            .filter(|(name, _)| {
+                model
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, pricing)| *pricing)
+    }
+
+    /// Compute the cost of a call, or `None` if the model is unknown
+    pub fn calculate(
+        &self,
+        provider: &str,
+        model: &str,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) -> Option<Cost> {
+        let pricing = self.get(model)?;
+        let prompt = prompt_tokens as f64 / 1000.0 * pricing.prompt_per_1k;
+        let completion = completion_tokens as f64 / 1000.0 * pricing.completion_per_1k;
+
+        Some(Cost {
+            prompt,
+            completion,
+            total: prompt + completion,
+            currency: Currency::Usd,
+            model: model.to_string(),
+            provider: provider.to_string(),
+        })
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        let mut table = Self::empty();
+
+        // OpenAI
+        table.insert("gpt-4", ModelPricing::new(0.03, 0.06));
+        table.insert("gpt-4-32k", ModelPricing::new(0.06, 0.12));
+        table.insert("gpt-4-turbo", ModelPricing::new(0.01, 0.03));
+        table.insert("gpt-4o", ModelPricing::new(0.005, 0.015));
+        table.insert("gpt-4o-mini", ModelPricing::new(0.00015, 0.0006));
+        table.insert("gpt-3.5-turbo", ModelPricing::new(0.0005, 0.0015));
+        table.insert("text-embedding-3-small", ModelPricing::new(0.00002, 0.0));
+        table.insert("text-embedding-3-large", ModelPricing::new(0.00013, 0.0));
+
+        // Anthropic
+        table.insert("claude-3-opus", ModelPricing::new(0.015, 0.075));
+        table.insert("claude-3-sonnet", ModelPricing::new(0.003, 0.015));
+        table.insert("claude-3-haiku", ModelPricing::new(0.00025, 0.00125));
+
+        table
+    }
+}
+
+/// Exchange rates for converting costs out of USD
+///
+/// Rates are units of a currency per US dollar. None are built in, since
+/// they change daily; set the ones you report in with
+/// [`ExchangeRates::with_rate`].
+#[derive(Debug, Clone)]
+pub struct ExchangeRates {
+    per_usd: HashMap<Currency, f64>,
+}
+
+impl Default for ExchangeRates {
+    fn default() -> Self {
+        Self {
+            per_usd: HashMap::from([(Currency::Usd, 1.0)]),
+        }
+    }
+}
+
+impl ExchangeRates {
+    /// Create a converter that only knows USD
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many units of `currency` one US dollar buys
+    pub fn with_rate(mut self, currency: Currency, per_usd: f64) -> Self {
+        self.per_usd.insert(currency, per_usd);
+        self
+    }
+
+    /// Get the rate for a currency, if known
+    pub fn rate(&self, currency: &Currency) -> Option<f64> {
+        self.per_usd.get(currency).copied()
+    }
+
+    /// Convert a cost into another currency
+    ///
+    /// Fails if either the cost's currency or `to` has no rate.
+    pub fn convert(&self, cost: &Cost, to: &Currency) -> UntraceResult<Cost> {
+        let rate = |currency: &Currency| {
+            self.rate(currency).ok_or_else(|| {
+                UntraceError::validation(format!("No exchange rate for {}", currency))
+            })
+        };
+        let factor = rate(to)? / rate(&cost.currency)?;
+
+        Ok(Cost {
+            prompt: cost.prompt * factor,
+            completion: cost.completion * factor,
+            total: cost.total * factor,
+            currency: to.clone(),
+            model: cost.model.clone(),
+            provider: cost.provider.clone(),
+        })
+    }
+}
@@ -2,6 +2,9 @@
 
 use thiserror::Error;
 
+/// Maximum number of bytes of a response body kept in an API error
+const MAX_ERROR_BODY_LEN: usize = 1024;
+
 /// Result type alias for Untrace operations
 pub type UntraceResult<T> = Result<T, UntraceError>;
 
@@ -12,7 +15,10 @@ pub enum UntraceError {
     Config { message: String },
 
     #[error("API error: {message}")]
-    Api { message: String },
+    Api {
+        message: String,
+        status: Option<u16>,
+    },
 
     #[error("Validation error: {message}")]
     Validation { message: String },
@@ -43,6 +49,13 @@ pub enum UntraceError {
 
     #[error("Unknown error: {message}")]
     Unknown { message: String },
+
+    #[error("{source} (trace_id: {trace_id})")]
+    Traced {
+        #[source]
+        source: Box<UntraceError>,
+        trace_id: String,
+    },
 }
 
 impl UntraceError {
@@ -57,6 +70,77 @@ impl UntraceError {
     pub fn api<S: Into<String>>(message: S) -> Self {
         Self::Api {
             message: message.into(),
+            status: None,
+        }
+    }
+
+    /// Create an API error from a non-success HTTP response
+    ///
+    /// The response body is truncated so large error pages don't bloat logs.
+    pub fn from_response(status: reqwest::StatusCode, body: &str) -> Self {
+        let body = body.trim();
+        let body = if body.len() > MAX_ERROR_BODY_LEN {
+            let mut end = MAX_ERROR_BODY_LEN;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("{}...", &body[..end])
+        } else {
+            body.to_string()
+        };
+
+        let message = if body.is_empty() {
+            format!("HTTP {}", status)
+        } else {
+            format!("HTTP {}: {}", status, body)
+        };
+
+        Self::Api {
+            message,
+            status: Some(status.as_u16()),
+        }
+    }
+
+    /// Get the HTTP status code, if this error came from an HTTP response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Api { status, .. } => *status,
+            Self::Http(err) => err.status().map(|s| s.as_u16()),
+            Self::Traced { source, .. } => source.status(),
+            _ => None,
+        }
+    }
+
+    /// Attach the active span's trace ID so the error can be tied to its trace
+    ///
+    /// The error is returned unchanged if no span is active or it already
+    /// carries a trace ID.
+    ///
+    /// ```
+    /// use untrace::UntraceError;
+    ///
+    /// let err = UntraceError::with_current_trace(UntraceError::validation("bad input"));
+    /// assert_eq!(err.trace_id(), None);
+    /// ```
+    pub fn with_current_trace(self) -> Self {
+        if matches!(self, Self::Traced { .. }) {
+            return self;
+        }
+
+        match crate::context::current_trace_id() {
+            Some(trace_id) => Self::Traced {
+                source: Box::new(self),
+                trace_id,
+            },
+            None => self,
+        }
+    }
+
+    /// Get the trace ID attached by [`UntraceError::with_current_trace`]
+    pub fn trace_id(&self) -> Option<&str> {
+        match self {
+            Self::Traced { trace_id, .. } => Some(trace_id),
+            _ => None,
         }
     }
 
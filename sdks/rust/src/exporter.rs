@@ -0,0 +1,553 @@
+//! Span exporter for the Untrace SDK
+
+use crate::config::{loggable_headers, Config};
+use crate::error::{UntraceError, UntraceResult};
+use crate::otlp_json;
+use crate::processor::SpanCounts;
+use crate::redaction::REDACTED;
+use crate::retry::{is_retryable, RetryConfig};
+use crate::rng::{default_rng, SharedRng};
+use opentelemetry::trace::TraceError;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Timeout for a single request to the Untrace API
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wire format of exported spans
+///
+/// Names follow `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Protocol {
+    /// OTLP protobuf over HTTP, the most compact encoding
+    #[default]
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    /// OTLP/JSON over HTTP, readable but larger and slower
+    ///
+    /// Uses the protobuf JSON mapping from the OTLP specification: field
+    /// names are lowerCamelCase, IDs are hex and 64-bit integers are strings.
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+impl Protocol {
+    /// Get the `Content-Type` of request bodies in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Protocol::HttpProtobuf => "application/x-protobuf",
+            Protocol::HttpJson => "application/json",
+        }
+    }
+
+    /// Encode a batch of spans as an export request body
+    pub fn encode(&self, batch: Vec<SpanData>) -> UntraceResult<Vec<u8>> {
+        self.encode_request(&ExportTraceServiceRequest {
+            resource_spans: batch.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    fn encode_request<M: Message + Serialize>(&self, request: &M) -> UntraceResult<Vec<u8>> {
+        match self {
+            Protocol::HttpProtobuf => Ok(request.encode_to_vec()),
+            Protocol::HttpJson => otlp_json::encode(request),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::HttpProtobuf => write!(f, "http/protobuf"),
+            Protocol::HttpJson => write!(f, "http/json"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = UntraceError;
+
+    fn from_str(value: &str) -> UntraceResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "http/protobuf" => Ok(Protocol::HttpProtobuf),
+            "http/json" => Ok(Protocol::HttpJson),
+            _ => Err(UntraceError::validation(format!(
+                "Unknown export protocol {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Retry limit set with [`UntraceExporter::limit_retries`], lifted when dropped
+#[must_use = "the limit is lifted as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct RetryLimit {
+    deadline: Arc<Mutex<Option<Instant>>>,
+    previous: Option<Instant>,
+}
+
+impl Drop for RetryLimit {
+    fn drop(&mut self) {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner) = self.previous;
+    }
+}
+
+/// Exports spans to the Untrace API as OTLP over HTTP
+#[derive(Debug, Clone)]
+pub struct UntraceExporter {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    headers: HashMap<String, String>,
+    sensitive_headers: Vec<String>,
+    protocol: Protocol,
+    retry: RetryConfig,
+    retry_deadline: Arc<Mutex<Option<Instant>>>,
+    rng: SharedRng,
+    dry_run: bool,
+}
+
+impl UntraceExporter {
+    /// Create a new exporter from the SDK configuration
+    pub fn new(config: &Config) -> UntraceResult<Self> {
+        // Fail early on a malformed base URL rather than on every export
+        url::Url::parse(&config.base_url)?;
+
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(proxy) = &config.proxy {
+            let proxy = reqwest::Proxy::all(proxy)?.no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build()?;
+
+        Ok(Self {
+            client,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            api_key: config.api_key.clone(),
+            headers: config.headers.clone(),
+            sensitive_headers: config.sensitive_headers.clone(),
+            protocol: config.protocol,
+            retry: config.retry.clone(),
+            retry_deadline: Arc::new(Mutex::new(None)),
+            rng: default_rng(),
+            dry_run: config.dry_run,
+        })
+    }
+
+    /// Use the given RNG for retry jitter
+    #[cfg(feature = "testing")]
+    pub fn with_rng(mut self, rng: std::sync::Arc<dyn crate::rng::Rng>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Get the URL spans are exported to
+    pub fn traces_endpoint(&self) -> String {
+        format!("{}/v1/traces", self.base_url)
+    }
+
+    /// Get the URL metrics are exported to
+    pub fn metrics_endpoint(&self) -> String {
+        format!("{}/v1/metrics", self.base_url)
+    }
+
+    /// Get the URL log records are exported to
+    #[cfg(feature = "logs")]
+    pub fn logs_endpoint(&self) -> String {
+        format!("{}/v1/logs", self.base_url)
+    }
+
+    /// Get the URL used for health checks
+    pub fn health_endpoint(&self) -> String {
+        format!("{}/health", self.base_url)
+    }
+
+    /// Add the authorization and custom headers to a request
+    fn with_headers(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request = request.bearer_auth(&self.api_key);
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+        request
+    }
+
+    /// Turn a non-success response into an API error
+    async fn check_response(response: reqwest::Response) -> UntraceResult<()> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        Err(UntraceError::from_response(status, &body))
+    }
+
+    /// Send a batch of spans to the Untrace API
+    ///
+    /// Retryable failures are retried with jittered exponential backoff as
+    /// configured by [`RetryConfig`]. In dry-run mode the batch is only
+    /// logged.
+    pub async fn send(&self, batch: Vec<SpanData>) -> UntraceResult<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let span_count = batch.len();
+        let request = ExportTraceServiceRequest {
+            resource_spans: batch.into_iter().map(Into::into).collect(),
+        };
+        let body = self.protocol.encode_request(&request)?;
+
+        if self.dry_run {
+            tracing::debug!(
+                "Dry run: would export {} span(s) in {} resource(s), {} bytes of {}, to {}",
+                span_count,
+                request.resource_spans.len(),
+                body.len(),
+                self.protocol,
+                self.traces_endpoint()
+            );
+            return Ok(());
+        }
+
+        self.send_with_retries(&self.traces_endpoint(), body).await
+    }
+
+    /// Send collected metrics to the Untrace API
+    ///
+    /// Nothing is sent if no instrument has recorded anything. Retried and
+    /// logged in dry-run mode like [`UntraceExporter::send`].
+    pub async fn send_metrics(
+        &self,
+        metrics: &opentelemetry_sdk::metrics::data::ResourceMetrics,
+    ) -> UntraceResult<()> {
+        use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+
+        let metric_count: usize = metrics
+            .scope_metrics
+            .iter()
+            .map(|scope| scope.metrics.len())
+            .sum();
+        if metric_count == 0 {
+            return Ok(());
+        }
+
+        let request = ExportMetricsServiceRequest::from(metrics);
+        let body = self.protocol.encode_request(&request)?;
+
+        if self.dry_run {
+            tracing::debug!(
+                "Dry run: would export {} metric(s), {} bytes of {}, to {}",
+                metric_count,
+                body.len(),
+                self.protocol,
+                self.metrics_endpoint()
+            );
+            return Ok(());
+        }
+
+        self.send_with_retries(&self.metrics_endpoint(), body).await
+    }
+
+    /// Send a batch of log records to the Untrace API
+    ///
+    /// Retried and logged in dry-run mode like [`UntraceExporter::send`].
+    #[cfg(feature = "logs")]
+    pub async fn send_logs(
+        &self,
+        batch: Vec<opentelemetry_sdk::export::logs::LogData>,
+    ) -> UntraceResult<()> {
+        use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let record_count = batch.len();
+        let request = ExportLogsServiceRequest {
+            resource_logs: batch.into_iter().map(Into::into).collect(),
+        };
+        let body = self.protocol.encode_request(&request)?;
+
+        if self.dry_run {
+            tracing::debug!(
+                "Dry run: would export {} log record(s), {} bytes of {}, to {}",
+                record_count,
+                body.len(),
+                self.protocol,
+                self.logs_endpoint()
+            );
+            return Ok(());
+        }
+
+        self.send_with_retries(&self.logs_endpoint(), body).await
+    }
+
+    /// Stop exports, including ones in progress, from running past `budget` from now
+    ///
+    /// Applies to this exporter and all its clones until the returned guard
+    /// is dropped. Used for the final flush on shutdown so that a failing
+    /// endpoint can't hold up process exit.
+    pub fn limit_retries(&self, budget: Duration) -> RetryLimit {
+        let mut deadline = self.retry_deadline.lock().unwrap_or_else(PoisonError::into_inner);
+        let previous = *deadline;
+        let limit = Instant::now() + budget;
+        *deadline = Some(previous.map_or(limit, |current| current.min(limit)));
+        RetryLimit {
+            deadline: Arc::clone(&self.retry_deadline),
+            previous,
+        }
+    }
+
+    /// Send a request body, retrying retryable failures
+    async fn send_with_retries(&self, endpoint: &str, body: Vec<u8>) -> UntraceResult<()> {
+        let mut backoff = self.retry.backoff();
+        loop {
+            let deadline = *self.retry_deadline.lock().unwrap();
+            let attempt = self.send_body(endpoint, body.clone());
+            let result = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), attempt)
+                    .await
+                    .unwrap_or_else(|_| Err(UntraceError::export("Export retry budget exhausted"))),
+                None => attempt.await,
+            };
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if !is_retryable(&err) {
+                return Err(err);
+            }
+
+            let Some(delay) = backoff.next_delay(&self.rng) else {
+                return Err(err);
+            };
+            if deadline.is_some_and(|deadline| Instant::now() + delay > deadline) {
+                return Err(err);
+            }
+            tracing::debug!("Export failed ({}), retrying in {:?}", err, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Describe the request headers for logging, with sensitive values redacted
+    fn log_headers(&self) -> String {
+        let mut headers = vec![("authorization".to_string(), REDACTED.to_string())];
+        headers.extend(loggable_headers(&self.headers, &self.sensitive_headers));
+        let headers: Vec<String> = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        headers.join(", ")
+    }
+
+    /// Make a single export request
+    async fn send_body(&self, endpoint: &str, body: Vec<u8>) -> UntraceResult<()> {
+        tracing::debug!(
+            "Exporting {} bytes of {} to {} with headers [{}]",
+            body.len(),
+            self.protocol,
+            endpoint,
+            self.log_headers()
+        );
+        let response = self
+            .with_headers(self.client.post(endpoint))
+            .header(reqwest::header::CONTENT_TYPE, self.protocol.content_type())
+            .body(body)
+            .send()
+            .await?;
+
+        Self::check_response(response).await
+    }
+
+    /// Check that the Untrace API is reachable and accepts the API key
+    pub async fn health_check(&self) -> UntraceResult<()> {
+        let response = self
+            .with_headers(self.client.get(self.health_endpoint()))
+            .send()
+            .await?;
+
+        Self::check_response(response).await
+    }
+}
+
+impl SpanExporter for UntraceExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let exporter = self.clone();
+        Box::pin(async move {
+            exporter
+                .send(batch)
+                .await
+                .map_err(|err| TraceError::Other(Box::new(err)))
+        })
+    }
+}
+
+/// Number of rotated files kept next to the active export file
+pub const FILE_EXPORT_ROTATED_FILES: usize = 3;
+
+/// Exports spans to a local file as newline-delimited OTLP JSON
+///
+/// Each exported batch is written as one line holding an OTLP
+/// `ExportTraceServiceRequest` in JSON, the same format as the
+/// `http/json` protocol. When a write would grow the file past `max_bytes`,
+/// the file is renamed to `<path>.1` first, shifting older files up to
+/// `<path>.3` and deleting the oldest.
+#[derive(Debug)]
+pub struct FileExporter {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Option<File>,
+    size: u64,
+}
+
+impl FileExporter {
+    /// Create an exporter appending to the file at `path`
+    ///
+    /// The file and its parent directory are created if needed.
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> UntraceResult<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut exporter = Self {
+            path,
+            max_bytes,
+            file: None,
+            size: 0,
+        };
+        exporter.open()?;
+        Ok(exporter)
+    }
+
+    /// Get the path of the active export file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the path of the `index`th rotated file, 1 being the most recent
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn open(&mut self) -> UntraceResult<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> UntraceResult<()> {
+        self.file = None;
+
+        let oldest = self.rotated_path(FILE_EXPORT_ROTATED_FILES);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for index in (1..FILE_EXPORT_ROTATED_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.open()
+    }
+
+    fn write(&mut self, batch: Vec<SpanData>) -> UntraceResult<()> {
+        let mut line = Protocol::HttpJson.encode(batch)?;
+        line.push(b'\n');
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            self.open()?;
+        }
+
+        let file = self.file.as_mut().expect("export file is open");
+        file.write_all(&line)?;
+        file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let result = if batch.is_empty() {
+            Ok(())
+        } else {
+            self.write(batch)
+                .map_err(|err| TraceError::Other(Box::new(err)))
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn shutdown(&mut self) {
+        self.file = None;
+    }
+}
+
+/// Span exporter that counts successfully exported spans
+#[derive(Debug)]
+pub struct CountingExporter<E> {
+    inner: E,
+    counts: SpanCounts,
+}
+
+impl<E: SpanExporter> CountingExporter<E> {
+    /// Wrap a span exporter, recording into the given counts
+    pub fn new(inner: E, counts: SpanCounts) -> Self {
+        Self { inner, counts }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for CountingExporter<E> {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let count = batch.len() as u64;
+        let counts = self.counts.clone();
+        let export = self.inner.export(batch);
+        Box::pin(async move {
+            let result = export.await;
+            if result.is_ok() {
+                counts.record_exported(count);
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.inner.force_flush()
+    }
+}
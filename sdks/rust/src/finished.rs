@@ -0,0 +1,178 @@
+//! Finished spans in a plain, serializable form
+//!
+//! [`FinishedSpan`] carries what a span recorded without OpenTelemetry's
+//! export types, for assertions, logging or forwarding spans elsewhere.
+//! [`CallbackExporter`] hands each exported batch to a callback in this form.
+
+use crate::types::{AttributeValue, SpanKindExt};
+use chrono::{DateTime, Utc};
+use opentelemetry::trace::{SpanId, SpanKind, Status};
+use opentelemetry::{Array, KeyValue, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Outcome of a finished span
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "lowercase")]
+pub enum SpanStatus {
+    #[default]
+    Unset,
+    Ok,
+    Error { message: String },
+}
+
+impl From<&Status> for SpanStatus {
+    fn from(status: &Status) -> Self {
+        match status {
+            Status::Unset => SpanStatus::Unset,
+            Status::Ok => SpanStatus::Ok,
+            Status::Error { description } => SpanStatus::Error {
+                message: description.to_string(),
+            },
+        }
+    }
+}
+
+/// Event recorded on a finished span
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpanEvent {
+    pub name: String,
+    pub time: DateTime<Utc>,
+    pub attributes: BTreeMap<String, AttributeValue>,
+}
+
+/// Span after it ended, with IDs as hex strings and typed attribute values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FinishedSpan {
+    pub name: String,
+    #[serde(serialize_with = "serialize_kind", deserialize_with = "deserialize_kind")]
+    pub kind: SpanKind,
+    pub trace_id: String,
+    pub span_id: String,
+    /// Parent span ID, `None` for a root span
+    pub parent_span_id: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub attributes: BTreeMap<String, AttributeValue>,
+    pub events: Vec<SpanEvent>,
+    pub status: SpanStatus,
+}
+
+impl FinishedSpan {
+    /// Get an attribute's value
+    pub fn attribute(&self, key: &str) -> Option<&AttributeValue> {
+        self.attributes.get(key)
+    }
+}
+
+impl From<&SpanData> for FinishedSpan {
+    fn from(span: &SpanData) -> Self {
+        Self {
+            name: span.name.to_string(),
+            kind: span.span_kind.clone(),
+            trace_id: span.span_context.trace_id().to_string(),
+            span_id: span.span_context.span_id().to_string(),
+            parent_span_id: Some(span.parent_span_id)
+                .filter(|id| *id != SpanId::INVALID)
+                .map(|id| id.to_string()),
+            start_time: span.start_time.into(),
+            end_time: span.end_time.into(),
+            attributes: attribute_map(&span.attributes),
+            events: span
+                .events
+                .iter()
+                .map(|event| SpanEvent {
+                    name: event.name.to_string(),
+                    time: event.timestamp.into(),
+                    attributes: attribute_map(&event.attributes),
+                })
+                .collect(),
+            status: SpanStatus::from(&span.status),
+        }
+    }
+}
+
+impl From<SpanData> for FinishedSpan {
+    fn from(span: SpanData) -> Self {
+        Self::from(&span)
+    }
+}
+
+fn attribute_map(attributes: &[KeyValue]) -> BTreeMap<String, AttributeValue> {
+    attributes
+        .iter()
+        .map(|kv| {
+            let value = match &kv.value {
+                Value::Bool(value) => AttributeValue::Bool(*value),
+                Value::I64(value) => AttributeValue::Int(*value),
+                Value::F64(value) => AttributeValue::Float(*value),
+                Value::String(value) => AttributeValue::String(value.to_string()),
+                Value::Array(Array::Bool(values)) => AttributeValue::BoolArray(values.clone()),
+                Value::Array(Array::I64(values)) => AttributeValue::IntArray(values.clone()),
+                Value::Array(Array::F64(values)) => AttributeValue::FloatArray(values.clone()),
+                Value::Array(Array::String(values)) => AttributeValue::StringArray(
+                    values.iter().map(ToString::to_string).collect(),
+                ),
+            };
+            (kv.key.to_string(), value)
+        })
+        .collect()
+}
+
+fn serialize_kind<S: Serializer>(kind: &SpanKind, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(kind.as_str())
+}
+
+fn deserialize_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SpanKind, D::Error> {
+    let kind = String::deserialize(deserializer)?;
+    <SpanKind as SpanKindExt>::from_str(&kind).map_err(serde::de::Error::custom)
+}
+
+/// Span exporter that passes each batch to a callback as [`FinishedSpan`]s
+///
+/// ```
+/// use untrace::CallbackExporter;
+///
+/// let exporter = CallbackExporter::new(|spans| {
+///     for span in spans {
+///         println!("{} took {}", span.name, span.end_time - span.start_time);
+///     }
+/// });
+/// # drop(exporter);
+/// ```
+pub struct CallbackExporter<F> {
+    callback: F,
+}
+
+impl<F> CallbackExporter<F>
+where
+    F: Fn(Vec<FinishedSpan>) + Send + Sync + 'static,
+{
+    /// Call `callback` with every exported batch
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> fmt::Debug for CallbackExporter<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackExporter").finish_non_exhaustive()
+    }
+}
+
+impl<F> SpanExporter for CallbackExporter<F>
+where
+    F: Fn(Vec<FinishedSpan>) + Send + Sync + 'static,
+{
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        (self.callback)(batch.iter().map(FinishedSpan::from).collect());
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
@@ -0,0 +1,246 @@
+//! LLM spans that record their own metrics
+
+use crate::attributes::{helpers, llm};
+use crate::clock::{Clock, SharedClock};
+use crate::metrics::UntraceMetrics;
+use crate::tracer::UntraceTracer;
+use crate::types::{LLMSpanOptions, TokenUsage};
+use opentelemetry::global::BoxedSpan;
+use opentelemetry::trace::Span;
+use opentelemetry::{Key, KeyValue, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Attribute holding the duration of a timed sub-operation event
+pub const SUBSPAN_DURATION_MS: &str = "duration_ms";
+
+/// LLM span that records token, cost and latency metrics from its options
+///
+/// Metrics are recorded at most once: either by an explicit call to
+/// [`SpanGuard::record_llm_metrics`] or, if the guard holds a metrics handle,
+/// when it ends. Latency is `duration_ms` if set, otherwise the time since
+/// the guard was created.
+///
+/// The span ends when [`SpanGuard::end`] is called or the guard is dropped.
+#[derive(Debug)]
+pub struct SpanGuard {
+    span: BoxedSpan,
+    options: LLMSpanOptions,
+    tracer: UntraceTracer,
+    metrics: Option<Arc<UntraceMetrics>>,
+    clock: SharedClock,
+    started_at: Instant,
+    metrics_recorded: bool,
+    ended: bool,
+}
+
+impl SpanGuard {
+    pub(crate) fn new(
+        span: BoxedSpan,
+        options: LLMSpanOptions,
+        tracer: UntraceTracer,
+        metrics: Option<Arc<UntraceMetrics>>,
+        clock: SharedClock,
+    ) -> Self {
+        let started_at = clock.now();
+        Self {
+            span,
+            options,
+            tracer,
+            metrics,
+            clock,
+            started_at,
+            metrics_recorded: false,
+            ended: false,
+        }
+    }
+
+    /// Record metrics to the given handle when the guard ends
+    pub fn with_metrics(mut self, metrics: Arc<UntraceMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Replace the span's LLM options, e.g. with the token counts from the response
+    ///
+    /// Attributes are only built if the span is recording.
+    pub fn complete(&mut self, options: LLMSpanOptions) {
+        if self.is_recording() {
+            let attributes = self.tracer.llm_attributes_without_metrics(options.clone());
+            self.tracer.set_span_attributes(&mut self.span, attributes);
+        }
+        self.options = options;
+    }
+
+    /// Check whether the span is recorded
+    ///
+    /// Spans dropped by the sampler aren't, and anything set on them is
+    /// discarded, so callers can skip building expensive attributes.
+    pub fn is_recording(&self) -> bool {
+        self.span.is_recording()
+    }
+
+    /// Set an attribute whose value is built by `value`, only if the span is recording
+    ///
+    /// The attribute gets the tracer's prefix and length limit like the
+    /// span's other attributes.
+    pub fn set_attribute_lazy<K, V, F>(&mut self, key: K, value: F)
+    where
+        K: Into<Key>,
+        V: Into<Value>,
+        F: FnOnce() -> V,
+    {
+        if self.is_recording() {
+            let attributes = vec![KeyValue::new(key, value())];
+            self.tracer.set_span_attributes(&mut self.span, attributes);
+        }
+    }
+
+    /// Record the prompt and completion returned by `build`
+    ///
+    /// `build` is only called if the span is recording and selected for body
+    /// capture, so large bodies are never serialized for spans that won't
+    /// carry them.
+    pub fn capture_body<F>(&mut self, build: F)
+    where
+        F: FnOnce() -> (Option<String>, Option<String>),
+    {
+        if !self.is_recording() || !self.tracer.should_capture_body() {
+            return;
+        }
+
+        let (prompt, completion) = build();
+        let mut attributes = Vec::new();
+        if let Some(prompt) = &prompt {
+            attributes.push(helpers::string(llm::PROMPT, prompt));
+        }
+        if let Some(completion) = &completion {
+            attributes.push(helpers::string(llm::COMPLETION, completion));
+        }
+        self.tracer.set_span_attributes(&mut self.span, attributes);
+
+        self.options.prompt = prompt;
+        self.options.completion = completion;
+    }
+
+    /// Get the span's current LLM options
+    pub fn options(&self) -> &LLMSpanOptions {
+        &self.options
+    }
+
+    /// Get the underlying span, e.g. to set extra attributes
+    pub fn span_mut(&mut self) -> &mut BoxedSpan {
+        &mut self.span
+    }
+
+    /// Time a sub-operation of the call, such as tokenization, and return its result
+    ///
+    /// The sub-operation is recorded as an event named `name` on the span,
+    /// with its duration in milliseconds as `duration_ms`. Unlike a child
+    /// span this costs nothing beyond the event itself.
+    pub fn time_subspan<T>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let started_at = self.clock.now();
+        let result = f();
+        self.record_subspan(name.into(), started_at);
+        result
+    }
+
+    /// Time an async sub-operation of the call, such as the network request
+    ///
+    /// Like [`SpanGuard::time_subspan`], timing from when the future is
+    /// created until it completes.
+    pub async fn time_subspan_async<T, F>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        f: impl FnOnce() -> F,
+    ) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let started_at = self.clock.now();
+        let result = f().await;
+        self.record_subspan(name.into(), started_at);
+        result
+    }
+
+    fn record_subspan(&mut self, name: Cow<'static, str>, started_at: Instant) {
+        let elapsed = self.clock.now().saturating_duration_since(started_at);
+        self.span.add_event(
+            name,
+            vec![helpers::float(
+                SUBSPAN_DURATION_MS,
+                elapsed.as_secs_f64() * 1000.0,
+            )],
+        );
+    }
+
+    /// Record token usage, cost and latency for the span
+    ///
+    /// Does nothing if metrics were already recorded.
+    pub fn record_llm_metrics(&mut self, metrics: &UntraceMetrics) {
+        if self.metrics_recorded {
+            return;
+        }
+        self.metrics_recorded = true;
+
+        let options = &self.options;
+        if options.prompt_tokens.is_some() || options.completion_tokens.is_some() {
+            let prompt_tokens = options.prompt_tokens.unwrap_or(0);
+            let completion_tokens = options.completion_tokens.unwrap_or(0);
+            let _ = metrics.record_token_usage(TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: options
+                    .total_tokens
+                    .unwrap_or(prompt_tokens.saturating_add(completion_tokens)),
+                model: options.model.clone(),
+                provider: options.provider.clone(),
+            });
+        }
+
+        if let Some(cost) = self.tracer.llm_cost(options) {
+            let _ = metrics.record_cost(cost);
+        }
+
+        let latency = options
+            .duration_ms
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| self.clock.now().saturating_duration_since(self.started_at));
+        let attributes = HashMap::from([
+            ("provider".to_string(), options.provider.clone()),
+            ("model".to_string(), options.model.clone()),
+            ("operation".to_string(), options.operation.to_string()),
+        ]);
+        let _ = metrics.record_latency(latency, attributes);
+    }
+
+    /// Record metrics if a handle is held and end the span
+    pub fn end(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+
+        if let Some(metrics) = self.metrics.take() {
+            self.record_llm_metrics(&metrics);
+        }
+        self.span.end();
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,88 @@
+//! Shared span handles for synchronous callbacks
+
+use opentelemetry::global::BoxedSpan;
+use opentelemetry::trace::{Span, SpanContext, Status};
+use opentelemetry::KeyValue;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Span that callbacks can record to without owning it
+///
+/// Provider SDKs often report progress through synchronous callbacks, such
+/// as one invoked for each streamed token, which can't hold a `&mut` span.
+/// Clones refer to the same span and each call locks it briefly, so handles
+/// can be used from several threads at once.
+///
+/// ```
+/// use untrace::{KeyValue, SpanHandle};
+/// # use opentelemetry::trace::Tracer;
+///
+/// # let span = opentelemetry::global::tracer("example").start("llm.stream");
+/// let handle = SpanHandle::new(span);
+/// let on_token = {
+///     let handle = handle.clone();
+///     move |token: &str| handle.add_event("token", vec![KeyValue::new("text", token.to_string())])
+/// };
+/// on_token("Hello");
+/// handle.end();
+/// ```
+///
+/// The span ends when [`SpanHandle::end`] is called or the last handle is
+/// dropped.
+#[derive(Debug, Clone)]
+pub struct SpanHandle {
+    span: Arc<Mutex<BoxedSpan>>,
+}
+
+impl SpanHandle {
+    /// Share a span between callbacks
+    pub fn new(span: BoxedSpan) -> Self {
+        Self {
+            span: Arc::new(Mutex::new(span)),
+        }
+    }
+
+    /// Record an event on the span
+    pub fn add_event<T>(&self, name: T, attributes: Vec<KeyValue>)
+    where
+        T: Into<Cow<'static, str>>,
+    {
+        self.lock().add_event(name, attributes);
+    }
+
+    /// Set an attribute on the span
+    pub fn set_attribute(&self, attribute: KeyValue) {
+        self.lock().set_attribute(attribute);
+    }
+
+    /// Set the span's status
+    pub fn set_status(&self, status: Status) {
+        self.lock().set_status(status);
+    }
+
+    /// Check whether the span is recording
+    pub fn is_recording(&self) -> bool {
+        self.lock().is_recording()
+    }
+
+    /// Get the span's context, e.g. to start child spans under it
+    pub fn span_context(&self) -> SpanContext {
+        self.lock().span_context().clone()
+    }
+
+    /// End the span for every handle; later calls are ignored
+    pub fn end(&self) {
+        self.lock().end();
+    }
+
+    /// Lock the span, recovering it if a callback panicked while holding it
+    pub(crate) fn lock(&self) -> MutexGuard<'_, BoxedSpan> {
+        self.span.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<BoxedSpan> for SpanHandle {
+    fn from(span: BoxedSpan) -> Self {
+        Self::new(span)
+    }
+}
@@ -1,5 +1,9 @@
 //! Instrumentation for the Untrace SDK
 
+#[cfg(feature = "async-openai")]
+pub mod async_openai;
+pub mod generic;
+
 use crate::error::UntraceResult;
 use crate::types::InstrumentationConfig;
 
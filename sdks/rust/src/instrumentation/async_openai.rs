@@ -0,0 +1,99 @@
+//! Instrumentation for the `async-openai` client
+//!
+//! Turns a chat completion request and its response into an LLM span. The
+//! client itself is not wrapped, so calls are made as usual and recorded
+//! afterwards:
+//!
+//! ```no_run
+//! # async fn run(
+//! #     client: async_openai::Client<async_openai::config::OpenAIConfig>,
+//! #     request: async_openai::types::CreateChatCompletionRequest,
+//! # ) -> Result<(), Box<dyn std::error::Error>> {
+//! use std::time::Instant;
+//! use untrace::instrumentation::async_openai::record_chat_completion;
+//!
+//! let untrace = untrace::Untrace::get_instance().expect("Untrace is initialized");
+//! let started = Instant::now();
+//! let response = client.chat().create(request.clone()).await?;
+//! record_chat_completion(untrace.tracer(), &request, &response, started.elapsed());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tracer::UntraceTracer;
+use crate::types::{LLMOperationType, LLMSpanOptions};
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+use std::time::Duration;
+
+/// Provider name recorded on spans from this module
+pub const PROVIDER: &str = "openai";
+
+/// Name of the spans recorded by [`record_chat_completion`]
+pub const CHAT_SPAN_NAME: &str = "openai.chat";
+
+/// Build LLM span options from a chat completion request and response
+///
+/// Sampling parameters and tools come from the request. Token usage, the
+/// serving model, the response ID, the first choice's finish reason and any
+/// tool calls come from the response. The prompt and completion are included
+/// as JSON and text respectively; whether they are recorded is up to the
+/// tracer's body capture settings. Cost is left unset so the tracer can fill
+/// it in from its cost table.
+pub fn chat_completion_options(
+    request: &CreateChatCompletionRequest,
+    response: &CreateChatCompletionResponse,
+) -> LLMSpanOptions {
+    let choice = response.choices.first();
+    let usage = response.usage.as_ref();
+
+    #[allow(deprecated)]
+    let max_tokens = request.max_completion_tokens.or(request.max_tokens);
+
+    LLMSpanOptions {
+        provider: PROVIDER.to_string(),
+        model: if response.model.is_empty() {
+            request.model.clone()
+        } else {
+            response.model.clone()
+        },
+        operation: LLMOperationType::Chat,
+        prompt_tokens: usage.map(|usage| usage.prompt_tokens),
+        completion_tokens: usage.map(|usage| usage.completion_tokens),
+        total_tokens: usage.map(|usage| usage.total_tokens),
+        temperature: request.temperature.map(f64::from),
+        top_p: request.top_p.map(f64::from),
+        max_tokens,
+        stream: request.stream,
+        tools: request
+            .tools
+            .as_ref()
+            .and_then(|tools| serde_json::to_string(tools).ok()),
+        tool_calls: choice
+            .and_then(|choice| choice.message.tool_calls.as_ref())
+            .and_then(|calls| serde_json::to_string(calls).ok()),
+        request_id: Some(response.id.clone()),
+        usage_reason: choice
+            .and_then(|choice| choice.finish_reason)
+            .and_then(|reason| serde_json::to_value(reason).ok())
+            .and_then(|reason| reason.as_str().map(str::to_string)),
+        prompt: serde_json::to_string(&request.messages).ok(),
+        completion: choice.and_then(|choice| choice.message.content.clone()),
+        ..Default::default()
+    }
+}
+
+/// Record a completed chat completion call as an LLM span
+///
+/// The span ends now and starts `duration` earlier, which should be how
+/// long the call took.
+pub fn record_chat_completion(
+    tracer: &UntraceTracer,
+    request: &CreateChatCompletionRequest,
+    response: &CreateChatCompletionResponse,
+    duration: Duration,
+) {
+    let mut options = chat_completion_options(request, response);
+    options.duration_ms = Some(duration.as_millis().min(u64::MAX as u128) as u64);
+
+    tracer.record_llm_spans(vec![(CHAT_SPAN_NAME.to_string(), options)]);
+}
@@ -0,0 +1,90 @@
+//! Instrumentation for any LLM client
+//!
+//! Wraps a call made through a provider-agnostic crate, or any other client,
+//! in an LLM span. The span starts before the call and ends once it
+//! completes, with token, cost and latency metrics recorded by the tracer:
+//!
+//! ```no_run
+//! # struct Reply { text: String, input_tokens: u32, output_tokens: u32 }
+//! # async fn chat(prompt: &str) -> Result<Reply, std::io::Error> { unimplemented!() }
+//! # async fn run() -> Result<(), std::io::Error> {
+//! use untrace::instrumentation::generic::trace_llm_call;
+//! use untrace::LLMSpanOptions;
+//!
+//! let untrace = untrace::Untrace::get_instance().expect("Untrace is initialized");
+//! let options = LLMSpanOptions {
+//!     provider: "anthropic".to_string(),
+//!     model: "claude-3-haiku".to_string(),
+//!     ..Default::default()
+//! };
+//! let call = chat("Hello");
+//! let reply = trace_llm_call(untrace.tracer(), "llm-chat", options, call, |reply, options| {
+//!     options.prompt_tokens = Some(reply.input_tokens);
+//!     options.completion_tokens = Some(reply.output_tokens);
+//!     options.completion = Some(reply.text.clone());
+//! })
+//! .await?;
+//! println!("{}", reply.text);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::provider::error_type;
+use crate::tracer::UntraceTracer;
+use crate::types::LLMSpanOptions;
+use opentelemetry::trace::{Span, Status};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+
+/// Trace an LLM call as a span named `name`
+///
+/// `record` is called with a successful result to fill in what it reports,
+/// usually token counts, the serving model and the completion. A failed call
+/// is recorded with its error message, an `unknown` error type unless
+/// `options` already sets one, and an error status, and is counted in the
+/// error metric. The call's result is returned unchanged.
+pub async fn trace_llm_call<T, E, F, R>(
+    tracer: &UntraceTracer,
+    name: &str,
+    options: LLMSpanOptions,
+    call: F,
+    record: R,
+) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+    R: FnOnce(&T, &mut LLMSpanOptions),
+{
+    let mut guard = tracer.start_llm_span_guard(name, options);
+    let result = call.await;
+
+    let mut options = guard.options().clone();
+    match &result {
+        Ok(value) => {
+            record(value, &mut options);
+            guard.complete(options);
+        }
+        Err(err) => {
+            let message = err.to_string();
+            let kind = options
+                .error_type
+                .get_or_insert_with(|| error_type::UNKNOWN.to_string())
+                .clone();
+            options.error = Some(message.clone());
+
+            if let Some(metrics) = tracer.metrics() {
+                let attributes = HashMap::from([
+                    ("provider".to_string(), options.provider.clone()),
+                    ("model".to_string(), options.model.clone()),
+                ]);
+                let _ = metrics.record_error(&kind, attributes);
+            }
+            guard.complete(options);
+            guard.span_mut().set_status(Status::error(message));
+        }
+    }
+
+    guard.end();
+    result
+}
@@ -34,12 +34,33 @@
 
 pub mod attributes;
 pub mod client;
+pub mod clock;
 pub mod config;
 pub mod context;
+pub mod cost;
 pub mod error;
+pub mod exporter;
+pub mod finished;
+pub mod guard;
+pub mod handle;
 pub mod instrumentation;
+#[cfg(feature = "logs")]
+pub mod logs;
 pub mod metrics;
+mod otlp_json;
+pub mod prelude;
+pub mod processor;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
 pub mod provider;
+pub mod redaction;
+pub mod response;
+pub mod retry;
+pub mod rng;
+pub mod sampling;
+pub mod streaming;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod tracer;
 pub mod types;
 pub mod untrace;
@@ -48,13 +69,28 @@ pub mod untrace;
 pub use crate::{
     attributes::*,
     client::UntraceClient,
-    config::Config,
-    context::UntraceContext,
+    clock::{Clock, SystemClock},
+    config::{Config, ConfigSource, Environment, Region, ValidationPolicy},
+    context::{spawn_traced, RunIdFormat, SerializableSpanContext, UntraceContext},
+    cost::{CostTable, ExchangeRates, ModelPricing},
     error::{UntraceError, UntraceResult},
-    metrics::UntraceMetrics,
-    tracer::UntraceTracer,
+    exporter::{CountingExporter, FileExporter, Protocol, RetryLimit, UntraceExporter},
+    finished::{CallbackExporter, FinishedSpan, SpanEvent, SpanStatus},
+    guard::SpanGuard,
+    handle::SpanHandle,
+    metrics::{LatencyTimer, UntraceMetrics},
+    processor::{
+        CountFlushProcessor, DroppedEventsProcessor, FlushPolicy, MinDurationProcessor,
+        SlowSpanProcessor, SpanCountProcessor, SpanCounts, WorkflowSummaryProcessor,
+    },
+    redaction::{RedactingExporter, Redactor},
+    response::ToLlmSpanOptions,
+    retry::{JitterKind, RetryConfig},
+    sampling::{SamplingOverrides, SamplingStrategy, UntraceSampler},
+    streaming::StreamingSpan,
+    tracer::{status_from_http, UntraceTracer},
     types::*,
-    untrace::Untrace,
+    untrace::{Untrace, UntraceBuilder},
 };
 
 // Re-export OpenTelemetry types for convenience
@@ -90,6 +126,105 @@ pub async fn shutdown() -> UntraceResult<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use opentelemetry_sdk::trace::{self as sdktrace, ShouldSample, TracerProvider};
+
+    /// Build a tracer that exports to an in-memory exporter
+    fn test_tracer<S: ShouldSample + 'static>(
+        sampler: S,
+    ) -> (UntraceTracer, InMemorySpanExporter, TracerProvider) {
+        test_tracer_with_config(sampler, Config::new("test-api-key".to_string()))
+    }
+
+    /// Build a tracer with the given configuration that exports to an in-memory exporter
+    fn test_tracer_with_config<S: ShouldSample + 'static>(
+        sampler: S,
+        config: Config,
+    ) -> (UntraceTracer, InMemorySpanExporter, TracerProvider) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_sampler(sampler))
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test")));
+        (
+            UntraceTracer::with_config(tracer, std::sync::Arc::new(config)),
+            exporter,
+            provider,
+        )
+    }
+
+    /// Wait up to five seconds for a condition met on another thread
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !condition() {
+            if std::time::Instant::now() > deadline {
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        true
+    }
+
+    /// Wait up to five seconds for a condition, letting other tasks on the runtime run
+    async fn wait_until_async(condition: impl Fn() -> bool) -> bool {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !condition() {
+            if std::time::Instant::now() > deadline {
+                return false;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        true
+    }
+
+    /// Serializes tests that modify `UNTRACE_*` environment variables
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Look up an attribute on an exported span
+    fn span_attribute(
+        span: &opentelemetry_sdk::export::trace::SpanData,
+        key: &str,
+    ) -> Option<opentelemetry::Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    /// Log output captured by [`capture_logs`]
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Capture the SDK's debug logs on the current thread until the guard drops
+    fn capture_logs() -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter("untrace=debug")
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
 
     #[test]
     fn test_config_creation() {
@@ -108,6 +243,41 @@ mod tests {
         assert!(config.validate().is_ok());
     }
 
+    #[test]
+    fn test_config_effective_summary_sources() {
+        let path =
+            std::env::temp_dir().join(format!("untrace-config-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"api_key": "file-secret", "service_name": "from-file", "environment": "staging"}"#,
+        )
+        .unwrap();
+
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_ENVIRONMENT", "production-eu");
+        let config = Config::from_file_with_env_overrides(&path)
+            .unwrap()
+            .with_debug(true);
+        std::env::remove_var("UNTRACE_ENVIRONMENT");
+        std::fs::remove_file(&path).unwrap();
+
+        let summary = config.effective_summary();
+        let entry = |field: &str| {
+            summary
+                .iter()
+                .find(|(name, _, _)| name == field)
+                .map(|(_, value, source)| (value.as_str(), *source))
+                .unwrap()
+        };
+
+        assert_eq!(entry("api_key"), ("[REDACTED]", ConfigSource::File));
+        assert_eq!(entry("service_name"), ("from-file", ConfigSource::File));
+        assert_eq!(entry("environment"), ("production-eu", ConfigSource::Env));
+        assert_eq!(entry("debug"), ("true", ConfigSource::Builder));
+        assert_eq!(entry("base_url"), ("https://untrace.dev", ConfigSource::Default));
+        assert!(summary.iter().all(|(_, value, _)| !value.contains("file-secret")));
+    }
+
     #[test]
     fn test_llm_operation_type_display() {
         assert_eq!(LLMOperationType::Chat.to_string(), "chat");
@@ -115,6 +285,15 @@ mod tests {
         assert_eq!(LLMOperationType::Embedding.to_string(), "embedding");
     }
 
+    #[test]
+    fn test_vector_db_operation_display() {
+        assert_eq!(VectorDbOperation::Insert.to_string(), "insert");
+        assert_eq!(VectorDbOperation::Upsert.to_string(), "upsert");
+        assert_eq!(VectorDbOperation::Query.to_string(), "query");
+        assert_eq!(VectorDbOperation::Delete.to_string(), "delete");
+        assert_eq!(VectorDbOperation::Fetch.to_string(), "fetch");
+    }
+
     #[test]
     fn test_workflow_creation() {
         let options = WorkflowOptions::default();
@@ -125,6 +304,27 @@ mod tests {
         assert!(!workflow.id.is_empty());
     }
 
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_workflow_duration_with_backward_clock() {
+        use crate::clock::MockClock;
+        use std::sync::Arc;
+
+        let clock = MockClock::new();
+        let workflow = Workflow::with_clock(
+            "test-workflow".to_string(),
+            "run-123".to_string(),
+            WorkflowOptions::default(),
+            Arc::new(clock.clone()),
+        );
+
+        // Move the wall clock backward after the workflow started
+        clock.set_utc(workflow.start_time - chrono::Duration::hours(1));
+
+        assert_eq!(workflow.duration(), chrono::Duration::zero());
+        assert_eq!(workflow.duration_ms(), 0);
+    }
+
     #[test]
     fn test_provider_registry() {
         use crate::provider::ProviderRegistry;
@@ -133,6 +333,7 @@ mod tests {
             name: "test-provider".to_string(),
             version: "1.0.0".to_string(),
             enabled: true,
+            ..Default::default()
         };
 
         registry.register(provider);
@@ -140,6 +341,35 @@ mod tests {
         assert!(registry.is_enabled("test-provider"));
     }
 
+    #[test]
+    fn test_provider_registry_with_capability() {
+        use crate::provider::{register_default_providers, ProviderRegistry};
+        let mut registry = ProviderRegistry::new();
+        register_default_providers(&mut registry);
+        registry.register(Provider {
+            name: "legacy".to_string(),
+            version: "1.0.0".to_string(),
+            enabled: true,
+            ..Default::default()
+        });
+
+        let mut embedding_providers: Vec<&str> = registry
+            .with_capability("embeddings")
+            .map(|p| p.name.as_str())
+            .collect();
+        embedding_providers.sort();
+        assert_eq!(
+            embedding_providers,
+            vec!["aws", "azure-openai", "cohere", "google", "microsoft", "openai"]
+        );
+        assert_eq!(registry.with_capability("rerank").count(), 1);
+        assert_eq!(registry.iter().count(), 8);
+
+        let legacy: Provider =
+            serde_json::from_str(r#"{"name":"legacy","version":"1.0.0","enabled":true}"#).unwrap();
+        assert!(legacy.capabilities.is_empty());
+    }
+
     #[test]
     fn test_attributes_helpers() {
         use attributes::helpers;
@@ -154,6 +384,25 @@ mod tests {
         assert_eq!(bool_attr.key.as_str(), "test_bool");
     }
 
+    #[test]
+    fn test_unit_suffixed_attribute_helpers() {
+        use attributes::helpers;
+        use opentelemetry::Value;
+        use std::time::Duration;
+
+        let duration = helpers::duration_ms("llm.latency", Duration::from_millis(250));
+        assert_eq!(duration.key.as_str(), "llm.latency_ms");
+        assert_eq!(duration.value, Value::I64(250));
+
+        let duration = helpers::duration_ms("llm.duration_ms", Duration::from_secs(2));
+        assert_eq!(duration.key.as_str(), "llm.duration_ms");
+        assert_eq!(duration.value, Value::I64(2000));
+
+        let size = helpers::bytes("http.response", 4096);
+        assert_eq!(size.key.as_str(), "http.response_bytes");
+        assert_eq!(size.value, Value::I64(4096));
+    }
+
     #[test]
     fn test_error_types() {
         let config_error = UntraceError::config("test error");
@@ -165,4 +414,4537 @@ mod tests {
         let api_error = UntraceError::api("test error");
         assert!(matches!(api_error, UntraceError::Api { .. }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_error_from_response() {
+        let body = r#"{"error":{"message":"invalid api key","type":"unauthorized"}}"#;
+        let error = UntraceError::from_response(reqwest::StatusCode::UNAUTHORIZED, body);
+
+        assert!(matches!(
+            error,
+            UntraceError::Api {
+                status: Some(401),
+                ..
+            }
+        ));
+        assert_eq!(error.status(), Some(401));
+        let message = error.to_string();
+        assert!(message.contains("401"));
+        assert!(message.contains("invalid api key"));
+
+        let long_body = "x".repeat(5000);
+        let error = UntraceError::from_response(reqwest::StatusCode::BAD_GATEWAY, &long_body);
+        assert!(error.to_string().len() < 1200);
+    }
+
+    #[test]
+    fn test_forced_user_bypasses_sampling_rate() {
+        let overrides = SamplingOverrides::new();
+        let (tracer, exporter, provider) = test_tracer(UntraceSampler::new(0.0, overrides.clone()));
+        overrides.force_user("user-123".to_string());
+
+        let forced = Workflow::new(
+            "forced".to_string(),
+            "run-1".to_string(),
+            WorkflowOptions {
+                user_id: Some("user-123".to_string()),
+                ..Default::default()
+            },
+        );
+        let other = Workflow::new(
+            "other".to_string(),
+            "run-2".to_string(),
+            WorkflowOptions {
+                user_id: Some("user-456".to_string()),
+                ..Default::default()
+            },
+        );
+
+        tracer.start_workflow_span(&forced).end();
+        tracer.start_workflow_span(&other).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "forced");
+
+        overrides.clear();
+        exporter.reset();
+        tracer.start_workflow_span(&forced).end();
+        provider.force_flush();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_sampling_rate_at_runtime() {
+        let sampler = UntraceSampler::new(0.0, SamplingOverrides::new());
+        let (tracer, exporter, provider) = test_tracer(sampler.clone());
+
+        for _ in 0..10 {
+            tracer.start_span("before").end();
+        }
+        provider.force_flush();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        sampler.set_rate(1.0).unwrap();
+        for _ in 0..10 {
+            tracer.start_span("after").end();
+        }
+        provider.force_flush();
+        assert_eq!(exporter.get_finished_spans().unwrap().len(), 10);
+
+        assert!(sampler.set_rate(1.5).is_err());
+        assert!(sampler.set_rate(-0.1).is_err());
+        assert_eq!(sampler.rate(), 1.0);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_metrics_text() {
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let metrics = UntraceMetrics::new(provider.meter("test"));
+
+        metrics
+            .record_token_usage(TokenUsage {
+                prompt_tokens: 100,
+                completion_tokens: 50,
+                total_tokens: 150,
+                model: "gpt-4".to_string(),
+                provider: "openai".to_string(),
+            })
+            .unwrap();
+
+        let text = exporter.metrics_text().unwrap();
+        assert!(text.contains("# TYPE untrace_token_usage_total counter"));
+        assert!(text.contains(r#"untrace_token_usage_total{model="gpt-4",provider="openai"} 150"#));
+    }
+
+    #[test]
+    fn test_body_capture_rate() {
+        let config = Config::new("test-api-key".to_string()).with_body_capture_rate(0.2);
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+
+        for _ in 0..1000 {
+            let options = LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                prompt: Some("What is the capital of France?".to_string()),
+                completion: Some("Paris".to_string()),
+                ..Default::default()
+            };
+            tracer.start_llm_span("llm-chat", options).end();
+        }
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1000);
+        let with_bodies = spans
+            .iter()
+            .filter(|span| span.attributes.iter().any(|kv| kv.key.as_str() == llm::PROMPT))
+            .count();
+        assert!(
+            (120..=280).contains(&with_bodies),
+            "expected roughly 20% of spans to carry bodies, got {}",
+            with_bodies
+        );
+
+        let disabled = Config::new("test-api-key".to_string()).with_body_capture_rate(0.0);
+        assert!(disabled.validate().is_ok());
+        let invalid = Config::new("test-api-key".to_string()).with_body_capture_rate(1.5);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_mock_clock_durations() {
+        use crate::clock::MockClock;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let clock = MockClock::new();
+        let workflow = Workflow::with_clock(
+            "test-workflow".to_string(),
+            "run-123".to_string(),
+            WorkflowOptions::default(),
+            Arc::new(clock.clone()),
+        );
+        assert_eq!(workflow.duration_ms(), 0);
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(workflow.duration_ms(), 1500);
+
+        // Moving the wall clock backward doesn't affect the duration
+        clock.set_utc(workflow.start_time - chrono::Duration::hours(1));
+        assert_eq!(workflow.duration(), chrono::Duration::milliseconds(1500));
+
+        let metrics = UntraceMetrics::with_clock(
+            opentelemetry::global::meter("test"),
+            Arc::new(clock.clone()),
+        );
+        let timer = metrics.start_timer(std::collections::HashMap::new());
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(timer.elapsed(), Duration::from_millis(250));
+        assert_eq!(timer.stop().unwrap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_record_llm_spans_in_bulk() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let calls: Vec<(String, LLMSpanOptions)> = (0..10_000)
+            .map(|i| {
+                (
+                    format!("replay-{}", i),
+                    LLMSpanOptions {
+                        provider: "openai".to_string(),
+                        model: "gpt-4".to_string(),
+                        duration_ms: Some(250),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        tracer.record_llm_spans(calls);
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 10_000);
+        let duration = spans[0].end_time.duration_since(spans[0].start_time).unwrap();
+        assert_eq!(duration, std::time::Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_traced_propagates_context() {
+        use opentelemetry::trace::TraceContextExt;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let parent = tracer.start_span("parent");
+        let parent_span_id = parent.span_context().span_id();
+        let cx = opentelemetry::Context::current_with_span(parent);
+
+        let handle = {
+            let _guard = cx.clone().attach();
+            let tracer = tracer.clone();
+            spawn_traced(async move {
+                tracer.start_span("child").end();
+            })
+        };
+        handle.await.unwrap();
+        cx.span().end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let child = spans.iter().find(|s| s.name == "child").unwrap();
+        assert_eq!(child.parent_span_id, parent_span_id);
+        assert_eq!(
+            child.span_context.trace_id(),
+            cx.span().span_context().trace_id()
+        );
+    }
+
+    #[test]
+    fn test_resource_deployment_attributes() {
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("billing".to_string())
+            .with_service_namespace("acme".to_string())
+            .with_environment("staging".to_string())
+            .with_host_name("worker-1".to_string());
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_resource(untrace::build_resource(&config)))
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = UntraceTracer::new(opentelemetry::global::BoxedTracer::new(Box::new(
+            provider.tracer("test"),
+        )));
+        tracer.start_span("operation").end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let resource = &spans[0].resource;
+        let get = |key: &'static str| resource.get(Key::from_static_str(key)).map(|v| v.to_string());
+        assert_eq!(get("service.name").as_deref(), Some("billing"));
+        assert_eq!(get("service.namespace").as_deref(), Some("acme"));
+        assert_eq!(get("deployment.environment").as_deref(), Some("staging"));
+        assert_eq!(get("host.name").as_deref(), Some("worker-1"));
+    }
+
+    #[test]
+    fn test_image_generation_span_attributes() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "dall-e-3".to_string(),
+            operation: LLMOperationType::ImageGeneration,
+            image_count: Some(2),
+            image_size: Some("1024x1024".to_string()),
+            ..Default::default()
+        };
+        tracer.start_llm_span("image-generation", options).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], llm::IMAGE_COUNT),
+            Some(opentelemetry::Value::I64(2))
+        );
+        assert_eq!(
+            span_attribute(&spans[0], llm::IMAGE_SIZE).map(|v| v.to_string()),
+            Some("1024x1024".to_string())
+        );
+        assert!(span_attribute(&spans[0], llm::AUDIO_FORMAT).is_none());
+    }
+
+    #[test]
+    fn test_audio_transcription_span_attributes() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let mut options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "whisper-1".to_string(),
+            operation: LLMOperationType::AudioTranscription,
+            audio_format: Some("mp3".to_string()),
+            ..Default::default()
+        };
+        let mut span = tracer.start_llm_span("audio-transcription", options.clone());
+
+        // Duration is only known once the response arrives
+        options.audio_duration_seconds = Some(12.5);
+        tracer.set_multimodal_attributes(&mut span, &options);
+        span.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], llm::AUDIO_DURATION_SECONDS),
+            Some(opentelemetry::Value::F64(12.5))
+        );
+        assert_eq!(
+            span_attribute(&spans[0], llm::AUDIO_FORMAT).map(|v| v.to_string()),
+            Some("mp3".to_string())
+        );
+
+        // Non-multimodal operations never carry image or audio attributes
+        let chat = LLMSpanOptions {
+            image_count: Some(1),
+            ..Default::default()
+        };
+        tracer.start_llm_span("chat", chat).end();
+        provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        let chat_span = spans.iter().find(|s| s.name == "chat").unwrap();
+        assert!(span_attribute(chat_span, llm::IMAGE_COUNT).is_none());
+    }
+
+    #[test]
+    fn test_llm_span_cost_computed_from_tokens() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            ..Default::default()
+        };
+        let span = tracer.start_llm_span("llm-chat", LLMSpanOptions::default());
+        tracer.complete_llm_span(span, options.clone());
+
+        // Unknown models are skipped rather than priced at zero
+        let unknown = LLMSpanOptions {
+            model: "in-house-model".to_string(),
+            ..options
+        };
+        tracer.start_llm_span("unknown", unknown).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let cost = |name: &str, key: &str| {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            match span_attribute(span, key) {
+                Some(opentelemetry::Value::F64(value)) => Some(value),
+                _ => None,
+            }
+        };
+        assert!((cost("llm-chat", llm::COST_PROMPT).unwrap() - 0.03).abs() < 1e-9);
+        assert!((cost("llm-chat", llm::COST_COMPLETION).unwrap() - 0.03).abs() < 1e-9);
+        assert!((cost("llm-chat", llm::COST_TOTAL).unwrap() - 0.06).abs() < 1e-9);
+        assert!(cost("unknown", llm::COST_TOTAL).is_none());
+
+        let table = CostTable::default();
+        assert_eq!(table.get("gpt-4-0613"), table.get("gpt-4"));
+        assert_eq!(table.get("gpt-4o-mini-2024-07-18"), table.get("gpt-4o-mini"));
+    }
+
+    #[test]
+    fn test_azure_deployment_uses_model_pricing() {
+        use crate::provider::azure::AzureDeployment;
+
+        let deployment = AzureDeployment::from_url(
+            "https://acme.openai.azure.com/openai/deployments/prod-gpt4/chat/completions?api-version=2024-02-01",
+            "gpt-4",
+        )
+        .unwrap();
+        assert_eq!(deployment.deployment, "prod-gpt4");
+        assert_eq!(deployment.api_version, "2024-02-01");
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let options = LLMSpanOptions {
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            ..deployment.span_options()
+        };
+        tracer.start_llm_span("azure-chat", options).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |key: &str| span_attribute(&spans[0], key).map(|v| v.to_string());
+        assert_eq!(attr(llm::PROVIDER).as_deref(), Some("azure-openai"));
+        assert_eq!(attr(llm::MODEL).as_deref(), Some("gpt-4"));
+        assert_eq!(attr(llm::AZURE_DEPLOYMENT).as_deref(), Some("prod-gpt4"));
+        assert_eq!(attr(llm::AZURE_API_VERSION).as_deref(), Some("2024-02-01"));
+        assert_eq!(
+            span_attribute(&spans[0], llm::COST_TOTAL),
+            Some(opentelemetry::Value::F64(0.06))
+        );
+
+        assert!(
+            AzureDeployment::from_url("https://api.openai.com/v1/chat/completions", "gpt-4")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_set_attributes_applies_valid_entries() {
+        let context = UntraceContext::new();
+        let attributes: std::collections::HashMap<String, String> = [
+            ("team", "search"),
+            ("bad key", "ignored"),
+            ("tier", "gold"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        // Nothing to attach to without an active workflow
+        assert!(context.set_attributes(attributes.clone()).is_err());
+
+        context
+            .start_workflow(
+                "checkout".to_string(),
+                context.generate_run_id(),
+                WorkflowOptions::default(),
+            )
+            .unwrap();
+        let err = context.set_attributes(attributes).unwrap_err();
+        assert!(err.to_string().contains("\"bad key\""));
+
+        let workflow = context.get_current_workflow().unwrap();
+        assert_eq!(workflow.metadata.get("team"), Some(&AttributeValue::from("search")));
+        assert_eq!(workflow.metadata.get("tier"), Some(&AttributeValue::from("gold")));
+        assert!(!workflow.metadata.contains_key("bad key"));
+    }
+
+    #[test]
+    fn test_moderation_span_top_categories() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let categories = [
+            ("violence", 0.92),
+            ("harassment", 0.40),
+            ("hate", 0.35),
+            ("self-harm", 0.10),
+            ("sexual", 0.05),
+            ("illicit", 0.02),
+            ("spam", 0.01),
+        ]
+        .into_iter()
+        .map(|(name, score)| (name.to_string(), score))
+        .collect();
+        let options = ModerationSpanOptions {
+            model: "omni-moderation-latest".to_string(),
+            flagged: true,
+            categories,
+        };
+        tracer.start_moderation_span(" OpenAI", options).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], llm::PROVIDER),
+            Some(opentelemetry::Value::from("openai"))
+        );
+        assert_eq!(
+            span_attribute(&spans[0], llm::MODERATION_FLAGGED),
+            Some(opentelemetry::Value::Bool(true))
+        );
+        assert_eq!(
+            span_attribute(&spans[0], "llm.moderation.category.violence"),
+            Some(opentelemetry::Value::F64(0.92))
+        );
+        let category_count = spans[0]
+            .attributes
+            .iter()
+            .filter(|kv| kv.key.as_str().starts_with(llm::MODERATION_CATEGORY))
+            .count();
+        assert_eq!(category_count, 5);
+        assert!(span_attribute(&spans[0], "llm.moderation.category.illicit").is_none());
+        assert!(span_attribute(&spans[0], "llm.moderation.category.spam").is_none());
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_latency_histogram_exponential_buckets() {
+        use crate::metrics::{latency_view, DEFAULT_LATENCY_BUCKETS};
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+        use std::time::Duration;
+
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .with_view(latency_view(DEFAULT_LATENCY_BUCKETS.to_vec()).unwrap())
+            .build();
+        let metrics = UntraceMetrics::new(provider.meter("test"));
+
+        for ms in [5, 15, 900, 30_000, 60_000] {
+            metrics
+                .record_latency(Duration::from_millis(ms), std::collections::HashMap::new())
+                .unwrap();
+        }
+
+        let text = exporter.metrics_text().unwrap();
+        assert!(text.contains(r#"untrace_latency_bucket{le="10"} 1"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="20"} 2"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="640"} 2"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="1280"} 3"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="20480"} 3"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="40960"} 4"#));
+        assert!(text.contains(r#"untrace_latency_bucket{le="+Inf"} 5"#));
+
+        let invalid = Config::new("test-api-key".to_string())
+            .with_latency_buckets(vec![100.0, 10.0]);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_start_span_with_explicit_parent() {
+        use crate::processor::SlowSpanProcessor;
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug)]
+        struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+        impl sdktrace::SpanProcessor for Collector {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, span: SpanData) {
+                self.0.lock().unwrap().push(span);
+            }
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let parent = tracer.start_span("framework-request");
+        let parent_context = parent.span_context().clone();
+
+        // An unrelated span is current, but the explicit parent wins
+        let current = tracer.start_span("unrelated");
+        let active = opentelemetry::trace::mark_span_as_active(current);
+
+        tracer.start_span_with_parent("child", &parent_context).end();
+        tracer
+            .start_llm_span_with_parent("llm-child", LLMSpanOptions::default(), &parent_context)
+            .end();
+        drop(parent);
+        drop(active);
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        for name in ["child", "llm-child"] {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            assert_eq!(span.parent_span_id, parent_context.span_id());
+            assert_eq!(span.span_context.trace_id(), parent_context.trace_id());
+        }
+
+        // Children of a deferred keep-slow parent are deferred along with it
+        let strategy: SamplingStrategy = "keep_slow:1000:0.0".parse().unwrap();
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_sampler(UntraceSampler::with_strategy(
+                &strategy,
+                1.0,
+                SamplingOverrides::new(),
+            )))
+            .with_span_processor(SlowSpanProcessor::new(
+                Collector(collected.clone()),
+                strategy.slow_threshold(),
+                SpanCounts::new(),
+            ))
+            .build();
+        let tracer = UntraceTracer::with_config(
+            opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test"))),
+            Arc::new(Config::new("test-api-key".to_string())),
+        );
+
+        let parent = tracer.start_span("deferred-request");
+        let parent_context = parent.span_context().clone();
+        assert!(!parent_context.is_sampled());
+        tracer.start_span_with_parent("child", &parent_context).end();
+        let slow = LLMSpanOptions {
+            duration_ms: Some(5000),
+            ..Default::default()
+        };
+        tracer
+            .start_llm_span_with_parent("slow-llm-child", slow, &parent_context)
+            .end();
+        drop(parent);
+
+        let spans = collected.lock().unwrap();
+        let mut names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        names.sort();
+        assert_eq!(names, ["child", "deferred-request", "slow-llm-child"]);
+        assert!(spans.iter().all(|span| span.span_context.is_sampled()));
+    }
+
+    #[test]
+    fn test_config_from_env_maps_and_parse_errors() {
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_API_KEY", "env-key");
+        std::env::set_var("UNTRACE_HEADERS", "x-team=search, x-region = eu");
+        std::env::set_var("UNTRACE_RESOURCE_ATTRIBUTES", "pod.name=api-7f9c");
+        let config = Config::from_env();
+
+        std::env::set_var("UNTRACE_HEADERS", "x-team");
+        let bad_header = Config::from_env();
+        std::env::remove_var("UNTRACE_HEADERS");
+        std::env::remove_var("UNTRACE_RESOURCE_ATTRIBUTES");
+
+        std::env::set_var("UNTRACE_SAMPLING_RATE", "half");
+        let bad_rate = Config::from_env();
+        std::env::remove_var("UNTRACE_SAMPLING_RATE");
+        std::env::remove_var("UNTRACE_API_KEY");
+
+        let config = config.unwrap();
+        assert_eq!(config.headers.get("x-team").map(String::as_str), Some("search"));
+        assert_eq!(config.headers.get("x-region").map(String::as_str), Some("eu"));
+        assert_eq!(
+            config.resource_attributes.get("pod.name").map(String::as_str),
+            Some("api-7f9c")
+        );
+        assert_eq!(config.source("headers"), ConfigSource::Env);
+
+        let err = bad_header.unwrap_err().to_string();
+        assert!(err.contains("UNTRACE_HEADERS"), "{}", err);
+        let err = bad_rate.unwrap_err().to_string();
+        assert!(err.contains("UNTRACE_SAMPLING_RATE") && err.contains("half"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_exporter_routes_through_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+
+        let config = Config::new("test-api-key".to_string())
+            .with_base_url("http://untrace.invalid".to_string())
+            .with_proxy(format!("http://user:secret@{}", proxy_addr));
+        let exporter = UntraceExporter::new(&config).unwrap();
+        exporter.health_check().await.unwrap();
+
+        let request = received.await.unwrap();
+        assert!(request.starts_with("GET http://untrace.invalid/health HTTP/1.1"));
+        // "user:secret" in base64
+        assert!(request.contains("proxy-authorization: Basic dXNlcjpzZWNyZXQ="));
+
+        let summary = config.effective_summary();
+        let (_, proxy_value, _) = summary.iter().find(|(name, _, _)| name == "proxy").unwrap();
+        assert!(!proxy_value.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_no_proxy_bypasses_proxy() {
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let (addr, requests) = recording_server().await;
+
+        let config = Config::new("test-api-key".to_string())
+            .with_base_url(format!("http://{}", addr))
+            .with_proxy(format!("http://{}", proxy_addr));
+        let exporter = {
+            let _env = ENV_LOCK.lock().unwrap();
+            std::env::set_var("NO_PROXY", "127.0.0.1");
+            let exporter = UntraceExporter::new(&config);
+            std::env::remove_var("NO_PROXY");
+            exporter.unwrap()
+        };
+        exporter.health_check().await.unwrap();
+
+        let paths: Vec<String> =
+            requests.lock().unwrap().iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, ["/health"]);
+        let proxied =
+            tokio::time::timeout(std::time::Duration::from_millis(100), proxy.accept()).await;
+        assert!(proxied.is_err());
+    }
+
+    #[test]
+    fn test_global_span_attributes_on_every_span() {
+        let config = Config::new("test-api-key".to_string())
+            .with_global_span_attribute("git.commit".to_string(), "abc123")
+            .with_global_span_attribute("build.number".to_string(), 42);
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+
+        tracer.start_span("plain").end();
+        tracer
+            .start_llm_span("llm-chat", LLMSpanOptions::default())
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        for span in &spans {
+            assert_eq!(
+                span_attribute(span, "git.commit").map(|v| v.to_string()),
+                Some("abc123".to_string())
+            );
+            assert_eq!(
+                span_attribute(span, "build.number"),
+                Some(opentelemetry::Value::I64(42))
+            );
+            // Global attributes are per-span, not part of the resource
+            assert!(span.resource.get(Key::new("git.commit")).is_none());
+        }
+    }
+
+    #[test]
+    fn test_serializable_span_context_round_trip() {
+        let (tracer, _exporter, _provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let span = tracer.start_span("producer");
+        let context = SerializableSpanContext::from(span.span_context());
+
+        let traceparent = context.to_string();
+        assert_eq!(traceparent.len(), 55);
+        assert!(traceparent.starts_with("00-") && traceparent.ends_with("-01"));
+        assert_eq!(traceparent.parse::<SerializableSpanContext>().unwrap(), context);
+
+        let json = serde_json::to_string(&context).unwrap();
+        assert_eq!(json, format!("\"{}\"", traceparent));
+        let decoded: SerializableSpanContext = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, context);
+
+        let remote = decoded.to_span_context();
+        assert!(remote.is_remote());
+        assert_eq!(remote.span_id(), span.span_context().span_id());
+
+        for invalid in [
+            "",
+            "not-a-traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert!(invalid.parse::<SerializableSpanContext>().is_err(), "{}", invalid);
+        }
+        assert!(serde_json::from_str::<SerializableSpanContext>("\"garbage\"").is_err());
+    }
+
+    #[test]
+    fn test_disabled_providers_denylist() {
+        let config = Config::new("test-api-key".to_string())
+            .with_disabled_providers(vec!["aws".to_string(), "cohere".to_string()]);
+        let registry = untrace::build_provider_registry(&config);
+
+        let mut disabled: Vec<&str> = registry
+            .iter()
+            .filter(|p| !p.enabled)
+            .map(|p| p.name.as_str())
+            .collect();
+        disabled.sort();
+        assert_eq!(disabled, vec!["aws", "cohere"]);
+        assert!(registry.is_enabled("openai"));
+        assert!(registry.is_enabled("anthropic"));
+        assert_eq!(registry.iter().count(), 7);
+
+        // Unknown names are ignored rather than failing init
+        let config = Config::new("test-api-key".to_string())
+            .with_disabled_providers(vec!["not-a-provider".to_string()]);
+        let registry = untrace::build_provider_registry(&config);
+        assert_eq!(registry.get_enabled().len(), 7);
+    }
+
+    #[test]
+    fn test_workflow_metadata_propagation() {
+        let context = std::sync::Arc::new(UntraceContext::new());
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("tenant".to_string(), "acme".into());
+        context
+            .start_workflow(
+                "checkout".to_string(),
+                context.generate_run_id(),
+                WorkflowOptions {
+                    metadata,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        for propagate in [true, false] {
+            let config = Config::new("test-api-key".to_string())
+                .with_propagate_workflow_metadata(propagate);
+            let (tracer, exporter, provider) =
+                test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+            let tracer = tracer.with_untrace_context(context.clone());
+
+            tracer
+                .start_llm_span("llm-chat", LLMSpanOptions::default())
+                .end();
+            tracer
+                .start_vector_db_span("vector-query", VectorDbSpanOptions::default())
+                .end();
+            provider.force_flush();
+
+            let spans = exporter.get_finished_spans().unwrap();
+            assert_eq!(spans.len(), 2);
+            for span in &spans {
+                let tenant = span_attribute(span, "workflow.metadata.tenant");
+                if propagate {
+                    assert_eq!(tenant.map(|v| v.to_string()), Some("acme".to_string()));
+                } else {
+                    assert!(tenant.is_none());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_based_flush() {
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        /// Span processor that only counts flushes
+        #[derive(Debug, Default)]
+        struct FlushCounter(Arc<AtomicUsize>);
+
+        impl sdktrace::SpanProcessor for FlushCounter {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, _span: SpanData) {}
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let policy = FlushPolicy::new();
+        let provider = TracerProvider::builder()
+            .with_span_processor(CountFlushProcessor::new(
+                FlushCounter(flushes.clone()),
+                policy.clone(),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+
+        // Disabled by default
+        for _ in 0..10 {
+            tracer.start("span").end();
+        }
+        assert_eq!(flushes.load(Ordering::SeqCst), 0);
+
+        policy.set_every(5);
+        for _ in 0..6 {
+            tracer.start("span").end();
+        }
+        assert!(wait_until(|| flushes.load(Ordering::SeqCst) == 1));
+
+        for _ in 0..4 {
+            tracer.start("span").end();
+        }
+        assert!(wait_until(|| flushes.load(Ordering::SeqCst) == 2));
+    }
+
+    #[tokio::test]
+    async fn test_count_based_flush_on_current_thread_runtime() {
+        let exporter = InMemorySpanExporter::default();
+        let batch = sdktrace::BatchSpanProcessor::builder(
+            exporter.clone(),
+            opentelemetry_sdk::runtime::Tokio,
+        )
+        .with_batch_config(
+            sdktrace::BatchConfig::default()
+                .with_scheduled_delay(std::time::Duration::from_secs(3600)),
+        )
+        .build();
+        let policy = FlushPolicy::new();
+        policy.set_every(3);
+        let provider = TracerProvider::builder()
+            .with_span_processor(CountFlushProcessor::new(batch, policy))
+            .build();
+        // Let the batch worker take its interval's immediate first tick
+        tokio::task::yield_now().await;
+
+        // The batch worker runs on this thread, so flushing inside `end` would deadlock
+        let tracer = provider.tracer("test");
+        for _ in 0..3 {
+            tracer.start("span").end();
+        }
+        assert!(wait_until_async(|| exporter.get_finished_spans().unwrap().len() == 3).await);
+
+        // Shutting the batch processor down blocks until its worker stops
+        tokio::task::spawn_blocking(move || drop(provider)).await.unwrap();
+    }
+
+    #[test]
+    fn test_redaction_scrubs_bodies_and_events() {
+        let config = Config::new("test-api-key".to_string())
+            .with_redaction_pattern(r"sk-[A-Za-z0-9]{20,}".to_string());
+        let redactor = Redactor::new(&config.redaction_patterns).unwrap();
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(RedactingExporter::new(exporter.clone(), redactor))
+            .build();
+        let tracer = UntraceTracer::with_config(
+            opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test"))),
+            std::sync::Arc::new(config),
+        );
+
+        let secret = "sk-abcdefghijklmnopqrstuvwxyz123456";
+        let options = LLMSpanOptions {
+            prompt: Some(format!("Use the key {} to call the API", secret)),
+            completion: Some("Done".to_string()),
+            ..Default::default()
+        };
+        let mut span = tracer.start_llm_span("llm-chat", options);
+        span.add_event(
+            "tool_call",
+            vec![KeyValue::new("arguments", format!("{{\"api_key\":\"{}\"}}", secret))],
+        );
+        span.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let prompt = span_attribute(&spans[0], llm::PROMPT).unwrap().to_string();
+        assert_eq!(prompt, "Use the key [REDACTED] to call the API");
+        assert_eq!(
+            span_attribute(&spans[0], llm::COMPLETION).map(|v| v.to_string()),
+            Some("Done".to_string())
+        );
+        let event = spans[0].events.iter().next().unwrap();
+        assert_eq!(event.attributes[0].value.to_string(), r#"{"api_key":"[REDACTED]"}"#);
+
+        let invalid = Config::new("test-api-key".to_string()).with_redaction_pattern("(".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_named_instances_export_independently() {
+        let billing_exporter = InMemorySpanExporter::default();
+        let search_exporter = InMemorySpanExporter::default();
+        let billing = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("billing".to_string()),
+            billing_exporter.clone(),
+        )
+        .await
+        .unwrap();
+        let search = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("search".to_string()),
+            search_exporter.clone(),
+        )
+        .await
+        .unwrap();
+
+        billing.tracer().start_span("charge").end();
+        search.tracer().start_span("query").end();
+        billing.flush().await.unwrap();
+        search.flush().await.unwrap();
+
+        let billing_spans = billing_exporter.get_finished_spans().unwrap();
+        assert_eq!(billing_spans.len(), 1);
+        assert_eq!(billing_spans[0].name, "charge");
+        assert_eq!(billing_spans[0].instrumentation_lib.name, "billing");
+        assert_eq!(
+            billing_spans[0].resource.get(opentelemetry::Key::from_static_str("service.name")),
+            Some(opentelemetry::Value::from("billing"))
+        );
+
+        let search_spans = search_exporter.get_finished_spans().unwrap();
+        assert_eq!(search_spans.len(), 1);
+        assert_eq!(search_spans[0].name, "query");
+        assert_eq!(search_spans[0].instrumentation_lib.name, "search");
+    }
+
+    #[test]
+    fn test_retry_backoff_stays_within_jitter_range() {
+        use rand::{rngs::StdRng, SeedableRng};
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let retry = |jitter| RetryConfig {
+            max_retries: 6,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter,
+            total_budget: Duration::from_secs(60),
+        };
+        let delays = |config: &RetryConfig, seed| {
+            let rng = Mutex::new(StdRng::seed_from_u64(seed));
+            let mut backoff = config.backoff();
+            std::iter::from_fn(|| backoff.next_delay(&rng)).collect::<Vec<_>>()
+        };
+        let ms = Duration::from_millis;
+
+        assert_eq!(
+            delays(&retry(JitterKind::None), 1),
+            [ms(100), ms(200), ms(400), ms(800), ms(1600), ms(2000)]
+        );
+
+        let full = retry(JitterKind::Full);
+        let full_delays = delays(&full, 1);
+        assert_eq!(full_delays.len(), 6);
+        for (attempt, delay) in full_delays.iter().enumerate() {
+            let cap = ms(100 * 2u64.pow(attempt as u32)).min(ms(2000));
+            assert!(*delay <= cap, "attempt {attempt}: {delay:?} > {cap:?}");
+        }
+        assert_eq!(full_delays, delays(&full, 1));
+        assert_ne!(full_delays, delays(&full, 2));
+
+        let decorrelated = retry(JitterKind::Decorrelated);
+        let decorrelated_delays = delays(&decorrelated, 7);
+        let mut previous = ms(100);
+        for delay in &decorrelated_delays {
+            assert!(*delay >= ms(100) && *delay <= (previous * 3).min(ms(2000)));
+            previous = *delay;
+        }
+        assert_eq!(decorrelated_delays, delays(&decorrelated, 7));
+
+        let disabled = RetryConfig {
+            max_retries: 0,
+            ..RetryConfig::default()
+        };
+        assert_eq!(delays(&disabled, 1), []);
+
+        let budgeted = RetryConfig {
+            total_budget: ms(1000),
+            ..retry(JitterKind::None)
+        };
+        assert_eq!(delays(&budgeted, 1), [ms(100), ms(200), ms(400)]);
+    }
+
+    #[test]
+    fn test_root_sampling_follows_trace_id() {
+        use opentelemetry::trace::{SamplingDecision, SpanKind, TraceId};
+
+        let decisions = |sampler: &UntraceSampler| {
+            (1..=64)
+                .map(|id| {
+                    let trace_id = TraceId::from_u128(id * (u64::MAX as u128 / 64));
+                    sampler
+                        .should_sample(None, trace_id, "root", &SpanKind::Internal, &[], &[])
+                        .decision
+                        == SamplingDecision::RecordAndSample
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Every service sampling at the same rate makes the same decision
+        let first = decisions(&UntraceSampler::new(0.5, SamplingOverrides::new()));
+        assert_eq!(first, decisions(&UntraceSampler::new(0.5, SamplingOverrides::new())));
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_seeded_rng_makes_body_capture_deterministic() {
+        use crate::rng::SeededRng;
+        use std::sync::Arc;
+
+        let body_captures = |seed| {
+            let config = Config::new("test-api-key".to_string()).with_body_capture_rate(0.5);
+            let (tracer, exporter, provider) =
+                test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+            let tracer = tracer.with_rng(Arc::new(SeededRng::new(seed)));
+            for _ in 0..32 {
+                let options = LLMSpanOptions {
+                    provider: "openai".to_string(),
+                    model: "gpt-4".to_string(),
+                    prompt: Some("Hello".to_string()),
+                    ..Default::default()
+                };
+                tracer.start_llm_span("llm-chat", options).end();
+            }
+            provider.force_flush();
+            exporter
+                .get_finished_spans()
+                .unwrap()
+                .iter()
+                .map(|span| span_attribute(span, llm::PROMPT).is_some())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(body_captures(7), body_captures(7));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_skips_network() {
+        use opentelemetry_sdk::export::trace::SpanExporter as _;
+        use std::time::Duration;
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config::new("test-api-key".to_string())
+            .with_base_url(format!("http://{}", server.local_addr().unwrap()))
+            .with_dry_run(true);
+
+        let (tracer, in_memory, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_span("dry-run").end();
+        provider.force_flush();
+        let spans = in_memory.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+
+        let mut exporter = UntraceExporter::new(&config).unwrap();
+        exporter.export(spans).await.unwrap();
+
+        let connection = tokio::time::timeout(Duration::from_millis(200), server.accept()).await;
+        assert!(connection.is_err(), "dry run must not contact the API");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_report_counts_spans() {
+        use opentelemetry_sdk::export::trace::{ExportResult, SpanExporter};
+
+        #[derive(Debug)]
+        struct FailingExporter;
+
+        impl SpanExporter for FailingExporter {
+            fn export(
+                &mut self,
+                _batch: Vec<opentelemetry_sdk::export::trace::SpanData>,
+            ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ExportResult> + Send>> {
+                Box::pin(async { Err("collector unavailable".into()) })
+            }
+        }
+
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("reported".to_string()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        for i in 0..5 {
+            untrace.tracer().start_span(&format!("span-{}", i)).end();
+        }
+
+        // Shutting down the in-memory exporter clears it, so look before
+        untrace.flush().await.unwrap();
+        assert_eq!(exporter.get_finished_spans().unwrap().len(), 5);
+        let report = untrace.shutdown_with_report().await.unwrap();
+        assert_eq!(report.flushed_spans, 5);
+        assert_eq!(report.dropped_spans, 0);
+
+        let failing = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("failing".to_string()),
+            FailingExporter,
+        )
+        .await
+        .unwrap();
+        for i in 0..3 {
+            failing.tracer().start_span(&format!("span-{}", i)).end();
+        }
+
+        let report = failing.shutdown_with_report().await.unwrap();
+        assert_eq!(report.flushed_spans, 0);
+        assert_eq!(report.dropped_spans, 3);
+    }
+
+    #[test]
+    fn test_truncate_respects_char_boundaries() {
+        use crate::attributes::helpers::{truncate, TRUNCATION_MARKER};
+
+        // "日本" is 3 bytes per character and "🎉" is 4 bytes
+        let value = "ab日本🎉🎉";
+        assert_eq!(truncate(value, 100), value);
+        // A 7-byte cut would land inside "本"
+        assert_eq!(truncate(value, 7 + TRUNCATION_MARKER.len()), "ab日…");
+        // A 10-byte cut would land inside the first "🎉"
+        assert_eq!(truncate(value, 10 + TRUNCATION_MARKER.len()), "ab日本…");
+        assert_eq!(truncate(value, 2), "ab");
+        assert_eq!(truncate(value, 1), "a");
+
+        let config = Config::new("test-api-key".to_string()).with_max_attribute_length(16);
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+        let span = tracer.start_llm_span(
+            "llm-chat",
+            LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                prompt: Some("翻訳してください🙂🙂🙂".to_string()),
+                ..Default::default()
+            },
+        );
+        tracer.complete_llm_span(
+            span,
+            LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                completion: Some("🎉🎉🎉🎉🎉".to_string()),
+                ..Default::default()
+            },
+        );
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let prompt = span_attribute(&spans[0], llm::PROMPT).unwrap().as_str().into_owned();
+        assert_eq!(prompt, "翻訳して…");
+        assert!(prompt.len() <= 16);
+        let completion = span_attribute(&spans[0], llm::COMPLETION).unwrap().as_str().into_owned();
+        assert_eq!(completion, "🎉🎉🎉…");
+    }
+
+    #[test]
+    fn test_classify_llm_errors() {
+        use crate::provider::{classify_llm_error, error_type, record_llm_error};
+
+        let openai_quota = r#"{"error": {"message": "You exceeded your current quota.", "type": "insufficient_quota", "param": null, "code": "insufficient_quota"}}"#;
+        assert_eq!(
+            classify_llm_error("openai", 429, openai_quota),
+            (error_type::QUOTA_EXCEEDED, false)
+        );
+
+        let openai_rate_limit = r#"{"error": {"message": "Rate limit reached", "type": "requests", "code": "rate_limit_exceeded"}}"#;
+        assert_eq!(
+            classify_llm_error("openai", 429, openai_rate_limit),
+            (error_type::RATE_LIMIT, true)
+        );
+
+        let anthropic_overloaded =
+            r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#;
+        assert_eq!(
+            classify_llm_error("anthropic", 529, anthropic_overloaded),
+            (error_type::OVERLOADED, true)
+        );
+
+        assert_eq!(classify_llm_error("cohere", 429, ""), (error_type::RATE_LIMIT, true));
+        assert_eq!(classify_llm_error("openai", 401, "<html>"), (error_type::AUTHENTICATION, false));
+        assert_eq!(classify_llm_error("openai", 503, ""), (error_type::SERVER_ERROR, true));
+
+        let mut options = LLMSpanOptions {
+            provider: "anthropic".to_string(),
+            ..Default::default()
+        };
+        assert!(record_llm_error(&mut options, 529, anthropic_overloaded));
+        assert_eq!(options.error_type.as_deref(), Some("overloaded"));
+        assert_eq!(options.error.as_deref(), Some("Overloaded"));
+
+        let mut options = LLMSpanOptions::default();
+        assert!(!record_llm_error(&mut options, 400, "not json"));
+        assert_eq!(options.error_type.as_deref(), Some("invalid_request"));
+        assert_eq!(options.error.as_deref(), Some("HTTP 400"));
+    }
+
+    #[test]
+    fn test_streaming_span_totals() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let mut stream = tracer.start_streaming_llm_span(
+            "llm-stream",
+            LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                ..Default::default()
+            },
+        );
+        for chunk in ["Hel", "lo, ", "wor", "ld!"] {
+            stream.record_chunk(chunk);
+        }
+        stream.record_chunk("🎉".as_bytes());
+        stream.set_completion_tokens(4);
+        assert_eq!(stream.chunk_count(), 5);
+        assert_eq!(stream.bytes(), 17);
+        stream.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span_attribute(span, llm::STREAM), Some(opentelemetry::Value::Bool(true)));
+        assert_eq!(
+            span_attribute(span, llm::STREAM_CHUNK_COUNT),
+            Some(opentelemetry::Value::I64(5))
+        );
+        assert_eq!(span_attribute(span, llm::STREAM_BYTES), Some(opentelemetry::Value::I64(17)));
+        assert_eq!(
+            span_attribute(span, llm::COMPLETION_TOKENS),
+            Some(opentelemetry::Value::I64(4))
+        );
+    }
+
+    #[test]
+    fn test_to_llm_span_options() {
+        let usage = TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            total_tokens: 15,
+            model: "claude-3-haiku".to_string(),
+            provider: "anthropic".to_string(),
+        };
+        let options = usage.to_llm_span_options("", "").unwrap();
+        assert_eq!(options.provider, "anthropic");
+        assert_eq!(options.model, "claude-3-haiku");
+        assert_eq!(
+            (options.prompt_tokens, options.completion_tokens, options.total_tokens),
+            (Some(10), Some(5), Some(15))
+        );
+        let options = usage.to_llm_span_options("bedrock", "haiku").unwrap();
+        assert_eq!((options.provider.as_str(), options.model.as_str()), ("bedrock", "haiku"));
+
+        let anthropic = serde_json::json!({
+            "id": "msg_01",
+            "model": "claude-3-haiku-20240307",
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 25, "output_tokens": 7}
+        });
+        let options = anthropic
+            .to_llm_span_options("anthropic", "claude-3-haiku")
+            .unwrap();
+        assert_eq!(options.model, "claude-3-haiku-20240307");
+        assert_eq!(
+            (options.prompt_tokens, options.completion_tokens, options.total_tokens),
+            (Some(25), Some(7), Some(32))
+        );
+        assert_eq!(options.request_id.as_deref(), Some("msg_01"));
+        assert_eq!(options.usage_reason.as_deref(), Some("end_turn"));
+
+        let without_usage = serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{"index": 0, "finish_reason": "stop"}]
+        });
+        let options = without_usage.to_llm_span_options("openai", "gpt-4").unwrap();
+        assert_eq!(options.model, "gpt-4");
+        assert_eq!(options.usage_reason.as_deref(), Some("stop"));
+        assert_eq!(
+            (options.prompt_tokens, options.completion_tokens, options.total_tokens),
+            (None, None, None)
+        );
+
+        assert!(serde_json::json!([1, 2])
+            .to_llm_span_options("openai", "gpt-4")
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_panic_hook_records_error_span() {
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("panicky".to_string()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        untrace.install_panic_hook();
+        untrace.install_panic_hook();
+
+        let result = std::panic::catch_unwind(|| panic!("tool call exploded"));
+        assert!(result.is_err());
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let panics: Vec<_> = spans
+            .iter()
+            .filter(|span| {
+                span.name == "panic"
+                    && span_attribute(span, attributes::panic::MESSAGE)
+                        == Some(opentelemetry::Value::from("tool call exploded"))
+            })
+            .collect();
+        assert_eq!(panics.len(), 1, "the hook must only be installed once");
+        assert_eq!(
+            panics[0].status,
+            opentelemetry::trace::Status::error("tool call exploded")
+        );
+        let location = span_attribute(panics[0], attributes::panic::LOCATION).unwrap();
+        assert!(location.as_str().starts_with("src/lib.rs:"));
+    }
+
+    #[test]
+    fn test_attribute_prefix() {
+        let config = Config::new("test-api-key".to_string())
+            .with_attribute_prefix("acme".to_string())
+            .with_global_span_attribute("team".to_string(), "search")
+            .with_global_span_attribute("http.route".to_string(), "/chat")
+            .with_global_span_attribute("acme.region".to_string(), "eu");
+        assert!(config.validate().is_ok());
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+
+        tracer
+            .start_llm_span(
+                "llm-chat",
+                LLMSpanOptions {
+                    provider: "openai".to_string(),
+                    model: "gpt-4".to_string(),
+                    prompt: Some("Hello".to_string()),
+                    ..Default::default()
+                },
+            )
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = &spans[0];
+        assert!(span_attribute(span, "acme.llm.prompt").is_some());
+        assert!(span_attribute(span, "acme.llm.model").is_some());
+        assert!(span_attribute(span, "acme.team").is_some());
+        assert!(span_attribute(span, "acme.region").is_some());
+        assert!(span_attribute(span, "http.route").is_some());
+        assert!(span_attribute(span, llm::PROMPT).is_none());
+        assert!(span_attribute(span, "acme.http.route").is_none());
+        assert!(span_attribute(span, "acme.acme.region").is_none());
+
+        let overrides = SamplingOverrides::new();
+        overrides.force_workflow("run-1".to_string());
+        let sampler = UntraceSampler::new(0.0, overrides);
+        let result = sampler.should_sample(
+            None,
+            opentelemetry::trace::TraceId::from_u128(1),
+            "prefixed",
+            &opentelemetry::trace::SpanKind::Internal,
+            &[opentelemetry::KeyValue::new("acme.workflow.run_id", "run-1")],
+            &[],
+        );
+        assert_eq!(
+            result.decision,
+            opentelemetry::trace::SamplingDecision::RecordAndSample
+        );
+
+        let invalid = Config::new("test-api-key".to_string()).with_attribute_prefix(" ".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_current_trace_and_span_ids() {
+        use opentelemetry::trace::TraceContextExt as _;
+
+        assert_eq!(context::current_trace_id(), None);
+        assert_eq!(context::current_span_id(), None);
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let cx = opentelemetry::Context::current_with_span(tracer.start_span("request"));
+        let (trace_id, span_id) = {
+            let _guard = cx.clone().attach();
+            (
+                context::current_trace_id().unwrap(),
+                context::current_span_id().unwrap(),
+            )
+        };
+        cx.span().end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(trace_id, spans[0].span_context.trace_id().to_string());
+        assert_eq!(span_id, spans[0].span_context.span_id().to_string());
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(span_id.len(), 16);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_record_token_usage_batch() {
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let metrics = UntraceMetrics::new(provider.meter("test"));
+
+        let usage = |provider: &str, model: &str, total_tokens| TokenUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens,
+            model: model.to_string(),
+            provider: provider.to_string(),
+        };
+        metrics
+            .record_token_usage_batch(&[
+                usage("openai", "gpt-4", 100),
+                usage("anthropic", "claude-3-haiku", 40),
+                usage("openai", "gpt-4", 50),
+                usage("openai", "gpt-4o", 7),
+                usage("anthropic", "claude-3-haiku", 2),
+            ])
+            .unwrap();
+        metrics.record_token_usage_batch(&[]).unwrap();
+
+        let text = exporter.metrics_text().unwrap();
+        assert!(text.contains(r#"untrace_token_usage_total{model="gpt-4",provider="openai"} 150"#));
+        assert!(text.contains(r#"untrace_token_usage_total{model="gpt-4o",provider="openai"} 7"#));
+        assert!(text.contains(
+            r#"untrace_token_usage_total{model="claude-3-haiku",provider="anthropic"} 42"#
+        ));
+        assert_eq!(text.matches("untrace_token_usage_total{").count(), 3);
+    }
+
+    #[test]
+    fn test_span_kind_from_str() {
+        use opentelemetry::trace::SpanKind;
+
+        for (value, kind) in [
+            ("client", SpanKind::Client),
+            ("SERVER", SpanKind::Server),
+            ("Internal", SpanKind::Internal),
+            (" producer ", SpanKind::Producer),
+            ("consumer", SpanKind::Consumer),
+        ] {
+            let parsed = <SpanKind as SpanKindExt>::from_str(value).unwrap();
+            assert_eq!(parsed, kind);
+            assert_eq!(SpanKindExt::from_str(parsed.as_str()).ok(), Some(kind));
+        }
+
+        let err = <SpanKind as SpanKindExt>::from_str("server-ish").unwrap_err();
+        assert!(err.to_string().contains("server-ish"), "{}", err);
+        assert!(<SpanKind as SpanKindExt>::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_empty_span_names() {
+        use crate::tracer::UNNAMED_SPAN;
+        use opentelemetry::trace::Span as _;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_span("").end();
+        tracer.start_llm_span("  ", LLMSpanOptions::default()).end();
+        tracer.start_span("named").end();
+        provider.force_flush();
+
+        let names: Vec<_> = exporter
+            .get_finished_spans()
+            .unwrap()
+            .iter()
+            .map(|span| span.name.to_string())
+            .collect();
+        assert_eq!(names, [UNNAMED_SPAN, UNNAMED_SPAN, "named"]);
+
+        let config = Config::new("test-api-key".to_string()).with_reject_empty_span_names(true);
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+        let mut rejected = tracer.start_span("");
+        assert!(!rejected.is_recording());
+        rejected.end();
+        tracer.start_span("kept").end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "kept");
+    }
+
+    #[cfg(feature = "async-openai")]
+    #[test]
+    fn test_async_openai_chat_completion_span() {
+        use crate::instrumentation::async_openai::{record_chat_completion, CHAT_SPAN_NAME};
+        use async_openai::types::{
+            ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
+            CreateChatCompletionResponse,
+        };
+
+        let request = CreateChatCompletionRequestArgs::default()
+            .model("gpt-4")
+            .temperature(0.2)
+            .max_completion_tokens(256u32)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content("Say hello")
+                .build()
+                .unwrap()
+                .into()])
+            .build()
+            .unwrap();
+        let response: CreateChatCompletionResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-4-0613",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello!"},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 1000, "completion_tokens": 500, "total_tokens": 1500}
+        }))
+        .unwrap();
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        record_chat_completion(
+            &tracer,
+            &request,
+            &response,
+            std::time::Duration::from_millis(1200),
+        );
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans[0].name, CHAT_SPAN_NAME);
+        let attr = |key: &str| span_attribute(&spans[0], key).map(|v| v.to_string());
+        assert_eq!(attr(llm::PROVIDER).as_deref(), Some("openai"));
+        assert_eq!(attr(llm::MODEL).as_deref(), Some("gpt-4-0613"));
+        assert_eq!(attr(llm::TOTAL_TOKENS).as_deref(), Some("1500"));
+        assert_eq!(attr(llm::MAX_TOKENS).as_deref(), Some("256"));
+        assert_eq!(attr(llm::REQUEST_ID).as_deref(), Some("chatcmpl-123"));
+        assert_eq!(attr(llm::USAGE_REASON).as_deref(), Some("stop"));
+        assert!(
+            (span_attribute(&spans[0], llm::COST_TOTAL)
+                .map(|v| v.to_string().parse::<f64>().unwrap())
+                .unwrap()
+                - 0.06)
+                .abs()
+                < 1e-9
+        );
+        assert_eq!(
+            spans[0]
+                .end_time
+                .duration_since(spans[0].start_time)
+                .unwrap(),
+            std::time::Duration::from_millis(1200)
+        );
+    }
+
+    #[test]
+    fn test_complete_llm_span_sets_throughput_and_ratio() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let options = LLMSpanOptions {
+            model: "in-house-model".to_string(),
+            prompt_tokens: Some(400),
+            completion_tokens: Some(100),
+            duration_ms: Some(2000),
+            ..Default::default()
+        };
+        let span = tracer.start_llm_span("timed", LLMSpanOptions::default());
+        tracer.complete_llm_span(span, options.clone());
+
+        // Zero divisors leave the derived attributes unset
+        let instant = LLMSpanOptions {
+            prompt_tokens: Some(0),
+            duration_ms: Some(0),
+            ..options.clone()
+        };
+        let span = tracer.start_llm_span("instant", LLMSpanOptions::default());
+        tracer.complete_llm_span(span, instant);
+
+        let untimed = LLMSpanOptions {
+            duration_ms: None,
+            ..options
+        };
+        let span = tracer.start_llm_span("untimed", LLMSpanOptions::default());
+        tracer.complete_llm_span(span, untimed);
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |name: &str, key: &str| {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            span_attribute(span, key)
+        };
+        assert_eq!(
+            attr("timed", llm::TOKENS_PER_SECOND),
+            Some(opentelemetry::Value::F64(50.0))
+        );
+        assert_eq!(
+            attr("timed", llm::COMPLETION_RATIO),
+            Some(opentelemetry::Value::F64(0.25))
+        );
+        assert!(attr("instant", llm::TOKENS_PER_SECOND).is_none());
+        assert!(attr("instant", llm::COMPLETION_RATIO).is_none());
+        assert!(attr("untimed", llm::TOKENS_PER_SECOND).is_none());
+        assert!(attr("untimed", llm::COMPLETION_RATIO).is_none());
+    }
+
+    #[test]
+    fn test_error_with_current_trace() {
+        use opentelemetry::trace::TraceContextExt as _;
+
+        let (tracer, _exporter, _provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let cx = opentelemetry::Context::current_with_span(tracer.start_span("request"));
+        let err = {
+            let _guard = cx.clone().attach();
+            UntraceError::with_current_trace(UntraceError::api("upstream failed"))
+        };
+
+        let trace_id = cx.span().span_context().trace_id().to_string();
+        assert_eq!(err.trace_id(), Some(trace_id.as_str()));
+        assert_eq!(
+            err.to_string(),
+            format!("API error: upstream failed (trace_id: {})", trace_id)
+        );
+        assert_eq!(
+            std::error::Error::source(&err).map(|source| source.to_string()),
+            Some("API error: upstream failed".to_string())
+        );
+
+        // Without an active span the error is left as is
+        let err = UntraceError::with_current_trace(UntraceError::api("upstream failed"));
+        assert_eq!(err.trace_id(), None);
+        assert_eq!(err.to_string(), "API error: upstream failed");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_workflow_summary_mode_rolls_up_llm_spans() {
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string())
+                .with_service_name("summarized".to_string())
+                .with_workflow_summary_mode(true),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        let tracer = untrace.tracer();
+
+        let workflow = untrace
+            .context()
+            .start_workflow(
+                "answer-question".to_string(),
+                "run-1".to_string(),
+                WorkflowOptions::default(),
+            )
+            .unwrap();
+        let mut workflow_span = tracer.start_workflow_span(&workflow);
+        let chat = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            ..Default::default()
+        };
+        tracer.start_llm_span("chat-1", chat.clone()).end();
+        tracer
+            .start_llm_span(
+                "chat-2",
+                LLMSpanOptions {
+                    prompt_tokens: Some(100),
+                    completion_tokens: Some(50),
+                    ..chat.clone()
+                },
+            )
+            .end();
+        tracer
+            .start_llm_span(
+                "embed",
+                LLMSpanOptions {
+                    operation: LLMOperationType::Embedding,
+                    prompt_tokens: Some(10),
+                    completion_tokens: None,
+                    cost_total: Some(0.001),
+                    ..chat.clone()
+                },
+            )
+            .end();
+        tracer.start_span("retrieval").end();
+        workflow_span.end();
+        untrace.context().end_current_workflow().unwrap();
+
+        // Calls outside a workflow are exported as usual
+        tracer.start_llm_span("standalone", chat).end();
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let mut names: Vec<&str> = spans.iter().map(|s| s.name.as_ref()).collect();
+        names.sort();
+        assert_eq!(names, ["answer-question", "retrieval", "standalone"]);
+
+        let summary = spans.iter().find(|s| s.name == "answer-question").unwrap();
+        let int = |key: &str| match span_attribute(summary, key) {
+            Some(opentelemetry::Value::I64(value)) => value,
+            other => panic!("{} is {:?}", key, other),
+        };
+        assert_eq!(int(workflow::LLM_CALLS), 3);
+        assert_eq!(int("workflow.operations.chat"), 2);
+        assert_eq!(int("workflow.operations.embedding"), 1);
+        assert_eq!(int(workflow::PROMPT_TOKENS), 1110);
+        assert_eq!(int(workflow::COMPLETION_TOKENS), 550);
+        assert_eq!(int(workflow::TOTAL_TOKENS), 1660);
+        match span_attribute(summary, workflow::COST_TOTAL) {
+            Some(opentelemetry::Value::F64(cost)) => assert!((cost - 0.067).abs() < 1e-9),
+            other => panic!("cost is {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_workflow_metadata_keeps_types() {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("retry_count".to_string(), 3.into());
+        metadata.insert("cached".to_string(), true.into());
+        metadata.insert("score".to_string(), 0.75.into());
+        metadata.insert("tenant".to_string(), "acme".into());
+        let workflow = Workflow::new(
+            "checkout".to_string(),
+            "run-1".to_string(),
+            WorkflowOptions {
+                metadata,
+                ..Default::default()
+            },
+        );
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_workflow_span(&workflow).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |key: &str| span_attribute(&spans[0], key);
+        assert_eq!(
+            attr("workflow.metadata.retry_count"),
+            Some(opentelemetry::Value::I64(3))
+        );
+        assert_eq!(
+            attr("workflow.metadata.cached"),
+            Some(opentelemetry::Value::Bool(true))
+        );
+        assert_eq!(
+            attr("workflow.metadata.score"),
+            Some(opentelemetry::Value::F64(0.75))
+        );
+        assert_eq!(
+            attr("workflow.metadata.tenant"),
+            Some(opentelemetry::Value::from("acme"))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_attribute_schema_info_reports_config() {
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string())
+                .with_service_name("schema".to_string())
+                .with_attribute_prefix("acme".to_string())
+                .with_max_attribute_length(256)
+                .with_redaction_pattern(r"sk-[a-z0-9]+".to_string())
+                .with_global_span_attribute("team".to_string(), "search")
+                .with_global_span_attribute("region".to_string(), "eu"),
+            InMemorySpanExporter::default(),
+        )
+        .await
+        .unwrap();
+
+        let info = untrace.attribute_schema_info();
+        assert_eq!(info.attribute_prefix.as_deref(), Some("acme"));
+        assert_eq!(info.max_attribute_length, 256);
+        assert_eq!(info.redaction_patterns, [r"sk-[a-z0-9]+"]);
+        assert_eq!(info.global_span_attribute_keys, ["region", "team"]);
+        assert!(!info.reject_empty_span_names);
+        assert!(!info.propagate_workflow_metadata);
+        assert!(info.namespaces.iter().any(|namespace| namespace == "llm"));
+        assert!(info.semconv_schema_url.starts_with("https://opentelemetry.io/schemas/"));
+    }
+
+    #[test]
+    fn test_record_completed_span() {
+        use opentelemetry::trace::Status;
+        use std::time::{Duration, SystemTime};
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let end = start + Duration::from_millis(250);
+        tracer.record_completed_span(
+            "batch-job",
+            SpanKind::Consumer,
+            start,
+            end,
+            vec![KeyValue::new("job.items", 42)],
+            Status::error("3 items failed"),
+        );
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "batch-job");
+        assert_eq!(spans[0].span_kind, SpanKind::Consumer);
+        assert_eq!(spans[0].start_time, start);
+        assert_eq!(spans[0].end_time, end);
+        assert_eq!(spans[0].status, Status::error("3 items failed"));
+        assert_eq!(
+            span_attribute(&spans[0], "job.items"),
+            Some(opentelemetry::Value::I64(42))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_init_keeps_existing_global_tracer_provider() {
+        use opentelemetry::trace::Tracer as _;
+
+        let host_exporter = InMemorySpanExporter::default();
+        let host_provider = TracerProvider::builder()
+            .with_simple_exporter(host_exporter.clone())
+            .build();
+        opentelemetry::global::set_tracer_provider(host_provider.clone());
+
+        let untrace = Untrace::init(Config::new("test-key".to_string()))
+            .await
+            .unwrap();
+        assert!(!untrace.config().use_global_provider);
+
+        untrace.tracer().start_span("untrace-span").end();
+        opentelemetry::global::tracer("host-library")
+            .start("host-span")
+            .end();
+        host_provider.force_flush();
+
+        let spans = host_exporter.get_finished_spans().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["host-span"]);
+
+        // A second init fails before it can replace the global providers
+        let config = Config::new("test-key".to_string()).with_use_global_provider(true);
+        assert!(Untrace::init(config).await.is_err());
+        opentelemetry::global::tracer("host-library")
+            .start("host-span-after-reinit")
+            .end();
+        host_provider.force_flush();
+        assert_eq!(host_exporter.get_finished_spans().unwrap().len(), 2);
+        assert!(!Untrace::get_instance().unwrap().config().use_global_provider);
+    }
+
+    #[test]
+    fn test_protobuf_payload_smaller_than_json() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        for i in 0..20 {
+            let options = LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                prompt_tokens: Some(100 + i),
+                completion_tokens: Some(50),
+                ..Default::default()
+            };
+            tracer.start_llm_span("llm-chat", options).end();
+        }
+        provider.force_flush();
+        let batch = exporter.get_finished_spans().unwrap();
+
+        let protobuf = Protocol::HttpProtobuf.encode(batch.clone()).unwrap();
+        let json = Protocol::HttpJson.encode(batch).unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&json).is_ok());
+        assert!(
+            protobuf.len() < json.len(),
+            "protobuf {} bytes, json {} bytes",
+            protobuf.len(),
+            json.len()
+        );
+
+        assert_eq!(Protocol::default(), Protocol::HttpProtobuf);
+        assert_eq!(Config::default().protocol, Protocol::HttpProtobuf);
+        assert_eq!("http/json".parse::<Protocol>().unwrap(), Protocol::HttpJson);
+        assert!("grpc".parse::<Protocol>().is_err());
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_span_guard_records_llm_metrics_once() {
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        let prometheus = PrometheusExporter::new();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(prometheus.clone())
+            .build();
+        let metrics = std::sync::Arc::new(UntraceMetrics::new(meter_provider.meter("test")));
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let mut guard = tracer
+            .start_llm_span_guard(
+                "chat",
+                LLMSpanOptions {
+                    provider: "openai".to_string(),
+                    model: "gpt-4".to_string(),
+                    ..Default::default()
+                },
+            )
+            .with_metrics(metrics.clone());
+        guard.complete(LLMSpanOptions {
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            duration_ms: Some(1200),
+            total_tokens: Some(1500),
+            cost_total: Some(0.06),
+            ..guard.options().clone()
+        });
+        guard.record_llm_metrics(&metrics);
+        drop(guard);
+        provider.force_flush();
+
+        let text = prometheus.metrics_text().unwrap();
+        assert!(text.contains(
+            r#"untrace_token_usage_total{model="gpt-4",provider="openai"} 1500"#
+        ));
+        assert!(text.contains(
+            r#"untrace_latency_count{model="gpt-4",operation="chat",provider="openai"} 1"#
+        ));
+        assert!(text.contains(
+            r#"untrace_cost_total{currency="USD",model="gpt-4",provider="openai"} 0.06"#
+        ));
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(
+            span_attribute(&spans[0], "llm.total_tokens"),
+            Some(opentelemetry::Value::I64(1500))
+        );
+    }
+
+    #[test]
+    fn test_region_selects_default_base_url() {
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_API_KEY", "env-key");
+        std::env::set_var("UNTRACE_REGION", "EU");
+        let from_region = Config::from_env();
+
+        std::env::set_var("UNTRACE_BASE_URL", "https://collector.internal");
+        let explicit_url = Config::from_env();
+        std::env::remove_var("UNTRACE_BASE_URL");
+
+        std::env::set_var("UNTRACE_REGION", "mars");
+        let unknown = Config::from_env();
+        std::env::remove_var("UNTRACE_REGION");
+        std::env::remove_var("UNTRACE_API_KEY");
+
+        let from_region = from_region.unwrap();
+        assert_eq!(from_region.region, Some(Region::Eu));
+        assert_eq!(from_region.base_url, "https://eu.untrace.dev");
+        assert_eq!(from_region.source("region"), ConfigSource::Env);
+
+        assert_eq!(explicit_url.unwrap().base_url, "https://collector.internal");
+
+        let err = unknown.unwrap_err().to_string();
+        assert!(err.contains("UNTRACE_REGION") && err.contains("mars"), "{}", err);
+
+        let config = Config::new("test-api-key".to_string()).with_region(Region::Us);
+        assert_eq!(config.base_url, "https://us.untrace.dev");
+
+        for config in [
+            Config::new("test-api-key".to_string())
+                .with_base_url("https://collector.internal".to_string())
+                .with_region(Region::Eu),
+            Config::new("test-api-key".to_string())
+                .with_region(Region::Eu)
+                .with_base_url("https://collector.internal".to_string()),
+        ] {
+            assert_eq!(config.base_url, "https://collector.internal");
+            assert_eq!(config.region, Some(Region::Eu));
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_span_assert_pass_and_fail() {
+        use crate::testing::SpanAssert;
+        use opentelemetry::trace::{Span as _, SpanKind, Status};
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.record_llm_spans(vec![(
+            "chat".to_string(),
+            LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                prompt_tokens: Some(10),
+                ..Default::default()
+            },
+        )]);
+        let mut failed = tracer.start_span("tool");
+        failed.set_status(Status::error("timed out"));
+        failed.end();
+        provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+
+        let assert = SpanAssert::new(&spans).has_span_count(2).has_no_span("missing");
+        assert
+            .assert_span("chat")
+            .has_attribute("llm.model", "gpt-4")
+            .has_attribute("llm.prompt_tokens", 10i64)
+            .has_attribute_key("llm.provider")
+            .lacks_attribute("llm.completion")
+            .has_kind(SpanKind::Client);
+        assert.assert_span("tool").has_status_error();
+
+        let panic_message = |check: &dyn Fn()| {
+            let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(check)).unwrap_err();
+            err.downcast_ref::<String>().cloned().unwrap_or_default()
+        };
+        let message = panic_message(&|| {
+            assert.assert_span("chat").has_attribute("llm.model", "gpt-3.5");
+        });
+        assert!(message.contains("\"chat\"") && message.contains("gpt-3.5"), "{}", message);
+        assert!(message.contains("gpt-4"), "{}", message);
+
+        let message = panic_message(&|| {
+            assert.assert_span("embed");
+        });
+        assert!(message.contains("\"embed\"") && message.contains("\"tool\""), "{}", message);
+
+        let message = panic_message(&|| {
+            assert.assert_span("tool").has_status_ok();
+        });
+        assert!(message.contains("expected status Ok"), "{}", message);
+
+        let message = panic_message(&|| {
+            assert.has_span_count(3);
+        });
+        assert!(message.contains("expected 3 span(s), found 2"), "{}", message);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_record_token_usage_unlabeled() {
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let metrics = UntraceMetrics::new(provider.meter("test"));
+
+        metrics.record_token_usage_unlabeled(100, 50).unwrap();
+        metrics.record_token_usage_unlabeled(10, 5).unwrap();
+        metrics
+            .record_token_usage(TokenUsage {
+                prompt_tokens: 1,
+                completion_tokens: 1,
+                total_tokens: 2,
+                model: String::new(),
+                provider: " ".to_string(),
+            })
+            .unwrap();
+
+        let text = exporter.metrics_text().unwrap();
+        assert!(text.contains(
+            r#"untrace_token_usage_total{model="unknown",provider="unknown"} 167"#
+        ));
+        let series = text
+            .lines()
+            .filter(|line| line.starts_with("untrace_token_usage_total{"))
+            .count();
+        assert_eq!(series, 1, "{}", text);
+    }
+
+    #[test]
+    fn test_context_snapshot_across_threads() {
+        use opentelemetry::trace::{Span as _, TraceContextExt as _};
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let parent = tracer.start_span("request");
+        let parent_context = parent.span_context().clone();
+        let snapshot = {
+            let _guard = opentelemetry::Context::current_with_span(parent).attach();
+            crate::context::capture()
+        };
+        assert!(crate::context::current_span_id().is_none());
+
+        let worker_tracer = tracer.clone();
+        std::thread::spawn(move || {
+            {
+                let _guard = snapshot.attach();
+                worker_tracer.start_span("work").end();
+            }
+            assert!(crate::context::current_span_id().is_none());
+        })
+        .join()
+        .unwrap();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let work = spans.iter().find(|span| span.name == "work").unwrap();
+        assert_eq!(work.parent_span_id, parent_context.span_id());
+        assert_eq!(work.span_context.trace_id(), parent_context.trace_id());
+    }
+
+    #[tokio::test]
+    async fn test_debug_export_log_redacts_sensitive_headers() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = server.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let (logs, _subscriber) = capture_logs();
+
+        let config = Config::new("sk-live-api-key".to_string())
+            .with_debug(true)
+            .with_base_url(format!("http://{}", addr))
+            .with_header("x-team".to_string(), "search".to_string())
+            .with_header("x-upstream-token".to_string(), "tok-123456".to_string())
+            .with_header("x-internal-id".to_string(), "internal-789".to_string())
+            .with_sensitive_header("internal".to_string());
+        let exporter = UntraceExporter::new(&config).unwrap();
+        let (tracer, spans, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_span("chat").end();
+        provider.force_flush();
+        exporter.send(spans.get_finished_spans().unwrap()).await.unwrap();
+
+        let logs = logs.contents();
+        assert!(logs.contains("Exporting"), "{}", logs);
+        assert!(logs.contains("x-team: search"), "{}", logs);
+        assert!(logs.contains("authorization: [REDACTED]"), "{}", logs);
+        for secret in ["sk-live-api-key", "tok-123456", "internal-789"] {
+            assert!(!logs.contains(secret), "{} leaked: {}", secret, logs);
+        }
+
+        let summary = config.effective_summary();
+        let (_, headers, _) = summary.iter().find(|(name, _, _)| name == "headers").unwrap();
+        assert!(!headers.contains("tok-123456"), "{}", headers);
+    }
+
+    #[test]
+    fn test_environment_parsing() {
+        for (name, expected) in [
+            ("production", Environment::Production),
+            ("Prod", Environment::Production),
+            (" development ", Environment::Development),
+            ("dev", Environment::Development),
+            ("STAGING", Environment::Staging),
+            ("stage", Environment::Staging),
+        ] {
+            assert_eq!(Environment::from(name), expected, "{}", name);
+        }
+
+        let custom = Environment::from("production-eu");
+        assert_eq!(custom, Environment::Custom("production-eu".to_string()));
+        assert_eq!(custom.to_string(), "production-eu");
+        assert_eq!(Environment::Staging.to_string(), "staging");
+
+        // Names given as strings are normalized
+        let config: Config = serde_json::from_str(r#"{"environment": "Prod"}"#).unwrap();
+        assert_eq!(config.environment, Environment::Production);
+        let config = Config::new("test-api-key".to_string()).with_environment("Prod".to_string());
+        assert_eq!(config.environment, "production");
+        let json = serde_json::to_value(
+            Config::new("test-api-key".to_string()).with_environment_typed(Environment::Staging),
+        )
+        .unwrap();
+        assert_eq!(json["environment"], "staging");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_drop_without_shutdown_warns() {
+        const WARNING: &str = "dropped without flush() or shutdown()";
+        let (logs, _subscriber) = capture_logs();
+
+        let config = Config::new("test-api-key".to_string()).with_service_name("leaky".to_string());
+        let untrace = Untrace::init_named_with_exporter(config, InMemorySpanExporter::default())
+            .await
+            .unwrap();
+        let handle = untrace.clone();
+        drop(untrace);
+        assert!(!logs.contents().contains(WARNING), "{}", logs.contents());
+        drop(handle);
+        assert!(logs.contents().contains(WARNING), "{}", logs.contents());
+
+        let (logs, _subscriber) = capture_logs();
+        let config = Config::new("test-api-key".to_string()).with_service_name("tidy".to_string());
+        let untrace = Untrace::init_named_with_exporter(config, InMemorySpanExporter::default())
+            .await
+            .unwrap();
+        untrace.shutdown().await.unwrap();
+        drop(untrace);
+        assert!(!logs.contents().contains(WARNING), "{}", logs.contents());
+    }
+
+    #[test]
+    fn test_start_span_templated() {
+        use opentelemetry::trace::Span as _;
+
+        let config = Config::new("test-api-key".to_string())
+            .with_global_span_attribute("deployment.region".to_string(), "eu");
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+
+        tracer
+            .start_span_templated(
+                "{llm.provider}.{llm.operation}",
+                vec![
+                    KeyValue::new(attributes::llm::PROVIDER, "openai"),
+                    KeyValue::new(attributes::llm::OPERATION, "chat"),
+                ],
+            )
+            .end();
+        tracer
+            .start_span_templated(
+                "{llm.provider}.{llm.model}@{deployment.region} {unclosed",
+                vec![KeyValue::new(attributes::llm::PROVIDER, "anthropic")],
+            )
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["openai.chat", "anthropic.{llm.model}@eu {unclosed"]);
+        assert_eq!(
+            span_attribute(&spans[0], attributes::llm::PROVIDER),
+            Some(opentelemetry::Value::from("openai"))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_events_per_span() {
+        use opentelemetry::trace::Span as _;
+
+        let exporter = InMemorySpanExporter::default();
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("events".to_string())
+            .with_max_events_per_span(3);
+        let untrace = Untrace::init_named_with_exporter(config, exporter.clone())
+            .await
+            .unwrap();
+
+        let mut span = untrace.tracer().start_span("agent-loop");
+        for step in 0..5 {
+            span.add_event(format!("step-{}", step), Vec::new());
+        }
+        span.end();
+        untrace.tracer().start_span("quiet").end();
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let busy = spans.iter().find(|span| span.name == "agent-loop").unwrap();
+        assert_eq!(busy.events.len(), 3);
+        assert_eq!(
+            span_attribute(busy, attributes::sdk::DROPPED_EVENTS),
+            Some(opentelemetry::Value::I64(2))
+        );
+        let quiet = spans.iter().find(|span| span.name == "quiet").unwrap();
+        assert!(span_attribute(quiet, attributes::sdk::DROPPED_EVENTS).is_none());
+    }
+
+    #[cfg(feature = "logs")]
+    #[test]
+    fn test_log_llm_io_correlates_with_span() {
+        use opentelemetry::logs::{AnyValue, LogResult, LoggerProvider as _};
+        use opentelemetry::trace::{Span as _, TraceContextExt as _};
+        use opentelemetry_sdk::export::logs::LogData;
+        use opentelemetry_sdk::logs::LogProcessor;
+        use std::sync::{Arc, Mutex};
+
+        /// Collects records as they are emitted, without a background thread
+        #[derive(Debug, Clone, Default)]
+        struct CollectingProcessor(Arc<Mutex<Vec<LogData>>>);
+
+        impl LogProcessor for CollectingProcessor {
+            fn emit(&self, data: LogData) {
+                self.0.lock().unwrap().push(data);
+            }
+
+            fn force_flush(&self) -> LogResult<()> {
+                Ok(())
+            }
+
+            fn shutdown(&mut self) -> LogResult<()> {
+                Ok(())
+            }
+        }
+
+        let logs = CollectingProcessor::default();
+        let logger_provider = opentelemetry_sdk::logs::LoggerProvider::builder()
+            .with_log_processor(logs.clone())
+            .build();
+        let (tracer, _spans, _provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let client = UntraceClient::new(
+            Arc::new(tracer.clone()),
+            Arc::new(UntraceMetrics::new(opentelemetry::global::meter("test"))),
+            Arc::new(UntraceContext::new()),
+        )
+        .with_logger(logger_provider.logger("test"));
+
+        let span = tracer.start_span("chat");
+        let span_context = span.span_context().clone();
+        {
+            let _guard = opentelemetry::Context::current_with_span(span).attach();
+            client.log_llm_io(
+                "What is 2+2?",
+                "4",
+                vec![KeyValue::new(attributes::llm::MODEL, "gpt-4")],
+            );
+        }
+
+        let records = logs.0.lock().unwrap().clone();
+        assert_eq!(records.len(), 1);
+        let record = &records[0].record;
+        let trace_context = record.trace_context.as_ref().unwrap();
+        assert_eq!(trace_context.trace_id, span_context.trace_id());
+        assert_eq!(trace_context.span_id, span_context.span_id());
+
+        let Some(AnyValue::Map(body)) = &record.body else {
+            panic!("unexpected body {:?}", record.body);
+        };
+        assert!(matches!(
+            body.get(&opentelemetry::Key::from("prompt")),
+            Some(AnyValue::String(prompt)) if prompt.as_str() == "What is 2+2?"
+        ));
+        let attributes = record.attributes.as_ref().unwrap();
+        assert!(attributes.iter().any(|(key, value)| {
+            key.as_str() == "event.name"
+                && matches!(value, AnyValue::String(name) if name.as_str() == "llm.io")
+        }));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_retry_budget() {
+        use crate::retry::SHUTDOWN_RETRY_BUDGET;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let server_attempts = attempts.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = server.accept().await.unwrap();
+                server_attempts.fetch_add(1, Ordering::SeqCst);
+                let mut buf = vec![0; 64 * 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\
+                          connection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        });
+
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("failing-endpoint".to_string())
+            .with_base_url(format!("http://{}", addr))
+            .with_retry_config(RetryConfig {
+                max_retries: u32::MAX,
+                initial_backoff: Duration::from_millis(50),
+                max_backoff: Duration::from_millis(200),
+                jitter: JitterKind::None,
+                total_budget: Duration::from_secs(600),
+            });
+        let untrace = Untrace::init_named(config.clone()).await.unwrap();
+        untrace.tracer().start_span("doomed").end();
+
+        let started = Instant::now();
+        let report = untrace.shutdown_with_report().await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert!(elapsed < SHUTDOWN_RETRY_BUDGET + Duration::from_secs(2), "{:?}", elapsed);
+        assert!(attempts.load(Ordering::SeqCst) > 1);
+        assert_eq!(report.flushed_spans, 0);
+        assert_eq!(report.dropped_spans, 1);
+
+        // The limit only lasts as long as its guard
+        let (tracer, spans, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_span("later").end();
+        provider.force_flush();
+        let batch = spans.get_finished_spans().unwrap();
+        let exporter = UntraceExporter::new(&config.with_retry_config(RetryConfig {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            jitter: JitterKind::None,
+            total_budget: Duration::from_secs(600),
+        }))
+        .unwrap();
+        let limit = exporter.limit_retries(Duration::ZERO);
+        let before = attempts.load(Ordering::SeqCst);
+        assert!(exporter.clone().send(batch.clone()).await.is_err());
+        assert!(attempts.load(Ordering::SeqCst) - before <= 1);
+        drop(limit);
+        let before = attempts.load(Ordering::SeqCst);
+        assert!(exporter.send(batch).await.is_err());
+        assert!(attempts.load(Ordering::SeqCst) - before >= 3);
+    }
+
+    #[test]
+    fn test_provider_sdk_version() {
+        use crate::provider::{
+            detected_sdk_version, get_default_providers, DEFAULT_PROVIDER_VERSION,
+        };
+
+        let providers = get_default_providers();
+        let version = |name: &str| {
+            providers
+                .iter()
+                .find(|provider| provider.name == name)
+                .map(|provider| provider.version.clone())
+                .unwrap()
+        };
+        assert_eq!(detected_sdk_version("anthropic"), None);
+        assert_eq!(version("anthropic"), DEFAULT_PROVIDER_VERSION);
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer
+            .start_llm_span(
+                "chat",
+                LLMSpanOptions {
+                    provider: "openai".to_string(),
+                    model: "gpt-4".to_string(),
+                    ..Default::default()
+                },
+            )
+            .end();
+        provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        let sdk_version = span_attribute(&spans[0], llm::PROVIDER_SDK_VERSION);
+
+        if cfg!(feature = "async-openai") {
+            let detected = detected_sdk_version("openai").unwrap();
+            assert_ne!(detected, DEFAULT_PROVIDER_VERSION);
+            assert!(detected.starts_with("0."), "{}", detected);
+            assert_eq!(version("openai"), detected);
+            assert_eq!(sdk_version.map(|v| v.to_string()).as_deref(), Some(detected));
+        } else {
+            assert_eq!(version("openai"), DEFAULT_PROVIDER_VERSION);
+            assert!(sdk_version.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_span_guard_times_subspans() {
+        use crate::guard::SUBSPAN_DURATION_MS;
+        use std::time::Duration;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let mut guard = tracer.start_llm_span_guard(
+            "chat",
+            LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let tokens = guard.time_subspan("tokenize", || {
+            std::thread::sleep(Duration::from_millis(10));
+            42
+        });
+        let status = guard
+            .time_subspan_async("network", || async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                200
+            })
+            .await;
+        guard.end();
+        provider.force_flush();
+
+        assert_eq!((tokens, status), (42, 200));
+        let spans = exporter.get_finished_spans().unwrap();
+        let durations: Vec<(String, f64)> = spans[0]
+            .events
+            .iter()
+            .map(|event| {
+                let duration = event
+                    .attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == SUBSPAN_DURATION_MS)
+                    .and_then(|kv| match kv.value {
+                        opentelemetry::Value::F64(ms) => Some(ms),
+                        _ => None,
+                    })
+                    .unwrap();
+                (event.name.to_string(), duration)
+            })
+            .collect();
+        assert_eq!(durations.len(), 2);
+        assert_eq!(durations[0].0, "tokenize");
+        assert!((10.0..1000.0).contains(&durations[0].1), "{:?}", durations);
+        assert_eq!(durations[1].0, "network");
+        assert!((30.0..1000.0).contains(&durations[1].1), "{:?}", durations);
+    }
+
+    #[test]
+    fn test_keep_slow_sampling_strategy() {
+        use crate::processor::SlowSpanProcessor;
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug)]
+        struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+        impl sdktrace::SpanProcessor for Collector {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, span: SpanData) {
+                self.0.lock().unwrap().push(span);
+            }
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let strategy: SamplingStrategy = "keep_slow:1000:0.0".parse().unwrap();
+        assert_eq!(
+            strategy,
+            SamplingStrategy::KeepSlow {
+                threshold_ms: 1000,
+                base_rate: 0.0
+            }
+        );
+        assert_eq!(strategy.to_string(), "keep_slow:1000:0");
+        assert!("keep_slow:1000".parse::<SamplingStrategy>().is_err());
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let counts = SpanCounts::new();
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_sampler(UntraceSampler::with_strategy(
+                &strategy,
+                1.0,
+                SamplingOverrides::new(),
+            )))
+            .with_span_processor(SlowSpanProcessor::new(
+                Collector(collected.clone()),
+                strategy.slow_threshold(),
+                counts.clone(),
+            ))
+            .build();
+        let tracer = UntraceTracer::with_config(
+            opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test"))),
+            Arc::new(Config::new("test-api-key".to_string())),
+        );
+
+        let options = |duration_ms| LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            duration_ms: Some(duration_ms),
+            ..Default::default()
+        };
+        tracer.start_llm_span("slow", options(5000)).end();
+        tracer.start_llm_span("fast", options(20)).end();
+
+        let spans = collected.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "slow");
+        assert_eq!(counts.discarded(), 1);
+    }
+
+    #[test]
+    fn test_retrieval_span_caps_scores_at_top_k() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer
+            .start_retrieval_span(RetrievalSpanOptions {
+                index: "docs".to_string(),
+                query: Some("how do refunds work".to_string()),
+                top_k: Some(3),
+                retrieved_count: Some(5),
+                scores: vec![0.41, 0.93, 0.12, 0.88, 0.67],
+                ..Default::default()
+            })
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans[0].name, "retrieval");
+        let attr = |key: &str| span_attribute(&spans[0], key).map(|v| v.to_string());
+        assert_eq!(attr(retrieval::INDEX).as_deref(), Some("docs"));
+        assert_eq!(attr(retrieval::QUERY).as_deref(), Some("how do refunds work"));
+        assert_eq!(attr(retrieval::TOP_K).as_deref(), Some("3"));
+        assert_eq!(attr(retrieval::RETRIEVED_COUNT).as_deref(), Some("5"));
+        assert_eq!(attr(retrieval::SCORES).as_deref(), Some("0.93,0.88,0.67"));
+    }
+
+    #[test]
+    fn test_llm_span_cache_hit_costs_nothing() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+
+        let options = |cache_hit| LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            cache_hit: Some(cache_hit),
+            ..Default::default()
+        };
+        tracer.start_llm_span("hit", options(true)).end();
+        tracer.start_llm_span("miss", options(false)).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |name: &str, key: &str| {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            span_attribute(span, key)
+        };
+        assert_eq!(attr("hit", llm::CACHE_HIT), Some(opentelemetry::Value::Bool(true)));
+        assert_eq!(attr("hit", llm::COST_TOTAL), Some(opentelemetry::Value::F64(0.0)));
+        assert_eq!(attr("miss", llm::CACHE_HIT), Some(opentelemetry::Value::Bool(false)));
+        match attr("miss", llm::COST_TOTAL) {
+            Some(opentelemetry::Value::F64(total)) => assert!((total - 0.06).abs() < 1e-9),
+            other => panic!("unexpected cost {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_llm_spans_share_conversation_id() {
+        let context = std::sync::Arc::new(UntraceContext::new());
+        context
+            .start_workflow(
+                "support-chat".to_string(),
+                context.generate_run_id(),
+                WorkflowOptions {
+                    conversation_id: Some("conv-42".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let tracer = tracer.with_untrace_context(context);
+
+        let turn = |message_index, conversation_id: Option<&str>| LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            conversation_id: conversation_id.map(str::to_string),
+            message_index: Some(message_index),
+            ..Default::default()
+        };
+        tracer.start_llm_span("turn-0", turn(0, None)).end();
+        tracer.start_llm_span("turn-1", turn(1, None)).end();
+        tracer.start_llm_span("other", turn(0, Some("conv-7"))).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |name: &str, key: &str| {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            span_attribute(span, key).map(|v| v.to_string())
+        };
+        assert_eq!(attr("turn-0", llm::CONVERSATION_ID).as_deref(), Some("conv-42"));
+        assert_eq!(attr("turn-1", llm::CONVERSATION_ID).as_deref(), Some("conv-42"));
+        assert_eq!(
+            attr("turn-0", llm::CONVERSATION_MESSAGE_INDEX).as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            attr("turn-1", llm::CONVERSATION_MESSAGE_INDEX).as_deref(),
+            Some("1")
+        );
+        assert_eq!(attr("other", llm::CONVERSATION_ID).as_deref(), Some("conv-7"));
+    }
+
+    #[test]
+    fn test_span_with_key_values_keeps_types() {
+        use opentelemetry::{Array, Value};
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let key_values = vec![
+            KeyValue::new("http.status_code", 200i64),
+            KeyValue::new("cache.ratio", 0.75),
+            KeyValue::new("retry", false),
+            KeyValue::new("route", "/v1/chat"),
+            KeyValue::new("tags", Value::Array(Array::String(vec!["a".into(), "b".into()]))),
+        ];
+
+        let mut options = SpanOptions {
+            name: "options".to_string(),
+            ..Default::default()
+        }
+        .with_key_values(key_values.clone());
+        options.attributes.insert("source".to_string(), "otel".to_string());
+        tracer.start_span_with_options(options).end();
+        tracer
+            .start_span_with_key_values("direct", SpanKind::Client, key_values.clone())
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        for name in ["options", "direct"] {
+            let span = spans.iter().find(|s| s.name == name).unwrap();
+            for kv in &key_values {
+                assert_eq!(span_attribute(span, kv.key.as_str()), Some(kv.value.clone()));
+            }
+        }
+        let options_span = spans.iter().find(|s| s.name == "options").unwrap();
+        assert_eq!(
+            span_attribute(options_span, "source"),
+            Some(Value::from("otel"))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_metrics_are_pushed_to_the_api() {
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::<(String, String)>::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = server.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = vec![0; 64 * 1024];
+                let (head, body) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length || read == 0 {
+                            break (head.to_string(), body.to_string());
+                        }
+                    }
+                };
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                received.lock().unwrap().push((path, body));
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        });
+
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("metrics-push".to_string())
+            .with_base_url(format!("http://{}", addr))
+            .with_protocol(Protocol::HttpJson);
+        let untrace = Untrace::init_named(config).await.unwrap();
+        untrace
+            .metrics()
+            .record_token_usage(TokenUsage {
+                prompt_tokens: 1000,
+                completion_tokens: 500,
+                total_tokens: 1500,
+                model: "gpt-4".to_string(),
+                provider: "openai".to_string(),
+            })
+            .unwrap();
+        untrace.flush().await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let (_, body) = requests
+            .iter()
+            .find(|(path, _)| path == "/v1/metrics")
+            .expect("metrics were not exported");
+        assert!(body.contains("untrace.token_usage"), "{}", body);
+        assert!(body.contains("1500"), "{}", body);
+        assert!(body.contains("gpt-4"), "{}", body);
+    }
+
+    /// Requests received by [`recording_server`], as path and body
+    type RecordedRequests = std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+    /// Start an HTTP server that answers 200 and records every request
+    async fn recording_server() -> (std::net::SocketAddr, RecordedRequests) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let requests = RecordedRequests::default();
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = server.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = vec![0; 64 * 1024];
+                let (head, body) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length || read == 0 {
+                            break (head.to_string(), body.to_string());
+                        }
+                    }
+                };
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                received.lock().unwrap().push((path, body));
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        });
+
+        (addr, requests)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flush_metrics_and_spans_separately() {
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        /// Span processor that only counts flushes
+        #[derive(Debug)]
+        struct FlushCounter(Arc<AtomicUsize>);
+
+        impl sdktrace::SpanProcessor for FlushCounter {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, _span: SpanData) {}
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let (addr, requests) = recording_server().await;
+
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("split-flush".to_string())
+            .with_base_url(format!("http://{}", addr))
+            .with_protocol(Protocol::HttpJson);
+        let untrace =
+            Untrace::init_named_with_span_processor(config, FlushCounter(flushes.clone()))
+                .await
+                .unwrap();
+        untrace.tracer().start_span("chat").end();
+        untrace
+            .metrics()
+            .record_token_usage(TokenUsage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+                model: "gpt-4".to_string(),
+                provider: "openai".to_string(),
+            })
+            .unwrap();
+
+        untrace.flush_metrics().await.unwrap();
+        assert!(requests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(path, body)| path == "/v1/metrics" && body.contains("untrace.token_usage")));
+        assert_eq!(flushes.load(Ordering::SeqCst), 0);
+
+        untrace.flush_spans().await.unwrap();
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_workflow_metadata_is_redacted() {
+        let config = Config::new("test-api-key".to_string())
+            .with_redaction_pattern(r"[\w.+-]+@[\w-]+\.[\w.]+".to_string());
+        let (tracer, exporter, provider) =
+            test_tracer_with_config(sdktrace::Sampler::AlwaysOn, config);
+
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("email".to_string(), "jane.doe@example.com".into());
+        metadata.insert("plan".to_string(), "enterprise".into());
+        let workflow = Workflow::new(
+            "onboarding".to_string(),
+            "run-1".to_string(),
+            WorkflowOptions {
+                metadata,
+                ..Default::default()
+            },
+        );
+        tracer.start_workflow_span(&workflow).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |key: &str| span_attribute(&spans[0], key).map(|v| v.to_string());
+        assert_eq!(
+            attr("workflow.metadata.email").as_deref(),
+            Some(crate::redaction::REDACTED)
+        );
+        assert_eq!(attr("workflow.metadata.plan").as_deref(), Some("enterprise"));
+    }
+
+    #[tokio::test]
+    async fn test_file_exporter_writes_and_rotates() {
+        use opentelemetry_sdk::export::trace::SpanExporter;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_span("first").end();
+        tracer.start_span("second").end();
+        tracer.start_span("third").end();
+        provider.force_flush();
+        let mut spans = exporter.get_finished_spans().unwrap();
+
+        let dir =
+            std::env::temp_dir().join(format!("untrace-file-export-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("spans.jsonl");
+        let line_lengths: Vec<u64> = spans
+            .iter()
+            .map(|span| Protocol::HttpJson.encode(vec![span.clone()]).unwrap().len() as u64 + 1)
+            .collect();
+        let mut file_exporter =
+            FileExporter::new(&path, line_lengths[0] + line_lengths[1]).unwrap();
+
+        let span_names = |path: &std::path::Path| -> Vec<String> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let request: serde_json::Value = serde_json::from_str(line).unwrap();
+                    request["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["name"]
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect()
+        };
+
+        for span in spans.drain(..) {
+            file_exporter.export(vec![span]).await.unwrap();
+        }
+
+        assert_eq!(span_names(file_exporter.rotated_path(1).as_path()), ["first", "second"]);
+        assert_eq!(span_names(&path), ["third"]);
+        assert!(!file_exporter.rotated_path(2).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_provider_names_are_normalized() {
+        use crate::provider::{register_default_providers, ProviderRegistry};
+        let mut registry = ProviderRegistry::new();
+        register_default_providers(&mut registry);
+        registry.register(Provider {
+            name: "Mistral".to_string(),
+            version: "1.0.0".to_string(),
+            enabled: true,
+            ..Default::default()
+        });
+
+        let openai = registry.get("OpenAI").unwrap();
+        assert_eq!(openai.name, "openai");
+        assert_eq!(openai.label(), "OpenAI");
+        let mistral = registry.get("mistral").unwrap();
+        assert_eq!(mistral.name, "mistral");
+        assert_eq!(mistral.label(), "Mistral");
+        registry.disable("MISTRAL").unwrap();
+        assert!(!registry.is_enabled("Mistral"));
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer
+            .start_llm_span(
+                "chat",
+                LLMSpanOptions {
+                    provider: " OpenAI ".to_string(),
+                    model: "gpt-4".to_string(),
+                    prompt_tokens: Some(1000),
+                    ..Default::default()
+                },
+            )
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(span_attribute(&spans[0], "llm.provider"), Some("openai".into()));
+        assert!(span_attribute(&spans[0], "llm.cost_total").is_some());
+    }
+
+    #[test]
+    fn test_status_from_http() {
+        use opentelemetry::trace::Status;
+
+        assert_eq!(status_from_http(200), Status::Ok);
+        assert_eq!(status_from_http(404), Status::error("HTTP 404 Not Found"));
+        assert_eq!(status_from_http(503), Status::error("HTTP 503 Service Unavailable"));
+        assert_eq!(status_from_http(302), Status::Unset);
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let mut options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            ..Default::default()
+        };
+        crate::provider::record_llm_error(&mut options, 503, "");
+        tracer.record_llm_spans(vec![("chat".to_string(), options)]);
+
+        let mut request = tracer.start_span("GET /health");
+        tracer.set_http_status(&mut request, 200);
+        request.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans[0].status, Status::error("HTTP 503 Service Unavailable"));
+        assert_eq!(span_attribute(&spans[0], llm::HTTP_STATUS_CODE), Some(503.into()));
+        assert_eq!(spans[1].status, Status::Ok);
+        assert_eq!(span_attribute(&spans[1], framework::STATUS_CODE), Some(200.into()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_additional_span_processor_receives_spans() {
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug)]
+        struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+        impl sdktrace::SpanProcessor for Collector {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, span: SpanData) {
+                self.0.lock().unwrap().push(span);
+            }
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let untrace = Untrace::init_named_with_span_processor(
+            Config::new("test-key".to_string())
+                .with_service_name("extra-processor".to_string())
+                .with_dry_run(true),
+            Collector(Arc::clone(&collected)),
+        )
+        .await
+        .unwrap();
+
+        untrace.tracer().start_span("first").end();
+        untrace.tracer().start_span("second").end();
+        assert!(untrace.tracer_provider().force_flush().iter().all(Result::is_ok));
+        assert!(untrace.meter_provider().force_flush().is_ok());
+
+        let names: Vec<String> =
+            collected.lock().unwrap().iter().map(|span| span.name.to_string()).collect();
+        assert_eq!(names, ["first", "second"]);
+
+        let report = untrace.shutdown_with_report().await.unwrap();
+        assert_eq!(report.flushed_spans, 2);
+    }
+
+    #[test]
+    fn test_inapplicable_operation_options() {
+        let embedding = || LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "text-embedding-3-small".to_string(),
+            operation: LLMOperationType::Embedding,
+            prompt_tokens: Some(8),
+            temperature: Some(0.7),
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+
+        let (logs, _subscriber) = capture_logs();
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        tracer.start_llm_span("embed", embedding()).end();
+        provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(span_attribute(&spans[0], llm::TEMPERATURE), Some(0.7.into()));
+        // The warning is logged once per operation type; no other test sets
+        // these options on embedding or moderation spans
+        tracer.start_llm_span("embed", embedding()).end();
+        tracer
+            .start_llm_span(
+                "moderate",
+                LLMSpanOptions {
+                    operation: LLMOperationType::Moderation,
+                    ..embedding()
+                },
+            )
+            .end();
+        let logs = logs.contents();
+        let warning = "embedding spans don't use llm.temperature, llm.top_p";
+        assert_eq!(logs.matches(warning).count(), 1, "{}", logs);
+        assert!(logs.contains("moderation spans don't use llm.temperature, llm.top_p"), "{}", logs);
+
+        let (tracer, exporter, provider) = test_tracer_with_config(
+            sdktrace::Sampler::AlwaysOn,
+            Config::new("test-api-key".to_string()).with_strict_operation_attributes(true),
+        );
+        tracer.start_llm_span("embed", embedding()).end();
+        tracer
+            .start_llm_span(
+                "chat",
+                LLMSpanOptions {
+                    operation: LLMOperationType::Chat,
+                    ..embedding()
+                },
+            )
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(span_attribute(&spans[0], llm::TEMPERATURE), None);
+        assert_eq!(span_attribute(&spans[0], llm::TOP_P), None);
+        assert_eq!(span_attribute(&spans[0], llm::PROMPT_TOKENS), Some(8.into()));
+        assert_eq!(span_attribute(&spans[1], llm::TEMPERATURE), Some(0.7.into()));
+    }
+
+    #[test]
+    fn test_end_workflow_by_run_id() {
+        let context = UntraceContext::new();
+        for run_id in ["outer", "middle", "inner"] {
+            context
+                .start_workflow(run_id.to_string(), run_id.to_string(), WorkflowOptions::default())
+                .unwrap();
+        }
+
+        let middle = context.end_workflow("middle").unwrap();
+        assert_eq!(middle.run_id, "middle");
+        assert!(context.end_workflow("middle").is_err());
+        assert_eq!(context.get_current_workflow().unwrap().run_id, "inner");
+
+        context.end_current_workflow().unwrap();
+        assert_eq!(context.get_current_workflow().unwrap().run_id, "outer");
+        assert_eq!(context.end_workflow("outer").unwrap().name, "outer");
+        assert!(context.get_current_workflow().is_none());
+    }
+
+    #[test]
+    fn test_parallel_llm_spans_are_siblings() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let calls = ["gpt-4", "claude-3-opus", "gemini-pro"]
+            .iter()
+            .zip(["openai", "anthropic", "google"])
+            .map(|(model, provider)| LLMSpanOptions {
+                provider: provider.to_string(),
+                model: model.to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        let (parent, children) = tracer.start_parallel_llm_spans("ensemble", calls);
+        assert_eq!(children.len(), 3);
+        for child in children {
+            child.end();
+        }
+        parent.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let parent = spans.iter().find(|span| span.name == "ensemble").unwrap();
+        let children: Vec<_> = spans.iter().filter(|span| span.name != "ensemble").collect();
+        assert_eq!(children.len(), 3);
+        for child in &children {
+            assert_eq!(child.parent_span_id, parent.span_context.span_id());
+            assert_eq!(child.span_context.trace_id(), parent.span_context.trace_id());
+        }
+        let names: Vec<&str> = children.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, ["openai.chat", "anthropic.chat", "google.chat"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_validation_failure_policy() {
+        let invalid = || Config::new(String::new()).with_service_name("invalid".to_string());
+
+        let err = Untrace::init_named(invalid()).await.unwrap_err();
+        assert!(err.to_string().contains("API key cannot be empty"), "{}", err);
+
+        let (logs, _subscriber) = capture_logs();
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            invalid().with_on_validation_failure(ValidationPolicy::DisableAndWarn),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(untrace.is_disabled());
+        assert_eq!(untrace.config().service_name, "invalid");
+        assert!(logs.contents().contains("tracing is disabled"), "{}", logs.contents());
+
+        untrace.tracer().start_span("ignored").end();
+        untrace.flush().await.unwrap();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+        untrace.shutdown().await.unwrap();
+
+        assert_eq!(
+            "disable_and_warn".parse::<ValidationPolicy>().unwrap(),
+            ValidationPolicy::DisableAndWarn
+        );
+    }
+
+    #[test]
+    fn test_sdk_attribute_keys_are_interned() {
+        use crate::attributes::{helpers, llm, workflow};
+
+        let first = helpers::key(llm::PROVIDER);
+        let second = helpers::key(llm::PROVIDER);
+        assert_eq!(first.as_str().as_ptr(), second.as_str().as_ptr());
+
+        let first = helpers::string(workflow::RUN_ID, "run-1");
+        let second = helpers::int(workflow::RUN_ID, 1);
+        assert_eq!(first.key.as_str().as_ptr(), second.key.as_str().as_ptr());
+
+        // Keys outside the SDK's namespaces are still copied
+        let custom = String::from("app.custom");
+        assert_ne!(helpers::key(&custom).as_str().as_ptr(), custom.as_ptr());
+        assert_eq!(helpers::key(&custom).as_str(), "app.custom");
+    }
+
+    #[test]
+    fn test_start_span_borrowed() {
+        use crate::attributes::llm;
+        use crate::types::AttributeValue;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let model = AttributeValue::from("gpt-4o-mini");
+        let retries = AttributeValue::from(2);
+        let cached = AttributeValue::from(true);
+        tracer
+            .start_span_borrowed(
+                "request",
+                &[
+                    (llm::MODEL, &model),
+                    ("app.retries", &retries),
+                    ("app.cached", &cached),
+                ],
+            )
+            .end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans[0].name, "request");
+        assert_eq!(
+            span_attribute(&spans[0], llm::MODEL).map(|v| v.to_string()),
+            Some("gpt-4o-mini".to_string())
+        );
+        assert_eq!(
+            span_attribute(&spans[0], "app.retries"),
+            Some(opentelemetry::Value::I64(2))
+        );
+        assert_eq!(
+            span_attribute(&spans[0], "app.cached"),
+            Some(opentelemetry::Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn test_capture_body_skipped_for_unrecorded_spans() {
+        use crate::attributes::llm;
+        use std::cell::Cell;
+
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            ..Default::default()
+        };
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::TraceIdRatioBased(0.0));
+        let built = Cell::new(false);
+        let mut guard = tracer.start_llm_span_guard("llm-chat", options.clone());
+        assert!(!guard.is_recording());
+        guard.capture_body(|| {
+            built.set(true);
+            (Some("prompt".to_string()), Some("completion".to_string()))
+        });
+        guard.end();
+        provider.force_flush();
+        assert!(!built.get());
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let mut guard = tracer.start_llm_span_guard("llm-chat", options);
+        assert!(guard.is_recording());
+        guard.capture_body(|| {
+            built.set(true);
+            (Some("prompt".to_string()), None)
+        });
+        guard.end();
+        provider.force_flush();
+        assert!(built.get());
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], llm::PROMPT).map(|v| v.to_string()),
+            Some("prompt".to_string())
+        );
+        assert!(span_attribute(&spans[0], llm::COMPLETION).is_none());
+    }
+
+    #[test]
+    fn test_set_attribute_lazy() {
+        use crate::types::SpanExt;
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::TraceIdRatioBased(0.0));
+        let mut span = tracer.start_span("request");
+        assert!(!span.is_recording());
+        span.set_attribute_lazy("app.body", || -> String { panic!("span is not recording") });
+        span.end();
+
+        let mut guard = tracer.start_llm_span_guard("llm-chat", LLMSpanOptions::default());
+        guard.set_attribute_lazy("llm.prompt", || -> String { panic!("span is not recording") });
+        guard.end();
+        provider.force_flush();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let mut span = tracer.start_span("request");
+        span.set_attribute_lazy("app.body", || "body".to_string());
+        span.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], "app.body").map(|v| v.to_string()),
+            Some("body".to_string())
+        );
+    }
+
+    #[test]
+    fn test_min_span_duration_keeps_error_spans() {
+        use crate::processor::MinDurationProcessor;
+        use opentelemetry::trace::Status;
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::sync::{Arc, Mutex};
+        use std::time::{Duration, SystemTime};
+
+        #[derive(Debug)]
+        struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+        impl sdktrace::SpanProcessor for Collector {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, span: SpanData) {
+                self.0.lock().unwrap().push(span);
+            }
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let config = Config::new("test-api-key".to_string())
+            .with_min_span_duration(Duration::from_millis(10));
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let counts = SpanCounts::new();
+        let provider = TracerProvider::builder()
+            .with_span_processor(MinDurationProcessor::new(
+                Collector(collected.clone()),
+                config.min_span_duration,
+                counts.clone(),
+            ))
+            .build();
+        let tracer = UntraceTracer::with_config(
+            opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test"))),
+            Arc::new(config),
+        );
+
+        tracer.start_span("cache-hit").end();
+
+        let mut span = tracer.start_span("cache-error");
+        span.set_status(Status::error("cache unavailable"));
+        span.end();
+
+        let mut span = tracer.start_span("cache-miss");
+        span.end_with_timestamp(SystemTime::now() + Duration::from_millis(50));
+
+        let spans = collected.lock().unwrap();
+        let names: Vec<_> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert_eq!(names, vec!["cache-error", "cache-miss"]);
+        assert_eq!(counts.discarded(), 1);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn test_trace_llm_call() {
+        use crate::instrumentation::generic::trace_llm_call;
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry::trace::Status;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        struct Reply {
+            text: String,
+            input_tokens: u32,
+            output_tokens: u32,
+        }
+
+        let prometheus = PrometheusExporter::new();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(prometheus.clone())
+            .build();
+        let metrics = std::sync::Arc::new(UntraceMetrics::new(meter_provider.meter("test")));
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let tracer = tracer.with_metrics(metrics);
+        let options = LLMSpanOptions {
+            provider: "anthropic".to_string(),
+            model: "claude-3-haiku".to_string(),
+            ..Default::default()
+        };
+
+        let call = async {
+            Ok::<_, std::io::Error>(Reply {
+                text: "Hi!".to_string(),
+                input_tokens: 12,
+                output_tokens: 3,
+            })
+        };
+        let reply = trace_llm_call(&tracer, "llm-chat", options.clone(), call, |reply, options| {
+            options.prompt_tokens = Some(reply.input_tokens);
+            options.completion_tokens = Some(reply.output_tokens);
+        })
+        .await
+        .unwrap();
+        assert_eq!(reply.text, "Hi!");
+
+        let call = async { Err::<Reply, _>(std::io::Error::other("connection reset")) };
+        let err = trace_llm_call(&tracer, "llm-chat", options, call, |_, _| {})
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "connection reset");
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(
+            span_attribute(&spans[0], "llm.prompt_tokens"),
+            Some(opentelemetry::Value::I64(12))
+        );
+        assert_eq!(spans[0].status, Status::Unset);
+        assert_eq!(
+            span_attribute(&spans[1], "llm.error").map(|v| v.to_string()),
+            Some("connection reset".to_string())
+        );
+        assert_eq!(spans[1].status, Status::error("connection reset"));
+
+        let text = prometheus.metrics_text().unwrap();
+        assert!(text.contains(
+            r#"untrace_token_usage_total{model="claude-3-haiku",provider="anthropic"} 15"#
+        ));
+        assert!(text.contains(concat!(
+            r#"untrace_latency_count{model="claude-3-haiku",operation="chat","#,
+            r#"provider="anthropic"} 2"#
+        )));
+        assert!(text.contains(r#"error_type="unknown""#), "{}", text);
+    }
+
+    #[test]
+    fn test_currency_parsing_and_conversion() {
+        use crate::cost::ExchangeRates;
+        use crate::types::{Cost, Currency};
+
+        assert_eq!("usd".parse::<Currency>().unwrap(), Currency::Usd);
+        assert_eq!(" EUR ".parse::<Currency>().unwrap(), Currency::Eur);
+        assert_eq!(
+            "sek".parse::<Currency>().unwrap(),
+            Currency::Other("SEK".to_string())
+        );
+        assert_eq!(Currency::Other("SEK".to_string()).to_string(), "SEK");
+        assert!("dollars".parse::<Currency>().is_err());
+        assert!("U$D".parse::<Currency>().is_err());
+
+        let cost: Cost = serde_json::from_value(serde_json::json!({
+            "prompt": 0.01,
+            "completion": 0.02,
+            "total": 0.03,
+            "currency": "USD",
+            "model": "gpt-4",
+            "provider": "openai"
+        }))
+        .unwrap();
+        assert_eq!(cost.currency, Currency::Usd);
+        assert_eq!(serde_json::to_value(&cost).unwrap()["currency"], "USD");
+        assert!(serde_json::from_value::<Currency>(serde_json::json!("dollars")).is_err());
+
+        let rates = ExchangeRates::new().with_rate(Currency::Eur, 0.5);
+        let converted = rates.convert(&cost, &Currency::Eur).unwrap();
+        assert_eq!(converted.currency, Currency::Eur);
+        assert!((converted.total - 0.015).abs() < 1e-12);
+        let back = rates.convert(&converted, &Currency::Usd).unwrap();
+        assert!((back.total - 0.03).abs() < 1e-12);
+
+        let err = rates
+            .convert(&cost, &Currency::Other("SEK".to_string()))
+            .unwrap_err();
+        assert!(err.to_string().contains("SEK"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_builder_matches_config_then_init() {
+        let builder = Untrace::builder("test-api-key")
+            .service_name("chat-api")
+            .environment(Environment::Staging)
+            .sampling_rate(0.5)
+            .capture_body(false)
+            .configure(|config| config.with_dry_run(true));
+        let config = Config::new("test-api-key".to_string())
+            .with_service_name("chat-api".to_string())
+            .with_environment_typed(Environment::Staging)
+            .with_sampling_rate(0.5)
+            .with_capture_body(false)
+            .with_dry_run(true);
+        assert_eq!(builder.config().effective_summary(), config.effective_summary());
+
+        let from_builder = builder.init_named().await.unwrap();
+        let from_config = Untrace::init_named(config).await.unwrap();
+        assert_eq!(
+            from_builder.config().effective_summary(),
+            from_config.config().effective_summary()
+        );
+        from_builder.shutdown().await.unwrap();
+        from_config.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ingest_otlp_json_round_trip() {
+        use opentelemetry::trace::Status;
+
+        let client_exporter = InMemorySpanExporter::default();
+        let client = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("client".to_string()),
+            client_exporter.clone(),
+        )
+        .await
+        .unwrap();
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(10),
+            ..Default::default()
+        };
+        let mut span = client.tracer().start_llm_span("llm-chat", options);
+        span.add_event("retry", vec![KeyValue::new("attempt", 2)]);
+        span.set_attribute(KeyValue::new(
+            "app.attempts",
+            opentelemetry::Value::Array(vec![1i64, 2].into()),
+        ));
+        span.set_status(Status::error("rate limited"));
+        span.end();
+        client.tracer().start_span("postprocess").end();
+        client.flush().await.unwrap();
+        let sent = client_exporter.get_finished_spans().unwrap();
+        let body = Protocol::HttpJson.encode(sent.clone()).unwrap();
+
+        let relay_exporter = InMemorySpanExporter::default();
+        let relay = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("relay".to_string()),
+            relay_exporter.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(relay.ingest_otlp_json(&body).unwrap(), 2);
+        assert!(relay.ingest_otlp_json(b"not json").is_err());
+        relay.flush().await.unwrap();
+
+        let received = relay_exporter.get_finished_spans().unwrap();
+        assert_eq!(received.len(), sent.len());
+        for (sent, received) in sent.iter().zip(&received) {
+            assert_eq!(received.name, sent.name);
+            assert_eq!(received.span_context.trace_id(), sent.span_context.trace_id());
+            assert_eq!(received.span_context.span_id(), sent.span_context.span_id());
+            assert_eq!(received.parent_span_id, sent.parent_span_id);
+            assert_eq!(received.span_kind, sent.span_kind);
+            assert_eq!(received.start_time, sent.start_time);
+            assert_eq!(received.end_time, sent.end_time);
+            assert_eq!(received.attributes, sent.attributes);
+            assert_eq!(received.status, sent.status);
+            assert_eq!(received.events.len(), sent.events.len());
+            assert_eq!(received.instrumentation_lib.name, sent.instrumentation_lib.name);
+            assert_eq!(
+                received.resource.get(Key::new("service.name")).map(|v| v.to_string()),
+                Some("client".to_string())
+            );
+        }
+
+        client.shutdown().await.unwrap();
+        relay.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_record_feedback_references_original_span() {
+        use crate::attributes::feedback;
+        use crate::tracer::FEEDBACK_SPAN_NAME;
+
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+
+        let span = untrace.tracer().start_span("llm-chat");
+        let rated = span.span_context().clone();
+        drop(span);
+        untrace.record_feedback(
+            &rated,
+            Feedback {
+                score: 0.0,
+                label: Some("hallucination".to_string()),
+                comment: None,
+            },
+        );
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let feedback_span = spans
+            .iter()
+            .find(|span| span.name == FEEDBACK_SPAN_NAME)
+            .unwrap();
+        assert_eq!(feedback_span.span_context.trace_id(), rated.trace_id());
+        assert_eq!(feedback_span.parent_span_id, rated.span_id());
+        let links: Vec<_> = feedback_span.links.iter().collect();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].span_context, rated);
+        assert_eq!(
+            span_attribute(feedback_span, feedback::SPAN_ID).map(|v| v.to_string()),
+            Some(rated.span_id().to_string())
+        );
+        assert_eq!(
+            span_attribute(feedback_span, feedback::SCORE),
+            Some(opentelemetry::Value::F64(0.0))
+        );
+        assert_eq!(
+            span_attribute(feedback_span, feedback::LABEL).map(|v| v.to_string()),
+            Some("hallucination".to_string())
+        );
+        assert!(span_attribute(feedback_span, feedback::COMMENT).is_none());
+        untrace.shutdown().await.unwrap();
+
+        // Feedback on a trace kept by keep-slow sampling is kept with it
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string())
+                .with_sampling_strategy("keep_slow:1000:0.0".parse().unwrap()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        let slow = LLMSpanOptions {
+            duration_ms: Some(5000),
+            ..Default::default()
+        };
+        let span = untrace.tracer().start_llm_span("slow-chat", slow);
+        let rated = span.span_context().clone();
+        assert!(!rated.is_sampled());
+        drop(span);
+        untrace.record_feedback(
+            &rated,
+            Feedback {
+                score: 1.0,
+                label: None,
+                comment: None,
+            },
+        );
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let feedback_span = spans
+            .iter()
+            .find(|span| span.name == FEEDBACK_SPAN_NAME)
+            .unwrap();
+        assert_eq!(feedback_span.parent_span_id, rated.span_id());
+        untrace.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_invalid_env_headers_are_skipped() {
+        use crate::config::MAX_HEADER_VALUE_LENGTH;
+
+        let oversized = "a".repeat(MAX_HEADER_VALUE_LENGTH + 1);
+        let (logs, _guard) = capture_logs();
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_API_KEY", "env-key");
+        std::env::set_var(
+            "UNTRACE_HEADERS",
+            format!("x-team=search,x team=bad,x-big={}", oversized),
+        );
+        let config = Config::from_env();
+        std::env::remove_var("UNTRACE_HEADERS");
+        std::env::remove_var("UNTRACE_API_KEY");
+
+        let config = config.unwrap();
+        assert_eq!(config.headers.len(), 1);
+        assert_eq!(config.headers.get("x-team").map(String::as_str), Some("search"));
+        assert!(config.validate().is_ok());
+        let logs = logs.contents();
+        assert!(logs.contains("\"x team\" is not a valid header name"), "{}", logs);
+        assert!(logs.contains("the value of x-big is"), "{}", logs);
+
+        let config = Config::new("test-api-key".to_string())
+            .with_header("x-big".to_string(), oversized);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("x-big"), "{}", err);
+        let config = Config::new("test-api-key".to_string())
+            .with_header("x-line".to_string(), "a\nb".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_finished_span_round_trips_through_serde() {
+        use opentelemetry::trace::{Span as _, TraceContextExt as _, Tracer as _};
+
+        let (_tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let tracer = provider.tracer("test");
+        let parent = tracer.start("parent");
+        let cx = opentelemetry::Context::current_with_span(parent);
+        let mut child = tracer.start_with_context("child", &cx);
+        child.set_attribute(opentelemetry::KeyValue::new("llm.model", "gpt-4"));
+        child.set_attribute(opentelemetry::KeyValue::new("llm.temperature", 0.7));
+        child.set_attribute(opentelemetry::KeyValue::new("llm.prompt_tokens", 12i64));
+        child.set_attribute(opentelemetry::KeyValue::new(
+            "llm.stop_sequences",
+            opentelemetry::Value::Array(opentelemetry::Array::String(vec![
+                "\n\n".into(),
+                "END".into(),
+            ])),
+        ));
+        child.set_attribute(opentelemetry::KeyValue::new(
+            "retrieval.scores",
+            opentelemetry::Value::Array(opentelemetry::Array::F64(vec![0.9, 0.5])),
+        ));
+        child.add_event("retry", vec![opentelemetry::KeyValue::new("attempt", 2i64)]);
+        child.set_status(opentelemetry::trace::Status::error("rate limited"));
+        child.end();
+        cx.span().end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let finished = FinishedSpan::from(&spans[0]);
+        assert_eq!(finished.name, "child");
+        assert_eq!(finished.kind, opentelemetry::trace::SpanKind::Internal);
+        assert_eq!(
+            finished.parent_span_id.as_deref(),
+            Some(spans[1].span_context.span_id().to_string().as_str())
+        );
+        assert_eq!(
+            finished.attribute("llm.model"),
+            Some(&AttributeValue::String("gpt-4".to_string()))
+        );
+        assert_eq!(finished.attribute("llm.prompt_tokens"), Some(&AttributeValue::Int(12)));
+        assert_eq!(
+            finished.attribute("llm.stop_sequences"),
+            Some(&AttributeValue::StringArray(vec!["\n\n".to_string(), "END".to_string()]))
+        );
+        assert_eq!(
+            finished.attribute("retrieval.scores"),
+            Some(&AttributeValue::FloatArray(vec![0.9, 0.5]))
+        );
+        assert_eq!(finished.events[0].name, "retry");
+        assert_eq!(
+            finished.status,
+            SpanStatus::Error {
+                message: "rate limited".to_string()
+            }
+        );
+        assert_eq!(FinishedSpan::from(&spans[1]).parent_span_id, None);
+
+        let json = serde_json::to_string(&finished).unwrap();
+        assert!(json.contains(r#""kind":"internal""#));
+        let parsed: FinishedSpan = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, finished);
+    }
+
+    #[test]
+    fn test_callback_exporter_receives_finished_spans() {
+        use opentelemetry::trace::Tracer as _;
+
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(CallbackExporter::new(move |spans| {
+                sink.lock().unwrap().extend(spans)
+            }))
+            .build();
+        provider.tracer("test").in_span("callback", |_| {});
+        provider.force_flush();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].name, "callback");
+        assert_eq!(received[0].status, SpanStatus::Unset);
+    }
+
+    #[test]
+    fn test_model_sampling_rates() {
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_API_KEY", "test-api-key");
+        std::env::set_var(
+            "UNTRACE_MODEL_SAMPLING_RATES",
+            "gpt-4=1.0,ft:gpt-3.5-turbo:acme=0.01",
+        );
+        let config = Config::from_env();
+        std::env::remove_var("UNTRACE_MODEL_SAMPLING_RATES");
+        std::env::remove_var("UNTRACE_API_KEY");
+        let config = config.unwrap();
+        assert_eq!(config.model_sampling_rates.get("gpt-4"), Some(&1.0));
+        assert_eq!(config.model_sampling_rates.get("ft:gpt-3.5-turbo:acme"), Some(&0.01));
+        assert_eq!(config.sampling_strategy, SamplingStrategy::Ratio);
+
+        let invalid = Config::new("test-api-key".to_string())
+            .with_model_sampling_rate("gpt-4".to_string(), 1.5);
+        assert!(invalid.validate().is_err());
+
+        let sampler = UntraceSampler::with_strategy(
+            &SamplingStrategy::Ratio,
+            0.0,
+            SamplingOverrides::new(),
+        )
+        .with_model_rates(
+            [("gpt-4".to_string(), 1.0), ("gpt-3.5-turbo".to_string(), 0.01)]
+                .into_iter()
+                .collect(),
+        );
+        let (tracer, exporter, provider) = test_tracer(sampler);
+        for model in ["gpt-4", "gpt-3.5-turbo", "claude-3-haiku"] {
+            for _ in 0..200 {
+                let options = LLMSpanOptions {
+                    provider: "openai".to_string(),
+                    model: model.to_string(),
+                    ..Default::default()
+                };
+                tracer.start_llm_span("llm.call", options).end();
+            }
+        }
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let kept = |model: &'static str| {
+            spans
+                .iter()
+                .filter(|span| span_attribute(span, "llm.model") == Some(model.into()))
+                .count()
+        };
+        assert_eq!(kept("gpt-4"), 200);
+        assert!(kept("gpt-3.5-turbo") < 20);
+        assert_eq!(kept("claude-3-haiku"), 0);
+    }
+
+    #[test]
+    fn test_run_id_formats() {
+        let is_ulid = |id: &str| {
+            id.len() == 26
+                && id.chars().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+                && !id.contains(['I', 'L', 'O', 'U'])
+        };
+
+        let uuid = UntraceContext::new().generate_run_id();
+        assert!(uuid::Uuid::parse_str(&uuid).is_ok(), "{}", uuid);
+
+        let context = UntraceContext::with_run_id_format(RunIdFormat::Ulid);
+        let first = context.generate_run_id();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = context.generate_run_id();
+        assert!(is_ulid(&first), "{}", first);
+        assert!(first < second, "{} >= {}", first, second);
+
+        let context = UntraceContext::with_run_id_format(RunIdFormat::Prefixed("run".to_string()));
+        let prefixed = context.generate_run_id();
+        let ulid = prefixed.strip_prefix("run_").unwrap();
+        assert!(is_ulid(ulid), "{}", prefixed);
+        let job = UntraceContext::new().generate_run_id_with_prefix("job");
+        assert!(job.starts_with("job_") && is_ulid(&job[4..]), "{}", job);
+
+        assert_eq!("ULID".parse::<RunIdFormat>().unwrap(), RunIdFormat::Ulid);
+        let format: RunIdFormat = "prefixed:run".parse().unwrap();
+        assert_eq!(format, RunIdFormat::Prefixed("run".to_string()));
+        assert_eq!(format.to_string(), "prefixed:run");
+        assert!("snowflake".parse::<RunIdFormat>().is_err());
+
+        let config = Config::new("test-api-key".to_string())
+            .with_run_id_format(RunIdFormat::Prefixed(String::new()));
+        assert!(config.validate().is_err());
+        let config: Config = serde_json::from_str(r#"{"run_id_format": "ulid"}"#).unwrap();
+        assert_eq!(config.run_id_format, RunIdFormat::Ulid);
+    }
+
+    #[test]
+    fn test_span_handle_from_concurrent_callbacks() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let handle = SpanHandle::new(tracer.start_span("llm.stream"));
+        let on_token = |handle: &SpanHandle, thread: usize, token: usize| {
+            handle.add_event(
+                "token",
+                vec![KeyValue::new("thread", thread as i64), KeyValue::new("index", token as i64)],
+            );
+        };
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let handle = handle.clone();
+                scope.spawn(move || {
+                    for token in 0..25 {
+                        on_token(&handle, thread, token);
+                    }
+                    handle.set_attribute(KeyValue::new(format!("thread.{}.tokens", thread), 25));
+                });
+            }
+        });
+        assert!(handle.is_recording());
+        handle.end();
+        handle.add_event("after_end", Vec::new());
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].events.len(), 100);
+        for thread in 0..4 {
+            assert_eq!(
+                span_attribute(&spans[0], &format!("thread.{}.tokens", thread)),
+                Some(opentelemetry::Value::I64(25))
+            );
+        }
+    }
+
+    #[test]
+    fn test_span_handle_after_callback_panic() {
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let handle = SpanHandle::new(tracer.start_span("llm.stream"));
+        let poisoned = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _span = handle.lock();
+            panic!("callback panicked");
+        }));
+        assert!(poisoned.is_err());
+
+        assert!(handle.is_recording());
+        handle.add_event("token", Vec::new());
+        handle.set_attribute(KeyValue::new("stream.tokens", 1));
+        handle.end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].events.len(), 1);
+        assert_eq!(span_attribute(&spans[0], "stream.tokens"), Some(opentelemetry::Value::I64(1)));
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_span_names() {
+        let exporter = InMemorySpanExporter::default();
+        let batch = sdktrace::BatchSpanProcessor::builder(
+            exporter.clone(),
+            opentelemetry_sdk::runtime::Tokio,
+        )
+        .with_batch_config(
+            sdktrace::BatchConfig::default()
+                .with_scheduled_delay(std::time::Duration::from_secs(3600)),
+        )
+        .build();
+        let config = Config::new("test-api-key".to_string())
+            .with_flush_on_span_names(vec!["critical-op".to_string()]);
+        let policy = FlushPolicy::new().with_span_names(config.flush_on_span_names.clone());
+        let provider = TracerProvider::builder()
+            .with_span_processor(CountFlushProcessor::new(batch, policy))
+            .build();
+        // Let the batch worker take its interval's immediate first tick
+        tokio::task::yield_now().await;
+
+        let tracer = provider.tracer("test");
+        tracer.start("normal-op").end();
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+
+        tracer.start("critical-op").end();
+        assert!(
+            wait_until_async(|| {
+                exporter.get_finished_spans().unwrap().iter().any(|span| span.name == "critical-op")
+            })
+            .await
+        );
+
+        // Shutting the batch processor down blocks until its worker stops
+        tokio::task::spawn_blocking(move || drop(provider)).await.unwrap();
+    }
+
+    #[test]
+    fn test_secret_across_truncation_limit_is_redacted() {
+        let config = Config::new("test-api-key".to_string())
+            .with_max_attribute_length(40)
+            .with_redaction_pattern(r"sk-[A-Za-z0-9]{20,}".to_string());
+        let redactor = Redactor::new(&config.redaction_patterns).unwrap();
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(RedactingExporter::new(exporter.clone(), redactor))
+            .build();
+        let tracer = UntraceTracer::with_config(
+            opentelemetry::global::BoxedTracer::new(Box::new(provider.tracer("test"))),
+            std::sync::Arc::new(config),
+        );
+
+        // The cut at 40 bytes lands inside the key, leaving a prefix too short to match
+        let prompt = "Call the API with sk-abcdefghijklmnopqrstuvwxyz123456 please";
+        let options = LLMSpanOptions {
+            prompt: Some(prompt.to_string()),
+            ..Default::default()
+        };
+        tracer.start_llm_span("llm-chat", options).end();
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let prompt = span_attribute(&spans[0], llm::PROMPT).unwrap().to_string();
+        assert!(!prompt.contains("sk-abc"), "{}", prompt);
+        assert!(prompt.starts_with("Call the API with [REDACTED]"), "{}", prompt);
+        assert!(prompt.len() <= 40, "{}", prompt);
+    }
+
+    #[test]
+    fn test_http_json_uses_the_otlp_json_mapping() {
+        use opentelemetry::trace::{SpanContext, SpanId, SpanKind, TraceFlags, TraceId, TraceState};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let span = opentelemetry_sdk::export::trace::SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_hex("5b8efff798038103d269b633813fc60c").unwrap(),
+                SpanId::from_hex("eee19b7ec3c1b174").unwrap(),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from_hex("eee19b7ec3c1b173").unwrap(),
+            span_kind: SpanKind::Server,
+            name: "I'm a server span".into(),
+            start_time: UNIX_EPOCH + Duration::from_nanos(1544712660000000000),
+            end_time: UNIX_EPOCH + Duration::from_nanos(1544712661000000000),
+            attributes: vec![
+                KeyValue::new("my.span.attr", "some value"),
+                KeyValue::new("my.span.count", 3_i64),
+                KeyValue::new("my.span.cached", false),
+            ],
+            dropped_attributes_count: 0,
+            events: sdktrace::EvictedQueue::new(0),
+            links: sdktrace::EvictedQueue::new(0),
+            status: opentelemetry::trace::Status::Unset,
+            resource: std::borrow::Cow::Owned(opentelemetry_sdk::Resource::new([
+                KeyValue::new("service.name", "my.service"),
+            ])),
+            instrumentation_lib: opentelemetry::InstrumentationLibrary::new(
+                "my.library",
+                Some("1.0.0"),
+                None::<&'static str>,
+                Some(vec![KeyValue::new("my.scope.attribute", "some scope attribute")]),
+            ),
+        };
+
+        // The trace example from the OTLP specification, with the canonical
+        // lowercase IDs and string timestamps
+        let expected = serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        {"key": "service.name", "value": {"stringValue": "my.service"}}
+                    ]
+                },
+                "scopeSpans": [{
+                    "scope": {
+                        "name": "my.library",
+                        "version": "1.0.0",
+                        "attributes": [{
+                            "key": "my.scope.attribute",
+                            "value": {"stringValue": "some scope attribute"}
+                        }]
+                    },
+                    "spans": [{
+                        "traceId": "5b8efff798038103d269b633813fc60c",
+                        "spanId": "eee19b7ec3c1b174",
+                        "parentSpanId": "eee19b7ec3c1b173",
+                        "name": "I'm a server span",
+                        "startTimeUnixNano": "1544712660000000000",
+                        "endTimeUnixNano": "1544712661000000000",
+                        "kind": 2,
+                        "attributes": [
+                            {"key": "my.span.attr", "value": {"stringValue": "some value"}},
+                            {"key": "my.span.count", "value": {"intValue": "3"}},
+                            {"key": "my.span.cached", "value": {"boolValue": false}}
+                        ],
+                        "status": {}
+                    }]
+                }]
+            }]
+        });
+
+        let json = Protocol::HttpJson.encode(vec![span]).unwrap();
+        let actual: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ingest_otlp_json_from_the_specification() {
+        use opentelemetry::trace::{SpanKind, TraceFlags};
+
+        // The trace example from the OTLP specification, plus an unsampled
+        // span carrying W3C trace flags
+        let body = br#"{
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{"key": "service.name", "value": {"stringValue": "my.service"}}]
+                },
+                "scopeSpans": [{
+                    "scope": {
+                        "name": "my.library",
+                        "version": "1.0.0",
+                        "attributes": [{
+                            "key": "my.scope.attribute",
+                            "value": {"stringValue": "some scope attribute"}
+                        }]
+                    },
+                    "spans": [
+                        {
+                            "traceId": "5B8EFFF798038103D269B633813FC60C",
+                            "spanId": "EEE19B7EC3C1B174",
+                            "parentSpanId": "EEE19B7EC3C1B173",
+                            "name": "I'm a server span",
+                            "startTimeUnixNano": 1544712660000000000,
+                            "endTimeUnixNano": 1544712661000000000,
+                            "kind": 2,
+                            "attributes": [{
+                                "key": "my.span.attr",
+                                "value": {"stringValue": "some value"}
+                            }]
+                        },
+                        {
+                            "traceId": "5B8EFFF798038103D269B633813FC60C",
+                            "spanId": "EEE19B7EC3C1B175",
+                            "name": "unsampled",
+                            "startTimeUnixNano": "1544712660000000000",
+                            "endTimeUnixNano": "1544712661000000000",
+                            "flags": 256
+                        }
+                    ]
+                }]
+            }]
+        }"#;
+
+        let exporter = InMemorySpanExporter::default();
+        let relay = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("relay".to_string()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(relay.ingest_otlp_json(body).unwrap(), 1);
+        relay.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "I'm a server span");
+        assert_eq!(
+            span.span_context.trace_id().to_string(),
+            "5b8efff798038103d269b633813fc60c"
+        );
+        assert_eq!(span.span_context.span_id().to_string(), "eee19b7ec3c1b174");
+        assert_eq!(span.parent_span_id.to_string(), "eee19b7ec3c1b173");
+        assert_eq!(span.span_context.trace_flags(), TraceFlags::SAMPLED);
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(
+            span.end_time.duration_since(span.start_time).unwrap(),
+            std::time::Duration::from_secs(1)
+        );
+        assert_eq!(span.attributes, vec![KeyValue::new("my.span.attr", "some value")]);
+        assert_eq!(span.instrumentation_lib.name, "my.library");
+        assert_eq!(span.instrumentation_lib.version.as_deref(), Some("1.0.0"));
+        assert_eq!(
+            span.resource.get(Key::new("service.name")).map(|v| v.to_string()),
+            Some("my.service".to_string())
+        );
+
+        relay.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_keep_slow_decides_per_trace() {
+        use crate::processor::SlowSpanProcessor;
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry::trace::{TraceContextExt, Tracer};
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+        use opentelemetry_sdk::export::trace::SpanData;
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Debug)]
+        struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+        impl sdktrace::SpanProcessor for Collector {
+            fn on_start(&self, _span: &mut sdktrace::Span, _cx: &opentelemetry::Context) {}
+            fn on_end(&self, span: SpanData) {
+                self.0.lock().unwrap().push(span);
+            }
+            fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+            fn shutdown(&mut self) -> opentelemetry::trace::TraceResult<()> {
+                Ok(())
+            }
+        }
+
+        let strategy: SamplingStrategy = "keep_slow:1000:0.0".parse().unwrap();
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let counts = SpanCounts::new();
+        let provider = TracerProvider::builder()
+            .with_config(sdktrace::config().with_sampler(UntraceSampler::with_strategy(
+                &strategy,
+                1.0,
+                SamplingOverrides::new(),
+            )))
+            .with_span_processor(SlowSpanProcessor::new(
+                Collector(collected.clone()),
+                strategy.slow_threshold(),
+                counts.clone(),
+            ))
+            .build();
+        let tracer = provider.tracer("test");
+        let child = |name: &'static str, duration_ms: i64, cx: &opentelemetry::Context| {
+            tracer
+                .span_builder(name)
+                .with_attributes(vec![KeyValue::new(llm::DURATION_MS, duration_ms)])
+                .start_with_context(&tracer, cx)
+        };
+
+        // A slow child keeps its whole trace, including spans ending after the root
+        let root = tracer.start("kept-root");
+        let cx = opentelemetry::Context::current_with_span(root);
+        let mut headers = HashMap::new();
+        TraceContextPropagator::new().inject_context(&cx, &mut headers);
+        child("slow-child", 5000, &cx).end();
+        child("fast-child", 20, &cx).end();
+        let mut late = child("late-child", 20, &cx);
+        cx.span().end();
+        assert_eq!(collected.lock().unwrap().len(), 3);
+        late.end();
+
+        // A trace of fast spans is discarded as a whole
+        let root = tracer.start("dropped-root");
+        let cx = opentelemetry::Context::current_with_span(root);
+        child("fast-child", 20, &cx).end();
+        cx.span().end();
+
+        let spans = collected.lock().unwrap();
+        let mut names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        names.sort();
+        assert_eq!(names, ["fast-child", "kept-root", "late-child", "slow-child"]);
+        assert!(spans.iter().all(|span| span.span_context.is_sampled()));
+        assert_eq!(counts.discarded(), 2);
+
+        // Deferred traces leave the process unsampled and without a mark
+        assert!(headers["traceparent"].ends_with("-00"), "{:?}", headers);
+        assert!(headers.get("tracestate").is_none_or(|state| state.is_empty()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_workflow_summary_passes_through_llm_spans_after_workflow_span() {
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string())
+                .with_service_name("summarized".to_string())
+                .with_workflow_summary_mode(true),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+        let tracer = untrace.tracer();
+        let chat = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(10),
+            completion_tokens: Some(5),
+            ..Default::default()
+        };
+
+        let workflow = untrace
+            .context()
+            .start_workflow(
+                "answer-question".to_string(),
+                "run-1".to_string(),
+                WorkflowOptions::default(),
+            )
+            .unwrap();
+        let mut workflow_span = tracer.start_workflow_span(&workflow);
+        let mut late = tracer.start_llm_span("late-chat", chat.clone());
+        tracer.start_llm_span("chat", chat.clone()).end();
+        workflow_span.end();
+        late.end();
+
+        // No workflow span was ever started for this run
+        let _workflow = untrace
+            .context()
+            .start_workflow(
+                "background".to_string(),
+                "run-2".to_string(),
+                WorkflowOptions::default(),
+            )
+            .unwrap();
+        tracer.start_llm_span("orphan-chat", chat).end();
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let mut names: Vec<&str> = spans.iter().map(|s| s.name.as_ref()).collect();
+        names.sort();
+        assert_eq!(names, ["answer-question", "late-chat", "orphan-chat"]);
+        let summary = spans.iter().find(|s| s.name == "answer-question").unwrap();
+        assert_eq!(
+            span_attribute(summary, workflow::LLM_CALLS),
+            Some(opentelemetry::Value::I64(1))
+        );
+    }
+
+    #[test]
+    fn test_redaction_patterns_from_env() {
+        let _env = ENV_LOCK.lock().unwrap();
+        std::env::set_var("UNTRACE_API_KEY", "test-api-key");
+        std::env::set_var(
+            "UNTRACE_REDACTION_PATTERNS",
+            r#"["sk-[A-Za-z0-9]{20,}", "\\d{3}-\\d{2}-\\d{4}"]"#,
+        );
+        let config = Config::from_env();
+        std::env::set_var("UNTRACE_REDACTION_PATTERNS", "sk-.*");
+        let invalid = Config::from_env();
+        std::env::remove_var("UNTRACE_REDACTION_PATTERNS");
+        std::env::remove_var("UNTRACE_API_KEY");
+
+        let config = config.unwrap();
+        assert_eq!(
+            config.redaction_patterns,
+            vec![r"sk-[A-Za-z0-9]{20,}".to_string(), r"\d{3}-\d{2}-\d{4}".to_string()]
+        );
+        assert!(config.validate().is_ok());
+        let err = invalid.unwrap_err().to_string();
+        assert!(err.contains("UNTRACE_REDACTION_PATTERNS"), "{}", err);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_computed_cost_is_recorded_once() {
+        use crate::prometheus::PrometheusExporter;
+        use opentelemetry::metrics::MeterProvider as _;
+        use opentelemetry_sdk::metrics::MeterProvider as SdkMeterProvider;
+
+        let prometheus = PrometheusExporter::new();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(prometheus.clone())
+            .build();
+        let metrics = std::sync::Arc::new(UntraceMetrics::new(meter_provider.meter("test")));
+        let (tracer, exporter, provider) = test_tracer(sdktrace::Sampler::AlwaysOn);
+        let tracer = tracer.with_metrics(metrics);
+
+        let options = LLMSpanOptions {
+            provider: "openai".to_string(),
+            model: "gpt-4".to_string(),
+            prompt_tokens: Some(1000),
+            completion_tokens: Some(500),
+            ..Default::default()
+        };
+        let span = tracer.start_llm_span("llm-chat", options.clone());
+        tracer.complete_llm_span(span, options);
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(
+            span_attribute(&spans[0], llm::COST_TOTAL),
+            Some(opentelemetry::Value::F64(0.06))
+        );
+        let text = prometheus.metrics_text().unwrap();
+        assert!(text.contains(
+            r#"untrace_cost_total{currency="USD",model="gpt-4",provider="openai"} 0.06"#
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_keep_slow_defers_parallel_llm_spans() {
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string())
+                .with_service_name("parallel-keep-slow".to_string())
+                .with_sampling_strategy("keep_slow:1000:0.0".parse().unwrap()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+
+        let calls = [5000, 20]
+            .into_iter()
+            .map(|duration_ms| LLMSpanOptions {
+                provider: "openai".to_string(),
+                model: "gpt-4".to_string(),
+                duration_ms: Some(duration_ms),
+                ..Default::default()
+            })
+            .collect();
+        let (parent, children) = untrace.tracer().start_parallel_llm_spans("ensemble", calls);
+        for child in children {
+            assert!(child.is_recording());
+            child.end();
+        }
+        parent.end();
+        untrace.flush().await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 3, "{:?}", spans);
+        let parent = spans.iter().find(|span| span.name == "ensemble").unwrap();
+        assert!(spans
+            .iter()
+            .filter(|span| span.name != "ensemble")
+            .all(|span| span.parent_span_id == parent.span_context.span_id()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_after_shutdown_are_not_exported() {
+        let exporter = InMemorySpanExporter::default();
+        let untrace = Untrace::init_named_with_exporter(
+            Config::new("test-key".to_string()).with_service_name("shut-down".to_string()),
+            exporter.clone(),
+        )
+        .await
+        .unwrap();
+
+        untrace.tracer().start_span("before").end();
+        let report = untrace.shutdown_with_report().await.unwrap();
+        assert_eq!(report.flushed_spans, 1);
+
+        untrace.tracer().start_span("after").end();
+        untrace.flush_spans().await.unwrap();
+        assert!(exporter.get_finished_spans().unwrap().is_empty());
+        let report = untrace.shutdown_with_report().await.unwrap();
+        assert_eq!(report.flushed_spans, 1);
+    }
+}
@@ -0,0 +1,74 @@
+//! LLM inputs and outputs as OpenTelemetry log records
+//!
+//! Available with the `logs` feature. Some backends would rather store
+//! prompts and completions as logs than as span attributes; records emitted
+//! with [`UntraceClient::log_llm_io`](crate::client::UntraceClient::log_llm_io)
+//! carry the active span's trace and span IDs so they can still be joined
+//! to the trace.
+
+use crate::exporter::UntraceExporter;
+use crate::redaction::Redactor;
+use async_trait::async_trait;
+use opentelemetry::logs::{AnyValue, LogError, LogResult};
+use opentelemetry::StringValue;
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+
+/// `event.name` of the records emitted by `log_llm_io`
+pub const LLM_IO_EVENT: &str = "llm.io";
+
+/// Log exporter that redacts records and sends them to the Untrace API
+#[derive(Debug)]
+pub struct UntraceLogExporter {
+    exporter: UntraceExporter,
+    redactor: Redactor,
+}
+
+impl UntraceLogExporter {
+    /// Export through the given span exporter's connection and settings
+    pub fn new(exporter: UntraceExporter, redactor: Redactor) -> Self {
+        Self { exporter, redactor }
+    }
+}
+
+#[async_trait]
+impl LogExporter for UntraceLogExporter {
+    async fn export(&mut self, mut batch: Vec<LogData>) -> LogResult<()> {
+        if !self.redactor.is_empty() {
+            for data in &mut batch {
+                if let Some(body) = &mut data.record.body {
+                    redact_value(&self.redactor, body);
+                }
+                for (_, value) in data.record.attributes.iter_mut().flatten() {
+                    redact_value(&self.redactor, value);
+                }
+            }
+        }
+
+        self.exporter
+            .send_logs(batch)
+            .await
+            .map_err(|err| LogError::Other(Box::new(err)))
+    }
+}
+
+/// Redact the strings in a log value, including inside lists and maps
+pub(crate) fn redact_value(redactor: &Redactor, value: &mut AnyValue) {
+    match value {
+        AnyValue::String(text) => {
+            if let Some(redacted) = redactor.redact(text.as_str()) {
+                *text = StringValue::from(redacted);
+            }
+        }
+        AnyValue::ListAny(values) => {
+            for value in values {
+                redact_value(redactor, value);
+            }
+        }
+        AnyValue::Map(map) => {
+            for value in map.values_mut() {
+                redact_value(redactor, value);
+            }
+        }
+        _ => {}
+    }
+}
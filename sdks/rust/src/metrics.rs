@@ -1,25 +1,165 @@
 //! Metrics collection for the Untrace SDK
 
+use crate::clock::{default_clock, Clock, SharedClock};
 use crate::error::UntraceResult;
+use crate::exporter::UntraceExporter;
 use crate::types::{Cost, TokenUsage};
-use opentelemetry::metrics::{Counter, Histogram, Meter, MeterProvider};
+use async_trait::async_trait;
+use opentelemetry::global;
+use opentelemetry::metrics::{Counter, Histogram, Meter, MetricsError};
 use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{
+    AggregationSelector, DefaultAggregationSelector, DefaultTemporalitySelector,
+    TemporalitySelector,
+};
+use opentelemetry_sdk::metrics::{
+    new_view, Aggregation, Instrument, InstrumentKind, MeterProvider as SdkMeterProvider, Stream,
+    View,
+};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Name of the latency histogram
+pub const LATENCY_METRIC: &str = "untrace.latency";
+
+/// Name of the streaming throughput histogram
+pub const STREAM_THROUGHPUT_METRIC: &str = "untrace.stream.throughput";
+
+/// Label recorded for the model or provider of usage that doesn't name one
+pub const UNKNOWN_LABEL: &str = "unknown";
+
+/// Default latency histogram bucket boundaries in milliseconds
+///
+/// Doubles from 10ms to about 41s, which spans cached responses through long
+/// generations far better than the SDK's linear defaults.
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 13] = [
+    10.0, 20.0, 40.0, 80.0, 160.0, 320.0, 640.0, 1280.0, 2560.0, 5120.0, 10240.0, 20480.0,
+    40960.0,
+];
+
+/// Build a view that applies the given bucket boundaries to the latency histogram
+pub fn latency_view(boundaries: Vec<f64>) -> opentelemetry::metrics::Result<Box<dyn View>> {
+    new_view(
+        Instrument::new().name(LATENCY_METRIC),
+        Stream::new().aggregation(Aggregation::ExplicitBucketHistogram {
+            boundaries,
+            record_min_max: true,
+        }),
+    )
+}
+
+/// Whether an instance has installed its meter provider globally
+static GLOBAL_METER_PROVIDER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Guards the warning about recording to the no-op global meter provider
+static NO_PROVIDER_WARNING: Once = Once::new();
+
+/// Check whether an instance has installed its meter provider globally
+///
+/// Unlike tracer providers, OpenTelemetry gives no way to tell a meter
+/// provider installed by another library from the no-op default, so only
+/// providers installed through this SDK are detected.
+pub(crate) fn global_meter_provider_installed() -> bool {
+    GLOBAL_METER_PROVIDER_INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Install a meter provider globally, so [`UntraceMetrics::global`] records to it
+pub(crate) fn set_global_meter_provider(meter_provider: SdkMeterProvider) {
+    global::set_meter_provider(meter_provider);
+    GLOBAL_METER_PROVIDER_INSTALLED.store(true, Ordering::Relaxed);
+}
+
+/// Metric exporter that pushes collected metrics to the Untrace API
+///
+/// Used with a periodic reader, which exports on the configured interval
+/// and whenever the meter provider is flushed.
+#[derive(Debug)]
+pub struct UntraceMetricExporter {
+    exporter: UntraceExporter,
+}
+
+impl UntraceMetricExporter {
+    /// Export through the given span exporter's connection and settings
+    pub fn new(exporter: UntraceExporter) -> Self {
+        Self { exporter }
+    }
+}
+
+impl AggregationSelector for UntraceMetricExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+impl TemporalitySelector for UntraceMetricExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        DefaultTemporalitySelector::new().temporality(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for UntraceMetricExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+        self.exporter
+            .send_metrics(metrics)
+            .await
+            .map_err(|err| MetricsError::Other(err.to_string()))
+    }
+
+    async fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+        Ok(())
+    }
+}
 
 /// Untrace metrics implementation
 #[derive(Debug)]
 pub struct UntraceMetrics {
     meter: Meter,
+    /// Whether the meter came from the global provider before one was
+    /// installed, so its instruments are no-ops for good
+    noop_provider: bool,
     token_usage_counter: Counter<u64>,
     cost_counter: Counter<f64>,
     latency_histogram: Histogram<f64>,
     error_counter: Counter<u64>,
+    stream_throughput_histogram: Histogram<f64>,
+    clock: SharedClock,
 }
 
 impl UntraceMetrics {
     /// Create new metrics
     pub fn new(meter: Meter) -> Self {
+        Self::with_shared_clock(meter, default_clock())
+    }
+
+    /// Create metrics from the global meter provider
+    ///
+    /// `Untrace::init` installs a provider globally. Until then the global
+    /// provider is a no-op, and metrics created from it keep discarding what
+    /// they record even after a provider is installed. Recording to them logs
+    /// a warning once.
+    pub fn global(name: &'static str) -> Self {
+        let mut metrics = Self::new(global::meter(name));
+        metrics.noop_provider = !GLOBAL_METER_PROVIDER_INSTALLED.load(Ordering::Relaxed);
+        metrics
+    }
+
+    /// Create new metrics whose timers read time from the given clock
+    #[cfg(feature = "testing")]
+    pub fn with_clock(meter: Meter, clock: std::sync::Arc<dyn Clock>) -> Self {
+        Self::with_shared_clock(meter, clock)
+    }
+
+    fn with_shared_clock(meter: Meter, clock: SharedClock) -> Self {
         let token_usage_counter = meter
             .u64_counter("untrace.token_usage")
             .with_description("Total token usage")
@@ -31,7 +171,7 @@ impl UntraceMetrics {
             .init();
 
         let latency_histogram = meter
-            .f64_histogram("untrace.latency")
+            .f64_histogram(LATENCY_METRIC)
             .with_description("Operation latency")
             .init();
 
@@ -40,32 +180,85 @@ impl UntraceMetrics {
             .with_description("Total errors")
             .init();
 
+        let stream_throughput_histogram = meter
+            .f64_histogram(STREAM_THROUGHPUT_METRIC)
+            .with_description("Streaming response throughput in tokens per second")
+            .init();
+
         Self {
             meter,
+            noop_provider: false,
             token_usage_counter,
             cost_counter,
             latency_histogram,
             error_counter,
+            stream_throughput_histogram,
+            clock,
         }
     }
 
     /// Record token usage
+    ///
+    /// An empty model or provider is recorded as [`UNKNOWN_LABEL`].
     pub fn record_token_usage(&self, usage: TokenUsage) -> UntraceResult<()> {
+        self.check_provider();
         let attributes = vec![
-            KeyValue::new("provider", usage.provider),
-            KeyValue::new("model", usage.model),
+            KeyValue::new("provider", or_unknown(&usage.provider).to_string()),
+            KeyValue::new("model", or_unknown(&usage.model).to_string()),
         ];
 
         self.token_usage_counter.add(usage.total_tokens as u64, &attributes);
         Ok(())
     }
 
+    /// Record token usage that isn't broken down by model, e.g. from an aggregated report
+    ///
+    /// The usage is recorded with both the model and provider set to
+    /// [`UNKNOWN_LABEL`], so it always lands in a single series.
+    pub fn record_token_usage_unlabeled(
+        &self,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) -> UntraceResult<()> {
+        self.record_token_usage(TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens.saturating_add(completion_tokens),
+            model: String::new(),
+            provider: String::new(),
+        })
+    }
+
+    /// Record token usage for many calls at once, e.g. when replaying history
+    ///
+    /// Usage is summed per provider and model first, so the counter is only
+    /// updated once per pair.
+    pub fn record_token_usage_batch(&self, usages: &[TokenUsage]) -> UntraceResult<()> {
+        self.check_provider();
+        let mut totals: HashMap<(&str, &str), u64> = HashMap::new();
+        for usage in usages {
+            *totals
+                .entry((or_unknown(&usage.provider), or_unknown(&usage.model)))
+                .or_default() += usage.total_tokens as u64;
+        }
+
+        for ((provider, model), total) in totals {
+            let attributes = [
+                KeyValue::new("provider", provider.to_string()),
+                KeyValue::new("model", model.to_string()),
+            ];
+            self.token_usage_counter.add(total, &attributes);
+        }
+        Ok(())
+    }
+
     /// Record cost
     pub fn record_cost(&self, cost: Cost) -> UntraceResult<()> {
+        self.check_provider();
         let attributes = vec![
             KeyValue::new("provider", cost.provider),
             KeyValue::new("model", cost.model),
-            KeyValue::new("currency", cost.currency),
+            KeyValue::new("currency", cost.currency.to_string()),
         ];
 
         self.cost_counter.add(cost.total, &attributes);
@@ -74,6 +267,7 @@ impl UntraceMetrics {
 
     /// Record latency
     pub fn record_latency(&self, duration: Duration, attributes: HashMap<String, String>) -> UntraceResult<()> {
+        self.check_provider();
         let latency_ms = duration.as_millis() as f64;
         let otel_attributes: Vec<KeyValue> = attributes
             .into_iter()
@@ -84,8 +278,18 @@ impl UntraceMetrics {
         Ok(())
     }
 
+    /// Start a timer that records latency when stopped
+    pub fn start_timer(&self, attributes: HashMap<String, String>) -> LatencyTimer<'_> {
+        LatencyTimer {
+            metrics: self,
+            start: self.clock.now(),
+            attributes,
+        }
+    }
+
     /// Record an error
     pub fn record_error(&self, error_type: &str, attributes: HashMap<String, String>) -> UntraceResult<()> {
+        self.check_provider();
         let mut otel_attributes = vec![KeyValue::new("error_type", error_type.to_string())];
         otel_attributes.extend(
             attributes
@@ -97,6 +301,34 @@ impl UntraceMetrics {
         Ok(())
     }
 
+    /// Record the throughput of a streamed response in tokens per second
+    pub fn record_stream_throughput(&self, tokens_per_second: f64, provider: &str, model: &str) {
+        self.check_provider();
+        let attributes = [
+            KeyValue::new("provider", provider.to_string()),
+            KeyValue::new("model", model.to_string()),
+        ];
+        self.stream_throughput_histogram
+            .record(tokens_per_second, &attributes);
+    }
+
+    /// Warn once if recording to a meter from the no-op global provider
+    fn check_provider(&self) {
+        if self.noop_provider {
+            NO_PROVIDER_WARNING.call_once(|| {
+                warn!(
+                    "Recording metrics without a meter provider installed; they are discarded. \
+                     Initialize Untrace before creating UntraceMetrics::global"
+                );
+            });
+        }
+    }
+
+    /// Get the clock used for timing
+    pub(crate) fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
     /// Get the underlying meter
     pub fn get_meter(&self) -> &Meter {
         &self.meter
@@ -107,10 +339,44 @@ impl Clone for UntraceMetrics {
     fn clone(&self) -> Self {
         Self {
             meter: self.meter.clone(),
+            noop_provider: self.noop_provider,
             token_usage_counter: self.token_usage_counter.clone(),
             cost_counter: self.cost_counter.clone(),
             latency_histogram: self.latency_histogram.clone(),
             error_counter: self.error_counter.clone(),
+            stream_throughput_histogram: self.stream_throughput_histogram.clone(),
+            clock: self.clock.clone(),
         }
     }
 }
+
+/// Timer that measures an operation and records it to the latency histogram
+#[derive(Debug)]
+pub struct LatencyTimer<'a> {
+    metrics: &'a UntraceMetrics,
+    start: Instant,
+    attributes: HashMap<String, String>,
+}
+
+impl LatencyTimer<'_> {
+    /// Get the time elapsed since the timer started
+    pub fn elapsed(&self) -> Duration {
+        self.metrics.clock.now().saturating_duration_since(self.start)
+    }
+
+    /// Stop the timer, record the latency and return the measured duration
+    pub fn stop(self) -> UntraceResult<Duration> {
+        let elapsed = self.elapsed();
+        self.metrics.record_latency(elapsed, self.attributes)?;
+        Ok(elapsed)
+    }
+}
+
+/// Use [`UNKNOWN_LABEL`] in place of an empty metric label
+fn or_unknown(label: &str) -> &str {
+    if label.trim().is_empty() {
+        UNKNOWN_LABEL
+    } else {
+        label
+    }
+}
@@ -0,0 +1,419 @@
+//! OTLP/JSON, the protobuf JSON mapping used by OTLP over HTTP
+//!
+//! Follows the OTLP specification: field names are lowerCamelCase, trace and
+//! span IDs are hex strings instead of base64, 64-bit integers are strings,
+//! enums are integers, `oneof` fields are inlined, e.g. an `AnyValue` is
+//! `{"stringValue": "a"}`, and fields with default values are left out.
+
+use crate::error::{UntraceError, UntraceResult};
+use base64::Engine as _;
+use opentelemetry::trace::{
+    Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId,
+};
+use opentelemetry::{Array, InstrumentationLibrary, KeyValue, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::EvictedQueue;
+use opentelemetry_sdk::Resource;
+use serde::Serialize;
+use serde_json::{Map, Value as Json};
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fields holding trace or span IDs, written as hex
+const ID_FIELDS: &[&str] = &["traceId", "spanId", "parentSpanId"];
+
+/// Fields of 64-bit integer types, written as strings
+const INT64_FIELDS: &[&str] = &[
+    "timeUnixNano",
+    "startTimeUnixNano",
+    "endTimeUnixNano",
+    "observedTimeUnixNano",
+    "intValue",
+    "asInt",
+    "count",
+    "zeroCount",
+    "bucketCounts",
+];
+
+/// Encode an OTLP export request as OTLP/JSON
+pub(crate) fn encode<M: Serialize>(message: &M) -> UntraceResult<Vec<u8>> {
+    // The generated types serialize with their protobuf field names, byte
+    // arrays and externally tagged `oneof`s, which is rewritten here
+    let value = match serde_json::to_value(message)? {
+        Json::Object(object) => Json::Object(encode_object(object)),
+        other => other,
+    };
+    Ok(serde_json::to_vec(&value)?)
+}
+
+fn encode_object(object: Map<String, Json>) -> Map<String, Json> {
+    let mut encoded = Map::new();
+    for (name, value) in object {
+        match value {
+            // Like protobuf JSON encoders, leave out fields with default values
+            value if is_default(&value) => {}
+            // A `oneof` serializes as `{"StringValue": ..}`; inline the variant
+            Json::Object(variant) if is_oneof(&variant) => {
+                let (variant, value) = variant.into_iter().next().expect("one entry");
+                let name = lower_camel_case(&variant);
+                let value = encode_field(&name, value);
+                encoded.insert(name, value);
+            }
+            value => {
+                let name = lower_camel_case(&name);
+                let value = encode_field(&name, value);
+                encoded.insert(name, value);
+            }
+        }
+    }
+    encoded
+}
+
+fn encode_field(name: &str, value: Json) -> Json {
+    match value {
+        Json::Array(bytes) if ID_FIELDS.contains(&name) => {
+            Json::String(bytes.iter().map(|byte| format!("{:02x}", byte_of(byte))).collect())
+        }
+        Json::Array(bytes) if name == "bytesValue" => {
+            let bytes: Vec<u8> = bytes.iter().map(byte_of).collect();
+            Json::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+        }
+        Json::Number(number) if INT64_FIELDS.contains(&name) => Json::String(number.to_string()),
+        Json::Object(object) => Json::Object(encode_object(object)),
+        Json::Array(items) => {
+            Json::Array(items.into_iter().map(|item| encode_field(name, item)).collect())
+        }
+        other => other,
+    }
+}
+
+fn is_default(value: &Json) -> bool {
+    match value {
+        Json::Null => true,
+        Json::Bool(value) => !value,
+        // Floating-point fields may be optional, so only integer zeros are left out
+        Json::Number(number) => number.as_u64() == Some(0) || number.as_i64() == Some(0),
+        Json::String(value) => value.is_empty(),
+        Json::Array(values) => values.is_empty(),
+        Json::Object(_) => false,
+    }
+}
+
+fn is_oneof(object: &Map<String, Json>) -> bool {
+    object.len() == 1
+        && object
+            .keys()
+            .next()
+            .is_some_and(|key| key.starts_with(|c: char| c.is_ascii_uppercase()))
+}
+
+fn byte_of(value: &Json) -> u8 {
+    value.as_u64().unwrap_or_default() as u8
+}
+
+/// Convert a protobuf field name or `oneof` variant to its JSON name
+fn lower_camel_case(name: &str) -> String {
+    let mut converted = String::with_capacity(name.len());
+    let mut upper = false;
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            converted.push(c.to_ascii_uppercase());
+            upper = false;
+        } else if i == 0 {
+            converted.push(c.to_ascii_lowercase());
+        } else {
+            converted.push(c);
+        }
+    }
+    converted
+}
+
+/// Convert a JSON field name back to its protobuf name
+fn snake_case(name: &str) -> String {
+    let mut converted = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            converted.push('_');
+            converted.push(c.to_ascii_lowercase());
+        } else {
+            converted.push(c);
+        }
+    }
+    converted
+}
+
+/// Decode an OTLP/JSON trace export request into spans
+///
+/// Fields may use their JSON or protobuf names, IDs are hex in either case
+/// and 64-bit integers may be numbers or strings, as parsers are required to
+/// accept. Spans and links keep the trace flags they were sent with. Each
+/// span keeps its resource and instrumentation scope. Attribute values
+/// OpenTelemetry spans can't hold, such as maps and mixed arrays, are kept as
+/// their OTLP/JSON text.
+pub(crate) fn decode_traces(bytes: &[u8]) -> UntraceResult<Vec<SpanData>> {
+    let request: Json = serde_json::from_slice(bytes)?;
+    if !request.is_object() {
+        return Err(invalid("request"));
+    }
+
+    let mut batch = Vec::new();
+    for resource_spans in list(&request, "resourceSpans") {
+        let resource = field(resource_spans, "resource").unwrap_or(&Json::Null);
+        let resource = Resource::from_schema_url(
+            attributes(resource),
+            string(resource_spans, "schemaUrl"),
+        );
+        for scope_spans in list(resource_spans, "scopeSpans") {
+            let scope = field(scope_spans, "scope").unwrap_or(&Json::Null);
+            let library = InstrumentationLibrary::new(
+                string(scope, "name"),
+                Some(string(scope, "version")).filter(|version| !version.is_empty()),
+                Some(string(scope_spans, "schemaUrl")).filter(|url| !url.is_empty()),
+                Some(attributes(scope)),
+            );
+            for span in list(scope_spans, "spans") {
+                batch.push(decode_span(span, &resource, &library)?);
+            }
+        }
+    }
+    Ok(batch)
+}
+
+fn decode_span(
+    span: &Json,
+    resource: &Resource,
+    library: &InstrumentationLibrary,
+) -> UntraceResult<SpanData> {
+    let trace_id = id::<16>(span, "traceId")?.ok_or_else(|| invalid("trace ID"))?;
+    let span_id = id::<8>(span, "spanId")?.ok_or_else(|| invalid("span ID"))?;
+    let parent_span_id = id::<8>(span, "parentSpanId")?
+        .map(SpanId::from_bytes)
+        .unwrap_or(SpanId::INVALID);
+    let trace_state = string(span, "traceState").parse().unwrap_or_default();
+
+    let mut events = EvictedQueue::new(u32::MAX);
+    for event in list(span, "events") {
+        events.extend([Event::new(
+            string(event, "name"),
+            time(event, "timeUnixNano")?,
+            attributes(event),
+            uint(event, "droppedAttributesCount")? as u32,
+        )]);
+    }
+
+    let mut links = EvictedQueue::new(u32::MAX);
+    for link in list(span, "links") {
+        let trace_id = id::<16>(link, "traceId")?.ok_or_else(|| invalid("link trace ID"))?;
+        let span_id = id::<8>(link, "spanId")?.ok_or_else(|| invalid("link span ID"))?;
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(trace_id),
+            SpanId::from_bytes(span_id),
+            trace_flags(link)?,
+            false,
+            string(link, "traceState").parse().unwrap_or_default(),
+        );
+        let mut decoded = Link::new(span_context, attributes(link));
+        decoded.dropped_attributes_count = uint(link, "droppedAttributesCount")? as u32;
+        links.extend([decoded]);
+    }
+
+    let status = field(span, "status").unwrap_or(&Json::Null);
+    let status = match enumeration(status, "code", "STATUS_CODE_")? {
+        1 => Status::Ok,
+        2 => Status::error(string(status, "message")),
+        _ => Status::Unset,
+    };
+    let span_kind = match enumeration(span, "kind", "SPAN_KIND_")? {
+        2 => SpanKind::Server,
+        3 => SpanKind::Client,
+        4 => SpanKind::Producer,
+        5 => SpanKind::Consumer,
+        _ => SpanKind::Internal,
+    };
+
+    Ok(SpanData {
+        span_context: SpanContext::new(
+            TraceId::from_bytes(trace_id),
+            SpanId::from_bytes(span_id),
+            trace_flags(span)?,
+            false,
+            trace_state,
+        ),
+        parent_span_id,
+        span_kind,
+        name: string(span, "name").into(),
+        start_time: time(span, "startTimeUnixNano")?,
+        end_time: time(span, "endTimeUnixNano")?,
+        attributes: attributes(span),
+        dropped_attributes_count: uint(span, "droppedAttributesCount")? as u32,
+        events,
+        links,
+        status,
+        resource: Cow::Owned(resource.clone()),
+        instrumentation_lib: library.clone(),
+    })
+}
+
+fn invalid(what: &str) -> UntraceError {
+    UntraceError::validation(format!("Invalid OTLP JSON {}", what))
+}
+
+/// Get a field by its JSON name, or by its protobuf name
+fn field<'a>(object: &'a Json, name: &str) -> Option<&'a Json> {
+    object
+        .get(name)
+        .or_else(|| object.get(snake_case(name)))
+        .filter(|value| !value.is_null())
+}
+
+fn list<'a>(object: &'a Json, name: &str) -> &'a [Json] {
+    field(object, name)
+        .and_then(Json::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn string(object: &Json, name: &str) -> String {
+    field(object, name)
+        .and_then(Json::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn uint(object: &Json, name: &str) -> UntraceResult<u64> {
+    match field(object, name) {
+        None => Ok(0),
+        Some(Json::Number(number)) => number.as_u64().ok_or_else(|| invalid(name)),
+        Some(Json::String(number)) => number.parse().map_err(|_| invalid(name)),
+        Some(_) => Err(invalid(name)),
+    }
+}
+
+fn time(object: &Json, name: &str) -> UntraceResult<SystemTime> {
+    Ok(UNIX_EPOCH + Duration::from_nanos(uint(object, name)?))
+}
+
+/// Get the W3C trace flags held in the low byte of `flags`
+///
+/// Senders that predate the field only export sampled spans, so spans
+/// without it are taken to be sampled.
+fn trace_flags(object: &Json) -> UntraceResult<TraceFlags> {
+    if field(object, "flags").is_none() {
+        return Ok(TraceFlags::SAMPLED);
+    }
+    Ok(TraceFlags::new((uint(object, "flags")? & 0xff) as u8))
+}
+
+/// Get an enum's number, also accepting its name, e.g. `SPAN_KIND_SERVER`
+fn enumeration(object: &Json, name: &str, prefix: &str) -> UntraceResult<i64> {
+    const NAMES: &[(&str, i64)] = &[
+        ("SPAN_KIND_INTERNAL", 1),
+        ("SPAN_KIND_SERVER", 2),
+        ("SPAN_KIND_CLIENT", 3),
+        ("SPAN_KIND_PRODUCER", 4),
+        ("SPAN_KIND_CONSUMER", 5),
+        ("STATUS_CODE_OK", 1),
+        ("STATUS_CODE_ERROR", 2),
+    ];
+
+    match field(object, name) {
+        None => Ok(0),
+        Some(Json::Number(number)) => number.as_i64().ok_or_else(|| invalid(name)),
+        Some(Json::String(value)) => Ok(NAMES
+            .iter()
+            .find(|(known, _)| known.starts_with(prefix) && known == value)
+            .map(|(_, number)| *number)
+            .unwrap_or_default()),
+        Some(_) => Err(invalid(name)),
+    }
+}
+
+/// Decode a hex ID, `None` if it is missing or empty
+fn id<const N: usize>(object: &Json, name: &str) -> UntraceResult<Option<[u8; N]>> {
+    let hex = match field(object, name) {
+        None => return Ok(None),
+        Some(Json::String(hex)) if hex.is_empty() => return Ok(None),
+        Some(Json::String(hex)) => hex,
+        Some(_) => return Err(invalid(name)),
+    };
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return Err(invalid(name));
+    }
+
+    let mut bytes = [0; N];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid(name))?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid(name))?;
+    }
+    Ok(Some(bytes))
+}
+
+fn attributes(object: &Json) -> Vec<KeyValue> {
+    list(object, "attributes")
+        .iter()
+        .filter_map(|kv| {
+            let value = any_value(field(kv, "value")?)?;
+            Some(KeyValue::new(string(kv, "key"), value))
+        })
+        .collect()
+}
+
+fn any_value(value: &Json) -> Option<Value> {
+    if let Some(Json::String(value)) = field(value, "stringValue") {
+        return Some(Value::from(value.clone()));
+    }
+    if let Some(Json::Bool(value)) = field(value, "boolValue") {
+        return Some(Value::Bool(*value));
+    }
+    if let Some(int) = field(value, "intValue") {
+        return match int {
+            Json::Number(number) => number.as_i64().map(Value::I64),
+            Json::String(number) => number.parse().ok().map(Value::I64),
+            _ => None,
+        };
+    }
+    if let Some(Json::Number(number)) = field(value, "doubleValue") {
+        return number.as_f64().map(Value::F64);
+    }
+    if let Some(array) = field(value, "arrayValue") {
+        if let Some(array) = homogeneous_array(list(array, "values")) {
+            return Some(Value::Array(array));
+        }
+    }
+
+    // Maps, bytes and mixed arrays
+    value
+        .as_object()
+        .filter(|object| !object.is_empty())
+        .map(|_| Value::from(value.to_string()))
+}
+
+/// Decode an array whose values all have the same scalar type
+fn homogeneous_array(values: &[Json]) -> Option<Array> {
+    let values: Vec<Value> = values.iter().map(any_value).collect::<Option<_>>()?;
+    let Some(first) = values.first() else {
+        return Some(Array::String(Vec::new()));
+    };
+
+    macro_rules! collect {
+        ($variant:ident, $array:ident) => {
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()
+                .map(Array::$array)
+        };
+    }
+    match first {
+        Value::Bool(_) => collect!(Bool, Bool),
+        Value::I64(_) => collect!(I64, I64),
+        Value::F64(_) => collect!(F64, F64),
+        Value::String(_) => collect!(String, String),
+        Value::Array(_) => None,
+    }
+}
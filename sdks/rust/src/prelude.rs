@@ -0,0 +1,41 @@
+//! Commonly used types and traits
+//!
+//! ```rust
+//! use untrace::prelude::*;
+//!
+//! fn trace_chat(untrace: &Untrace) -> UntraceResult<()> {
+//!     let options = LLMSpanOptions {
+//!         provider: "openai".to_string(),
+//!         model: "gpt-4".to_string(),
+//!         operation: LLMOperationType::Chat,
+//!         ..Default::default()
+//!     };
+//!
+//!     let mut span = untrace.tracer().start_llm_span("llm-chat", options);
+//!     span.set_attribute(KeyValue::new("llm.request_id", "req-123"));
+//!     span.end();
+//!
+//!     let workflow = untrace.context().start_workflow(
+//!         "my-workflow".to_string(),
+//!         untrace.context().generate_run_id(),
+//!         WorkflowOptions::default(),
+//!     )?;
+//!     untrace.tracer().start_workflow_span(&workflow).end();
+//!     untrace.context().end_current_workflow()
+//! }
+//! ```
+
+pub use crate::{
+    config::Config,
+    error::{UntraceError, UntraceResult},
+    types::{
+        Cost, LLMOperationType, LLMSpanOptions, SpanExt, SpanOptions, TokenUsage, Workflow,
+        WorkflowOptions,
+    },
+    untrace::Untrace,
+};
+
+pub use opentelemetry::{
+    trace::{Span, SpanKind, Status, Tracer},
+    KeyValue,
+};
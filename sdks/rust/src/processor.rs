@@ -0,0 +1,827 @@
+//! Span processors for the Untrace SDK
+
+use crate::attributes::{helpers, llm, sdk, workflow};
+use opentelemetry::trace::{SpanContext, SpanId, Status, TraceError, TraceId, TraceResult};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::{Span, SpanProcessor};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::warn;
+
+/// Shared setting for flushing after a number of spans, or after specific spans
+///
+/// A value of 0 (the default) disables count-based flushing.
+#[derive(Debug, Clone, Default)]
+pub struct FlushPolicy {
+    every: Arc<AtomicUsize>,
+    pending: Arc<AtomicUsize>,
+    span_names: Arc<HashSet<String>>,
+}
+
+impl FlushPolicy {
+    /// Create a policy with count-based flushing disabled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also flush whenever a span with one of these names ends
+    pub fn with_span_names(mut self, span_names: impl IntoIterator<Item = String>) -> Self {
+        self.span_names = Arc::new(span_names.into_iter().collect());
+        self
+    }
+
+    /// Flush once `n` spans have ended since the last flush, or never if `n` is 0
+    pub fn set_every(&self, n: usize) {
+        self.every.store(n, Ordering::Relaxed);
+        self.pending.store(0, Ordering::Relaxed);
+    }
+
+    /// Get the number of spans between flushes
+    pub fn every(&self) -> usize {
+        self.every.load(Ordering::Relaxed)
+    }
+
+    /// Count an ended span and report whether a flush is due
+    fn record_span(&self, name: &str) -> bool {
+        if self.span_names.contains(name) {
+            return true;
+        }
+
+        let every = self.every();
+        if every == 0 {
+            return false;
+        }
+
+        let count = self.pending.fetch_add(1, Ordering::AcqRel) + 1;
+        count >= every
+            && self
+                .pending
+                .compare_exchange(count, 0, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+    }
+}
+
+/// Span processor that flushes the wrapped processor every N ended spans
+///
+/// It also flushes when a span named in the [`FlushPolicy`] ends. This is in
+/// addition to the wrapped processor's own batch size and interval triggers.
+///
+/// Flushes run on a background thread, so ending a span never waits for the
+/// export. Flushes requested while one is already queued are merged into it.
+#[derive(Debug)]
+pub struct CountFlushProcessor<P> {
+    inner: Arc<P>,
+    policy: FlushPolicy,
+    flusher: OnceLock<Option<Flusher>>,
+}
+
+/// Background thread flushing a processor on request
+#[derive(Debug)]
+struct Flusher {
+    requests: SyncSender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl<P: SpanProcessor + 'static> CountFlushProcessor<P> {
+    /// Wrap a span processor
+    pub fn new(inner: P, policy: FlushPolicy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            policy,
+            flusher: OnceLock::new(),
+        }
+    }
+
+    /// Ask the background thread to flush, starting it on first use
+    fn request_flush(&self) {
+        let flusher = self.flusher.get_or_init(|| {
+            let (requests, pending) = mpsc::sync_channel(1);
+            let inner = Arc::clone(&self.inner);
+            let thread = thread::Builder::new()
+                .name("untrace-flush".to_string())
+                .spawn(move || {
+                    while pending.recv().is_ok() {
+                        if let Err(err) = inner.force_flush() {
+                            warn!("Triggered flush failed: {}", err);
+                        }
+                    }
+                });
+            match thread {
+                Ok(thread) => Some(Flusher { requests, thread }),
+                Err(err) => {
+                    warn!("Failed to start flush thread, triggered flushes are disabled: {}", err);
+                    None
+                }
+            }
+        });
+
+        // A full channel means a flush is already queued
+        if let Some(flusher) = flusher {
+            let _ = flusher.requests.try_send(());
+        }
+    }
+}
+
+impl<P: SpanProcessor + 'static> SpanProcessor for CountFlushProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let flush = self.policy.record_span(&span.name);
+        self.inner.on_end(span);
+
+        if flush {
+            self.request_flush();
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        // Let a queued flush finish, and release the thread's handle on the processor
+        if let Some(Some(flusher)) = self.flusher.take() {
+            drop(flusher.requests);
+            let _ = flusher.thread.join();
+        }
+
+        match Arc::get_mut(&mut self.inner) {
+            Some(inner) => inner.shutdown(),
+            None => Err(TraceError::Other("Span processor is still in use".into())),
+        }
+    }
+}
+
+/// Span processor that records how many events each span dropped
+///
+/// Spans that hit the event limit get an `untrace.dropped_events` attribute
+/// before being passed on.
+#[derive(Debug)]
+pub struct DroppedEventsProcessor<P> {
+    inner: P,
+}
+
+impl<P: SpanProcessor> DroppedEventsProcessor<P> {
+    /// Wrap a span processor
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for DroppedEventsProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let dropped = span.events.dropped_count();
+        if dropped > 0 {
+            span.attributes
+                .push(KeyValue::new(sdk::DROPPED_EVENTS, dropped as i64));
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Most traces whose spans are held while waiting for their root span
+const MAX_PENDING_TRACES: usize = 1024;
+
+/// Most traces whose decision is remembered for spans ending after their root
+const MAX_DECIDED_TRACES: usize = 4096;
+
+/// Span processor that makes deferred sampling decisions per trace
+///
+/// Spans that [`crate::sampling::UntraceSampler`] recorded without sampling
+/// are held by trace until the trace's root span ends. The trace is then
+/// passed on, marked as sampled, if any of its spans took at least the
+/// threshold, measured as the longer of the span's own timing and its
+/// `llm.duration_ms`, and discarded otherwise. Spans ending after their
+/// root follow the trace's decision. Sampled spans are always passed on,
+/// and without a threshold every span is passed on.
+///
+/// When more than 1024 traces are waiting for their root, the oldest is
+/// decided on the spans that have ended so far.
+#[derive(Debug)]
+pub struct SlowSpanProcessor<P> {
+    inner: P,
+    threshold: Option<Duration>,
+    counts: SpanCounts,
+    traces: Mutex<DeferredTraces>,
+}
+
+/// Deferred traces, in the order they were first seen
+#[derive(Debug, Default)]
+struct DeferredTraces {
+    pending: HashMap<TraceId, PendingTrace>,
+    pending_order: VecDeque<TraceId>,
+    decided: HashMap<TraceId, bool>,
+    decided_order: VecDeque<TraceId>,
+}
+
+#[derive(Debug, Default)]
+struct PendingTrace {
+    spans: Vec<SpanData>,
+    slow: bool,
+}
+
+impl DeferredTraces {
+    /// Hold a span, returning the spans of any trace decided as a result
+    fn add(&mut self, span: SpanData, slow: bool) -> Option<(Vec<SpanData>, bool)> {
+        let trace_id = span.span_context.trace_id();
+        if let Some(keep) = self.decided.get(&trace_id) {
+            return Some((vec![span], *keep));
+        }
+
+        let is_root = span.parent_span_id == SpanId::INVALID;
+        let trace = self.pending.entry(trace_id).or_insert_with(|| {
+            self.pending_order.push_back(trace_id);
+            PendingTrace::default()
+        });
+        trace.spans.push(span);
+        trace.slow |= slow;
+
+        if is_root {
+            return self.decide(trace_id);
+        }
+        if self.pending.len() > MAX_PENDING_TRACES {
+            let oldest = self.pending_order.front().copied()?;
+            return self.decide(oldest);
+        }
+        None
+    }
+
+    /// Decide a pending trace, remembering the decision for its later spans
+    fn decide(&mut self, trace_id: TraceId) -> Option<(Vec<SpanData>, bool)> {
+        let trace = self.pending.remove(&trace_id)?;
+        self.pending_order.retain(|id| *id != trace_id);
+
+        self.decided.insert(trace_id, trace.slow);
+        self.decided_order.push_back(trace_id);
+        if self.decided_order.len() > MAX_DECIDED_TRACES {
+            if let Some(oldest) = self.decided_order.pop_front() {
+                self.decided.remove(&oldest);
+            }
+        }
+        Some((trace.spans, trace.slow))
+    }
+
+    /// Decide every pending trace
+    fn drain(&mut self) -> Vec<(Vec<SpanData>, bool)> {
+        let pending: Vec<TraceId> = self.pending_order.drain(..).collect();
+        pending
+            .into_iter()
+            .filter_map(|trace_id| self.decide(trace_id))
+            .collect()
+    }
+}
+
+impl<P: SpanProcessor> SlowSpanProcessor<P> {
+    /// Wrap a span processor, recording discarded spans in `counts`
+    pub fn new(inner: P, threshold: Option<Duration>, counts: SpanCounts) -> Self {
+        Self {
+            inner,
+            threshold,
+            counts,
+            traces: Mutex::default(),
+        }
+    }
+
+    fn is_slow(&self, span: &SpanData, threshold: Duration) -> bool {
+        let timed = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+        let recorded = span
+            .attributes
+            .iter()
+            .find(|kv| helpers::matches_key(kv.key.as_str(), llm::DURATION_MS))
+            .and_then(|kv| match kv.value {
+                Value::I64(ms) => u64::try_from(ms).ok(),
+                Value::F64(ms) if ms >= 0.0 => Some(ms as u64),
+                _ => None,
+            })
+            .map(Duration::from_millis)
+            .unwrap_or_default();
+
+        timed.max(recorded) >= threshold
+    }
+
+    fn release(&self, spans: Vec<SpanData>, keep: bool) {
+        for mut span in spans {
+            if !keep {
+                self.counts.record_discarded();
+                continue;
+            }
+            let cx = &span.span_context;
+            span.span_context = SpanContext::new(
+                cx.trace_id(),
+                cx.span_id(),
+                cx.trace_flags().with_sampled(true),
+                cx.is_remote(),
+                cx.trace_state().clone(),
+            );
+            self.inner.on_end(span);
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for SlowSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let Some(threshold) = self.threshold else {
+            return self.inner.on_end(span);
+        };
+        if span.span_context.is_sampled() {
+            return self.inner.on_end(span);
+        }
+
+        let slow = self.is_slow(&span, threshold);
+        let decided = self
+            .traces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add(span, slow);
+        if let Some((spans, keep)) = decided {
+            self.release(spans, keep);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        let pending = self
+            .traces
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain();
+        for (spans, keep) in pending {
+            self.release(spans, keep);
+        }
+        self.inner.shutdown()
+    }
+}
+
+/// Span processor that drops spans shorter than a minimum duration
+///
+/// Spans with an error status are always passed on, however fast they
+/// failed. Without a minimum every span is passed on.
+#[derive(Debug)]
+pub struct MinDurationProcessor<P> {
+    inner: P,
+    min_duration: Option<Duration>,
+    counts: SpanCounts,
+}
+
+impl<P: SpanProcessor> MinDurationProcessor<P> {
+    /// Wrap a span processor, recording discarded spans in `counts`
+    pub fn new(inner: P, min_duration: Option<Duration>, counts: SpanCounts) -> Self {
+        Self {
+            inner,
+            min_duration,
+            counts,
+        }
+    }
+
+    fn is_discarded(&self, span: &SpanData) -> bool {
+        let Some(min_duration) = self.min_duration else {
+            return false;
+        };
+        if matches!(span.status, Status::Error { .. }) {
+            return false;
+        }
+
+        span.end_time
+            .duration_since(span.start_time)
+            .is_ok_and(|duration| duration < min_duration)
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for MinDurationProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.is_discarded(&span) {
+            self.counts.record_discarded();
+            return;
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// Span processor shared between the tracer provider and its instance
+///
+/// Lets the instance pass spans it didn't record, such as ingested OTLP
+/// spans, to the same export pipeline as its own, and shut that pipeline
+/// down while the tracer provider is still alive. Spans ending after the
+/// shutdown are dropped.
+#[derive(Debug)]
+pub(crate) struct SharedProcessor<P> {
+    inner: Arc<RwLock<P>>,
+    shut_down: Arc<AtomicBool>,
+}
+
+impl<P> Clone for SharedProcessor<P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            shut_down: Arc::clone(&self.shut_down),
+        }
+    }
+}
+
+impl<P: SpanProcessor> SharedProcessor<P> {
+    pub(crate) fn new(inner: P) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+            shut_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Shut down the wrapped processor, once for all clones
+    fn shutdown_once(&self) -> TraceResult<()> {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        match self.inner.write() {
+            Ok(mut inner) => inner.shutdown(),
+            Err(err) => Err(TraceError::Other(err.to_string().into())),
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for SharedProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(inner) = self.inner.read() {
+            inner.on_start(span, cx);
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(inner) = self.inner.read() {
+            inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        match self.inner.read() {
+            Ok(inner) => inner.force_flush(),
+            Err(err) => Err(TraceError::Other(err.to_string().into())),
+        }
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.shutdown_once()
+    }
+}
+
+/// Destination for spans recorded outside the instance's tracer
+pub(crate) trait SpanSink: fmt::Debug + Send + Sync {
+    /// Pass an ended span to the export pipeline
+    fn send(&self, span: SpanData);
+
+    /// Export what is queued and stop the export pipeline
+    fn shutdown(&self) -> TraceResult<()>;
+}
+
+impl<P: SpanProcessor> SpanSink for SharedProcessor<P> {
+    fn send(&self, span: SpanData) {
+        self.on_end(span);
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        self.shutdown_once()
+    }
+}
+
+/// Running totals of ended and exported spans, shared between clones
+#[derive(Debug, Clone, Default)]
+pub struct SpanCounts {
+    ended: Arc<AtomicU64>,
+    exported: Arc<AtomicU64>,
+    rolled_up: Arc<AtomicU64>,
+    discarded: Arc<AtomicU64>,
+}
+
+impl SpanCounts {
+    /// Create zeroed counts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the number of sampled spans that have ended
+    pub fn ended(&self) -> u64 {
+        self.ended.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of spans successfully exported
+    pub fn exported(&self) -> u64 {
+        self.exported.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of spans folded into their workflow's summary span
+    pub fn rolled_up(&self) -> u64 {
+        self.rolled_up.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of spans discarded at span end by the sampling strategy
+    pub fn discarded(&self) -> u64 {
+        self.discarded.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of ended spans that were neither exported, rolled up
+    /// nor discarded
+    pub fn unexported(&self) -> u64 {
+        self.ended()
+            .saturating_sub(self.exported())
+            .saturating_sub(self.rolled_up())
+            .saturating_sub(self.discarded())
+    }
+
+    pub(crate) fn record_ended(&self) {
+        self.ended.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_exported(&self, count: u64) {
+        self.exported.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rolled_up(&self) {
+        self.rolled_up.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_discarded(&self) {
+        self.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Span processor that counts ended spans
+///
+/// Pair with [`crate::exporter::CountingExporter`] sharing the same
+/// [`SpanCounts`] to tell how many spans were dropped.
+#[derive(Debug)]
+pub struct SpanCountProcessor {
+    counts: SpanCounts,
+}
+
+impl SpanCountProcessor {
+    /// Create a processor that records into the given counts
+    pub fn new(counts: SpanCounts) -> Self {
+        Self { counts }
+    }
+}
+
+impl SpanProcessor for SpanCountProcessor {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, _span: SpanData) {
+        self.counts.record_ended();
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// Totals for the LLM calls made during one workflow run
+#[derive(Debug, Default)]
+struct WorkflowTotals {
+    llm_calls: i64,
+    operations: BTreeMap<String, i64>,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    total_tokens: i64,
+    cost_total: f64,
+}
+
+impl WorkflowTotals {
+    /// Add an LLM span's tokens and cost
+    fn add(&mut self, span: &SpanData) {
+        let int = |name: &str| match find_attribute(span, name) {
+            Some((_, Value::I64(value))) => Some(*value),
+            _ => None,
+        };
+        let prompt_tokens = int(llm::PROMPT_TOKENS).unwrap_or(0);
+        let completion_tokens = int(llm::COMPLETION_TOKENS).unwrap_or(0);
+
+        self.llm_calls += 1;
+        self.prompt_tokens += prompt_tokens;
+        self.completion_tokens += completion_tokens;
+        self.total_tokens += int(llm::TOTAL_TOKENS).unwrap_or(prompt_tokens + completion_tokens);
+        if let Some((_, Value::F64(cost))) = find_attribute(span, llm::COST_TOTAL) {
+            self.cost_total += cost;
+        }
+        if let Some((_, operation)) = find_attribute(span, llm::OPERATION) {
+            *self.operations.entry(operation.to_string()).or_default() += 1;
+        }
+    }
+
+    /// Build the summary attributes, namespaced with `prefix` if one is in use
+    fn into_attributes(self, prefix: &str) -> Vec<KeyValue> {
+        let key = |name: &str| format!("{}{}", prefix, name);
+
+        let mut attributes = vec![
+            helpers::int(&key(workflow::LLM_CALLS), self.llm_calls),
+            helpers::int(&key(workflow::PROMPT_TOKENS), self.prompt_tokens),
+            helpers::int(&key(workflow::COMPLETION_TOKENS), self.completion_tokens),
+            helpers::int(&key(workflow::TOTAL_TOKENS), self.total_tokens),
+            helpers::float(&key(workflow::COST_TOTAL), self.cost_total),
+        ];
+        attributes.extend(self.operations.into_iter().map(|(operation, count)| {
+            helpers::int(
+                &format!("{}.{}", key(workflow::OPERATIONS), operation),
+                count,
+            )
+        }));
+        attributes
+    }
+}
+
+/// Find an attribute by its standard key, with or without an attribute prefix
+fn find_attribute<'a>(span: &'a SpanData, name: &str) -> Option<(&'a str, &'a Value)> {
+    span.attributes
+        .iter()
+        .find(|kv| helpers::matches_key(kv.key.as_str(), name))
+        .map(|kv| (kv.key.as_str(), &kv.value))
+}
+
+/// Most workflow runs tracked at once by [`WorkflowSummaryProcessor`]
+const MAX_OPEN_WORKFLOW_RUNS: usize = 1024;
+
+/// Context value marking a span started by
+/// [`crate::UntraceTracer::start_workflow_span`], carrying its run ID
+///
+/// Lets [`WorkflowSummaryProcessor`] track workflow spans as they start
+/// without copying each span's data.
+pub(crate) struct WorkflowSpanStart(pub(crate) String);
+
+/// Span processor that rolls LLM spans up into their workflow's span
+///
+/// LLM spans tagged with the `workflow.run_id` of a workflow span that is
+/// still open are not passed on. Workflow spans are those started with
+/// [`crate::UntraceTracer::start_workflow_span`]. Their call count, per-operation counts,
+/// tokens and cost are added to the workflow span when it ends, as
+/// `workflow.llm_calls`, `workflow.operations.*`, `workflow.*_tokens` and
+/// `workflow.cost_total`. All other spans, including LLM spans that end
+/// after their workflow span, pass through unchanged.
+///
+/// At most 1024 workflow runs are tracked. Starting another one stops
+/// tracking the oldest, whose remaining LLM spans then pass through and
+/// whose workflow span is exported without a summary.
+#[derive(Debug)]
+pub struct WorkflowSummaryProcessor<P> {
+    inner: P,
+    counts: SpanCounts,
+    runs: Mutex<WorkflowRuns>,
+}
+
+/// Workflow runs with an open workflow span, in the order they started
+#[derive(Debug, Default)]
+struct WorkflowRuns {
+    open: HashMap<String, OpenWorkflowRun>,
+    order: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+struct OpenWorkflowRun {
+    /// Number of open workflow spans with this run ID
+    spans: usize,
+    totals: WorkflowTotals,
+}
+
+impl WorkflowRuns {
+    fn start(&mut self, run_id: String) {
+        if let Some(run) = self.open.get_mut(&run_id) {
+            run.spans += 1;
+            return;
+        }
+
+        if self.open.len() >= MAX_OPEN_WORKFLOW_RUNS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.open.remove(&oldest);
+            }
+        }
+        self.order.push_back(run_id.clone());
+        self.open.insert(
+            run_id,
+            OpenWorkflowRun {
+                spans: 1,
+                totals: WorkflowTotals::default(),
+            },
+        );
+    }
+
+    /// Take a run's totals when its last open workflow span ends
+    fn end(&mut self, run_id: &str) -> Option<WorkflowTotals> {
+        let run = self.open.get_mut(run_id)?;
+        run.spans -= 1;
+        if run.spans > 0 {
+            return Some(std::mem::take(&mut run.totals));
+        }
+
+        self.order.retain(|id| id != run_id);
+        self.open.remove(run_id).map(|run| run.totals)
+    }
+}
+
+impl<P: SpanProcessor> WorkflowSummaryProcessor<P> {
+    /// Wrap a span processor, recording rolled-up spans into the given counts
+    pub fn new(inner: P, counts: SpanCounts) -> Self {
+        Self {
+            inner,
+            counts,
+            runs: Mutex::default(),
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for WorkflowSummaryProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        if let Some(WorkflowSpanStart(run_id)) = cx.get::<WorkflowSpanStart>() {
+            if let Ok(mut runs) = self.runs.lock() {
+                runs.start(run_id.clone());
+            }
+        }
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        let Some((run_key, run_id)) = find_attribute(&span, workflow::RUN_ID) else {
+            self.inner.on_end(span);
+            return;
+        };
+        let prefix = run_key
+            .strip_suffix(workflow::RUN_ID)
+            .unwrap_or_default()
+            .to_string();
+        let run_id = run_id.to_string();
+        let is_workflow = find_attribute(&span, workflow::NAME).is_some();
+        let is_llm = find_attribute(&span, llm::PROVIDER).is_some();
+
+        if is_workflow || is_llm {
+            if let Ok(mut runs) = self.runs.lock() {
+                if is_workflow {
+                    if let Some(totals) = runs.end(&run_id) {
+                        span.attributes.extend(totals.into_attributes(&prefix));
+                    }
+                } else if let Some(run) = runs.open.get_mut(&run_id) {
+                    run.totals.add(&span);
+                    self.counts.record_rolled_up();
+                    return;
+                }
+            }
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,227 @@
+//! Pull-based Prometheus metrics for the Untrace SDK
+
+use opentelemetry::metrics::Result;
+use opentelemetry::Value;
+use opentelemetry_sdk::metrics::data::{
+    Gauge, Histogram, Metric, ResourceMetrics, Sum, Temporality,
+};
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, MetricReader, TemporalitySelector};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind, ManualReader, Pipeline};
+use opentelemetry_sdk::{AttributeSet, Resource};
+use std::fmt::Write;
+use std::sync::{Arc, Weak};
+
+/// Metric reader that renders collected metrics in the Prometheus text format
+///
+/// Nothing is pushed; metrics are collected each time [`PrometheusExporter::metrics_text`]
+/// is called, e.g. from a `/metrics` scrape handler.
+#[derive(Debug, Clone)]
+pub struct PrometheusExporter {
+    reader: Arc<ManualReader>,
+}
+
+impl PrometheusExporter {
+    /// Create a new Prometheus exporter
+    pub fn new() -> Self {
+        Self {
+            reader: Arc::new(ManualReader::builder().build()),
+        }
+    }
+
+    /// Collect the current metrics and render them in the Prometheus text format
+    pub fn metrics_text(&self) -> Result<String> {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        self.reader.collect(&mut metrics)?;
+
+        let mut output = String::new();
+        for scope in &metrics.scope_metrics {
+            for metric in &scope.metrics {
+                write_metric(&mut output, metric);
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemporalitySelector for PrometheusExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.reader.temporality(kind)
+    }
+}
+
+impl AggregationSelector for PrometheusExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.reader.aggregation(kind)
+    }
+}
+
+impl MetricReader for PrometheusExporter {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> Result<()> {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> Result<()> {
+        self.reader.force_flush()
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.reader.shutdown()
+    }
+}
+
+/// Convert an OpenTelemetry name into a valid Prometheus name
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render a set of attributes (plus optional extra label) as Prometheus labels
+fn format_labels(attributes: &AttributeSet, extra: Option<(&str, String)>) -> String {
+    let mut labels: Vec<String> = attributes
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}=\"{}\"",
+                sanitize_name(key.as_str()),
+                escape_label_value(&value_to_string(value))
+            )
+        })
+        .collect();
+
+    if let Some((key, value)) = extra {
+        labels.push(format!("{}=\"{}\"", key, value));
+    }
+
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    value.as_str().into_owned()
+}
+
+/// Append a single metric in the Prometheus text format
+fn write_metric(output: &mut String, metric: &Metric) {
+    let name = sanitize_name(&metric.name);
+    let data = metric.data.as_any();
+
+    if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+        write_sum(output, &name, &metric.description, sum);
+    } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+        write_sum(output, &name, &metric.description, sum);
+    } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+        write_sum(output, &name, &metric.description, sum);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+        write_gauge(output, &name, &metric.description, gauge);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+        write_gauge(output, &name, &metric.description, gauge);
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+        write_gauge(output, &name, &metric.description, gauge);
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+        write_histogram(output, &name, &metric.description, histogram);
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+        write_histogram(output, &name, &metric.description, histogram);
+    }
+}
+
+fn write_header(output: &mut String, name: &str, description: &str, kind: &str) {
+    if !description.is_empty() {
+        let _ = writeln!(output, "# HELP {} {}", name, description);
+    }
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn write_sum<T: std::fmt::Display>(
+    output: &mut String,
+    name: &str,
+    description: &str,
+    sum: &Sum<T>,
+) {
+    let (name, kind) = if sum.is_monotonic {
+        (format!("{}_total", name), "counter")
+    } else {
+        (name.to_string(), "gauge")
+    };
+    write_header(output, &name, description, kind);
+    for point in &sum.data_points {
+        let _ = writeln!(
+            output,
+            "{}{} {}",
+            name,
+            format_labels(&point.attributes, None),
+            point.value
+        );
+    }
+}
+
+fn write_gauge<T: std::fmt::Display>(
+    output: &mut String,
+    name: &str,
+    description: &str,
+    gauge: &Gauge<T>,
+) {
+    write_header(output, name, description, "gauge");
+    for point in &gauge.data_points {
+        let _ = writeln!(
+            output,
+            "{}{} {}",
+            name,
+            format_labels(&point.attributes, None),
+            point.value
+        );
+    }
+}
+
+fn write_histogram<T: std::fmt::Display>(
+    output: &mut String,
+    name: &str,
+    description: &str,
+    histogram: &Histogram<T>,
+) {
+    write_header(output, name, description, "histogram");
+    for point in &histogram.data_points {
+        let mut cumulative = 0;
+        for (i, count) in point.bucket_counts.iter().enumerate() {
+            cumulative += count;
+            let bound = point
+                .bounds
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(
+                output,
+                "{}_bucket{} {}",
+                name,
+                format_labels(&point.attributes, Some(("le", bound))),
+                cumulative
+            );
+        }
+        let labels = format_labels(&point.attributes, None);
+        let _ = writeln!(output, "{}_sum{} {}", name, labels, point.sum);
+        let _ = writeln!(output, "{}_count{} {}", name, labels, point.count);
+    }
+}
@@ -1,7 +1,7 @@
 //! Provider instrumentation for the Untrace SDK
 
 use crate::error::{UntraceError, UntraceResult};
-use crate::types::{InstrumentationConfig, Provider};
+use crate::types::{LLMSpanOptions, Provider};
 use std::collections::HashMap;
 
 /// Provider registry for managing instrumentations
@@ -19,13 +19,22 @@ impl ProviderRegistry {
     }
 
     /// Register a provider
-    pub fn register(&mut self, provider: Provider) {
-        self.providers.insert(provider.name.clone(), provider);
+    ///
+    /// The name is normalized with [`normalize_provider_name`]. If that
+    /// changes it, the name as given is kept as the display name unless one
+    /// is set already.
+    pub fn register(&mut self, mut provider: Provider) {
+        let name = normalize_provider_name(&provider.name);
+        if name != provider.name && provider.display_name.is_none() {
+            provider.display_name = Some(provider.name.trim().to_string());
+        }
+        provider.name = name.clone();
+        self.providers.insert(name, provider);
     }
 
-    /// Get a provider by name
+    /// Get a provider by name, in any casing
     pub fn get(&self, name: &str) -> Option<&Provider> {
-        self.providers.get(name)
+        self.providers.get(&normalize_provider_name(name))
     }
 
     /// Get all providers
@@ -38,29 +47,48 @@ impl ProviderRegistry {
         self.providers.values().filter(|p| p.enabled).collect()
     }
 
+    /// Iterate over all providers
+    pub fn iter(&self) -> impl Iterator<Item = &Provider> {
+        self.providers.values()
+    }
+
+    /// Iterate over providers that have the given capability
+    pub fn with_capability<'a>(
+        &'a self,
+        capability: &'a str,
+    ) -> impl Iterator<Item = &'a Provider> {
+        self.iter().filter(move |p| p.has_capability(capability))
+    }
+
     /// Enable a provider
     pub fn enable(&mut self, name: &str) -> UntraceResult<()> {
-        if let Some(provider) = self.providers.get_mut(name) {
+        if let Some(provider) = self.providers.get_mut(&normalize_provider_name(name)) {
             provider.enabled = true;
             Ok(())
         } else {
-            Err(UntraceError::instrumentation(format!("Provider '{}' not found", name)))
+            Err(UntraceError::instrumentation(format!(
+                "Provider '{}' not found",
+                name
+            )))
         }
     }
 
     /// Disable a provider
     pub fn disable(&mut self, name: &str) -> UntraceResult<()> {
-        if let Some(provider) = self.providers.get_mut(name) {
+        if let Some(provider) = self.providers.get_mut(&normalize_provider_name(name)) {
             provider.enabled = false;
             Ok(())
         } else {
-            Err(UntraceError::instrumentation(format!("Provider '{}' not found", name)))
+            Err(UntraceError::instrumentation(format!(
+                "Provider '{}' not found",
+                name
+            )))
         }
     }
 
     /// Check if a provider is enabled
     pub fn is_enabled(&self, name: &str) -> bool {
-        self.providers.get(name).map_or(false, |p| p.enabled)
+        self.get(name).is_some_and(|p| p.enabled)
     }
 }
 
@@ -70,38 +98,105 @@ impl Default for ProviderRegistry {
     }
 }
 
+/// Normalize a provider name, e.g. `OpenAI` to `openai`
+///
+/// Names are trimmed and lowercased so spans from differently cased
+/// sources are grouped together.
+pub fn normalize_provider_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Version recorded for providers without a detected SDK version
+pub const DEFAULT_PROVIDER_VERSION: &str = "1.0.0";
+
+/// Versions of the provider SDK crates compiled in, read from the lockfile
+/// at build time
+const SDK_VERSIONS: &[(&str, Option<&str>)] = &[
+    #[cfg(feature = "async-openai")]
+    ("openai", option_env!("UNTRACE_ASYNC_OPENAI_VERSION")),
+];
+
+/// Get the version of a provider's SDK crate
+///
+/// Only known for providers whose SDK is enabled through a feature, e.g.
+/// `openai` with the `async-openai` feature. It is recorded on that
+/// provider's spans as `llm.provider.sdk_version`.
+pub fn detected_sdk_version(provider: &str) -> Option<&'static str> {
+    SDK_VERSIONS
+        .iter()
+        .find(|(name, _)| *name == provider)
+        .and_then(|(_, version)| *version)
+}
+
+fn version(provider: &str) -> String {
+    detected_sdk_version(provider)
+        .unwrap_or(DEFAULT_PROVIDER_VERSION)
+        .to_string()
+}
+
+fn capabilities(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|t| t.to_string()).collect()
+}
+
 /// Get default providers
 pub fn get_default_providers() -> Vec<Provider> {
     vec![
         Provider {
             name: "openai".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("OpenAI".to_string()),
+            version: version("openai"),
             enabled: true,
+            capabilities: capabilities(&[
+                "chat",
+                "completion",
+                "embeddings",
+                "images",
+                "audio",
+                "moderation",
+                "tools",
+            ]),
+        },
+        Provider {
+            name: azure::PROVIDER_NAME.to_string(),
+            display_name: Some("Azure OpenAI".to_string()),
+            version: version(azure::PROVIDER_NAME),
+            enabled: true,
+            capabilities: capabilities(&["chat", "completion", "embeddings", "images", "tools"]),
         },
         Provider {
             name: "anthropic".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("Anthropic".to_string()),
+            version: version("anthropic"),
             enabled: true,
+            capabilities: capabilities(&["chat", "tools"]),
         },
         Provider {
             name: "google".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("Google".to_string()),
+            version: version("google"),
             enabled: true,
+            capabilities: capabilities(&["chat", "embeddings", "tools"]),
         },
         Provider {
             name: "microsoft".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("Microsoft".to_string()),
+            version: version("microsoft"),
             enabled: true,
+            capabilities: capabilities(&["chat", "completion", "embeddings", "images"]),
         },
         Provider {
             name: "aws".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("AWS".to_string()),
+            version: version("aws"),
             enabled: true,
+            capabilities: capabilities(&["chat", "embeddings"]),
         },
         Provider {
             name: "cohere".to_string(),
-            version: "1.0.0".to_string(),
+            display_name: Some("Cohere".to_string()),
+            version: version("cohere"),
             enabled: true,
+            capabilities: capabilities(&["chat", "embeddings", "rerank"]),
         },
     ]
 }
@@ -112,3 +207,175 @@ pub fn register_default_providers(registry: &mut ProviderRegistry) {
         registry.register(provider);
     }
 }
+
+/// Values recorded as `llm.error_type`
+pub mod error_type {
+    pub const RATE_LIMIT: &str = "rate_limit";
+    pub const QUOTA_EXCEEDED: &str = "quota_exceeded";
+    pub const OVERLOADED: &str = "overloaded";
+    pub const CONTEXT_LENGTH: &str = "context_length_exceeded";
+    pub const AUTHENTICATION: &str = "authentication";
+    pub const INVALID_REQUEST: &str = "invalid_request";
+    pub const NOT_FOUND: &str = "not_found";
+    pub const TIMEOUT: &str = "timeout";
+    pub const SERVER_ERROR: &str = "server_error";
+    pub const UNKNOWN: &str = "unknown";
+}
+
+/// Classify an error response from an LLM provider
+///
+/// Returns the `llm.error_type` value and whether the call is worth retrying.
+/// Provider error codes in the body take precedence over the status, so an
+/// OpenAI `insufficient_quota` 429 is a non-retryable quota error rather
+/// than a rate limit. These describe the provider call, not Untrace's own
+/// exports.
+pub fn classify_llm_error(provider: &str, status: u16, body: &str) -> (&'static str, bool) {
+    let code = provider_error_code(body);
+    let code = code.as_deref().unwrap_or_default();
+
+    match (provider, code) {
+        (_, "insufficient_quota") => (error_type::QUOTA_EXCEEDED, false),
+        (_, "context_length_exceeded") => (error_type::CONTEXT_LENGTH, false),
+        (_, "rate_limit_exceeded" | "rate_limit_error" | "RESOURCE_EXHAUSTED") => {
+            (error_type::RATE_LIMIT, true)
+        }
+        ("anthropic", "overloaded_error") => (error_type::OVERLOADED, true),
+        ("google", "UNAVAILABLE") => (error_type::OVERLOADED, true),
+        _ => match status {
+            401 | 403 => (error_type::AUTHENTICATION, false),
+            404 => (error_type::NOT_FOUND, false),
+            408 | 504 => (error_type::TIMEOUT, true),
+            429 => (error_type::RATE_LIMIT, true),
+            529 => (error_type::OVERLOADED, true),
+            400..=499 => (error_type::INVALID_REQUEST, false),
+            500..=599 => (error_type::SERVER_ERROR, true),
+            _ => (error_type::UNKNOWN, false),
+        },
+    }
+}
+
+/// Record an LLM provider error response on span options
+///
+/// Sets `error_type` from [`classify_llm_error`], `error` to the provider's
+/// message, falling back to the HTTP status, and `http_status`. Returns
+/// whether the call is worth retrying.
+pub fn record_llm_error(options: &mut LLMSpanOptions, status: u16, body: &str) -> bool {
+    let (kind, retryable) = classify_llm_error(&options.provider, status, body);
+    options.http_status = Some(status);
+    options.error_type = Some(kind.to_string());
+    options.error = Some(provider_error_message(body).unwrap_or_else(|| format!("HTTP {}", status)));
+    retryable
+}
+
+/// Get the `error` object from a provider's JSON error body
+///
+/// OpenAI, Azure, Anthropic and Google all nest details under `error`.
+fn provider_error(body: &str) -> Option<serde_json::Value> {
+    let mut value: serde_json::Value = serde_json::from_str(body).ok()?;
+    Some(value.get_mut("error")?.take())
+}
+
+/// Get the machine-readable error code from a provider's error body
+fn provider_error_code(body: &str) -> Option<String> {
+    let error = provider_error(body)?;
+    ["code", "type", "status"]
+        .iter()
+        .filter_map(|field| error.get(field)?.as_str())
+        .find(|code| !code.is_empty() && *code != "error")
+        .map(str::to_string)
+}
+
+/// Get the human-readable error message from a provider's error body
+fn provider_error_message(body: &str) -> Option<String> {
+    let error = provider_error(body)?;
+    error
+        .get("message")?
+        .as_str()
+        .filter(|message| !message.is_empty())
+        .map(str::to_string)
+}
+
+/// Azure OpenAI support
+///
+/// Azure routes requests to named deployments rather than models, so the
+/// deployment has to be mapped back to its model for cost lookup.
+pub mod azure {
+    use crate::attributes::llm;
+    use crate::error::{UntraceError, UntraceResult};
+    use crate::types::LLMSpanOptions;
+
+    /// Provider name used for Azure OpenAI spans
+    pub const PROVIDER_NAME: &str = "azure-openai";
+
+    /// An Azure OpenAI deployment and the model it serves
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AzureDeployment {
+        pub deployment: String,
+        pub model: String,
+        pub api_version: String,
+    }
+
+    impl AzureDeployment {
+        /// Create a new deployment mapping
+        pub fn new(
+            deployment: impl Into<String>,
+            model: impl Into<String>,
+            api_version: impl Into<String>,
+        ) -> Self {
+            Self {
+                deployment: deployment.into(),
+                model: model.into(),
+                api_version: api_version.into(),
+            }
+        }
+
+        /// Parse the deployment name and `api-version` from a request URL
+        ///
+        /// Expects the `.../openai/deployments/{deployment}/...?api-version=...`
+        /// form used by Azure OpenAI endpoints.
+        pub fn from_url(url: &str, model: impl Into<String>) -> UntraceResult<Self> {
+            let url = url::Url::parse(url)?;
+
+            let deployment = url
+                .path_segments()
+                .and_then(|mut segments| {
+                    segments.find(|s| *s == "deployments")?;
+                    segments.next()
+                })
+                .filter(|d| !d.is_empty())
+                .ok_or_else(|| {
+                    UntraceError::validation("URL does not contain an Azure OpenAI deployment")
+                })?;
+
+            let api_version = url
+                .query_pairs()
+                .find(|(key, _)| key == "api-version")
+                .map(|(_, value)| value.into_owned())
+                .ok_or_else(|| UntraceError::validation("URL is missing the api-version parameter"))?;
+
+            Ok(Self::new(deployment, model, api_version))
+        }
+
+        /// Point LLM span options at this deployment
+        ///
+        /// Sets the provider and underlying model, so costs are looked up by
+        /// model, and records the deployment and API version as attributes.
+        pub fn apply(&self, options: &mut LLMSpanOptions) {
+            options.provider = PROVIDER_NAME.to_string();
+            options.model = self.model.clone();
+            options
+                .attributes
+                .insert(llm::AZURE_DEPLOYMENT.to_string(), self.deployment.clone());
+            options
+                .attributes
+                .insert(llm::AZURE_API_VERSION.to_string(), self.api_version.clone());
+        }
+
+        /// Create LLM span options for a call to this deployment
+        pub fn span_options(&self) -> LLMSpanOptions {
+            let mut options = LLMSpanOptions::default();
+            self.apply(&mut options);
+            options
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Redaction of secrets from exported spans
+
+use crate::error::{UntraceError, UntraceResult};
+use opentelemetry::{KeyValue, StringValue, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::EvictedQueue;
+use regex::Regex;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Replacement for redacted text
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Replaces text matching configured patterns with [`REDACTED`]
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Compile a redactor from regex patterns
+    pub fn new(patterns: &[String]) -> UntraceResult<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|err| {
+                    UntraceError::validation(format!(
+                        "Invalid redaction pattern {:?}: {}",
+                        pattern, err
+                    ))
+                })
+            })
+            .collect::<UntraceResult<Vec<_>>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    /// Check whether no patterns are configured
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Redact a string, returning `None` if nothing matched
+    pub fn redact(&self, value: &str) -> Option<String> {
+        let mut redacted: Option<String> = None;
+        for pattern in &self.patterns {
+            let current = redacted.as_deref().unwrap_or(value);
+            if pattern.is_match(current) {
+                redacted = Some(pattern.replace_all(current, REDACTED).into_owned());
+            }
+        }
+        redacted
+    }
+
+    /// Redact string values in place, returning whether anything changed
+    fn redact_attributes(&self, attributes: &mut [KeyValue]) -> bool {
+        let mut changed = false;
+        for kv in attributes {
+            if let Value::String(value) = &kv.value {
+                if let Some(redacted) = self.redact(value.as_str()) {
+                    kv.value = Value::String(StringValue::from(redacted));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Redact a span's attributes, including captured prompt and completion
+    /// bodies, and the attributes of its events
+    pub fn redact_span(&self, span: &mut SpanData) {
+        if self.is_empty() {
+            return;
+        }
+
+        self.redact_attributes(&mut span.attributes);
+
+        let mut events: Vec<_> = span.events.iter().cloned().collect();
+        let mut changed = false;
+        for event in &mut events {
+            changed |= self.redact_attributes(&mut event.attributes);
+        }
+
+        // The queue can't be edited in place, so only rebuild it when needed
+        if changed {
+            let mut queue = EvictedQueue::new(events.len() as u32);
+            queue.extend(events);
+            span.events = queue;
+        }
+    }
+}
+
+/// Span exporter that redacts spans before handing them to another exporter
+#[derive(Debug)]
+pub struct RedactingExporter<E> {
+    inner: E,
+    redactor: Redactor,
+}
+
+impl<E: SpanExporter> RedactingExporter<E> {
+    /// Wrap a span exporter
+    pub fn new(inner: E, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for RedactingExporter<E> {
+    fn export(
+        &mut self,
+        mut batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        for span in &mut batch {
+            self.redactor.redact_span(span);
+        }
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.inner.force_flush()
+    }
+}
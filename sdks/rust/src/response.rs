@@ -0,0 +1,104 @@
+//! Conversion of LLM client responses into span options
+
+use crate::error::{UntraceError, UntraceResult};
+use crate::types::{LLMSpanOptions, TokenUsage};
+use serde_json::Value;
+
+/// Build LLM span options from a response or usage report
+///
+/// ```
+/// use untrace::ToLlmSpanOptions;
+///
+/// let response = serde_json::json!({
+///     "model": "gpt-4-0613",
+///     "usage": {"prompt_tokens": 12, "completion_tokens": 30, "total_tokens": 42}
+/// });
+/// let options = response.to_llm_span_options("openai", "gpt-4").unwrap();
+/// assert_eq!(options.total_tokens, Some(42));
+/// ```
+pub trait ToLlmSpanOptions {
+    /// Convert into span options for a call to `model` on `provider`
+    fn to_llm_span_options(&self, provider: &str, model: &str) -> UntraceResult<LLMSpanOptions>;
+}
+
+impl ToLlmSpanOptions for TokenUsage {
+    /// Uses the usage's own provider and model when the arguments are empty
+    fn to_llm_span_options(&self, provider: &str, model: &str) -> UntraceResult<LLMSpanOptions> {
+        let or_own =
+            |value: &str, own: &str| if value.is_empty() { own } else { value }.to_string();
+
+        Ok(LLMSpanOptions {
+            provider: or_own(provider, &self.provider),
+            model: or_own(model, &self.model),
+            prompt_tokens: Some(self.prompt_tokens),
+            completion_tokens: Some(self.completion_tokens),
+            total_tokens: Some(self.total_tokens),
+            ..Default::default()
+        })
+    }
+}
+
+impl ToLlmSpanOptions for Value {
+    /// Reads token usage, the serving model, the response ID and the finish
+    /// reason from OpenAI, Anthropic and Google response bodies
+    ///
+    /// A response without usage information converts with the token counts
+    /// left unset. The model named in the response, e.g. a dated snapshot,
+    /// takes precedence over `model`.
+    fn to_llm_span_options(&self, provider: &str, model: &str) -> UntraceResult<LLMSpanOptions> {
+        let response = self
+            .as_object()
+            .ok_or_else(|| UntraceError::validation("LLM response must be a JSON object"))?;
+
+        let text = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+        let tokens = |usage: Option<&Value>, keys: &[&str]| -> Option<u32> {
+            let usage = usage?;
+            keys.iter()
+                .find_map(|key| usage.get(*key)?.as_u64())
+                .map(|count| count.min(u32::MAX as u64) as u32)
+        };
+
+        let usage = response
+            .get("usage")
+            .or_else(|| response.get("usageMetadata"));
+        let prompt_tokens = tokens(
+            usage,
+            &["prompt_tokens", "input_tokens", "promptTokenCount"],
+        );
+        let completion_tokens = tokens(
+            usage,
+            &["completion_tokens", "output_tokens", "candidatesTokenCount"],
+        );
+        let total_tokens = tokens(usage, &["total_tokens", "totalTokenCount"])
+            .or_else(|| Some(prompt_tokens?.saturating_add(completion_tokens?)));
+
+        let usage_reason = text(
+            response
+                .get("choices")
+                .or_else(|| response.get("candidates"))
+                .and_then(|choices| choices.get(0))
+                .and_then(|choice| {
+                    choice
+                        .get("finish_reason")
+                        .or_else(|| choice.get("finishReason"))
+                }),
+        )
+        .or_else(|| text(response.get("stop_reason")));
+
+        Ok(LLMSpanOptions {
+            provider: provider.to_string(),
+            model: text(
+                response
+                    .get("model")
+                    .or_else(|| response.get("modelVersion")),
+            )
+            .unwrap_or_else(|| model.to_string()),
+            prompt_tokens,
+            completion_tokens,
+            total_tokens,
+            request_id: text(response.get("id").or_else(|| response.get("responseId"))),
+            usage_reason,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,164 @@
+//! Retry backoff for span exports
+
+use crate::error::{UntraceError, UntraceResult};
+use crate::rng::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Retry budget applied to exports still pending when the SDK shuts down
+pub const SHUTDOWN_RETRY_BUDGET: Duration = Duration::from_secs(5);
+
+/// How randomness is applied to retry delays
+///
+/// Without jitter, clients that failed together retry together, which can
+/// knock a recovering collector over again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JitterKind {
+    /// Plain exponential backoff
+    None,
+    /// Uniformly random delay between zero and the exponential backoff
+    #[default]
+    Full,
+    /// Random delay between the initial backoff and three times the previous delay
+    Decorrelated,
+}
+
+impl fmt::Display for JitterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JitterKind::None => write!(f, "none"),
+            JitterKind::Full => write!(f, "full"),
+            JitterKind::Decorrelated => write!(f, "decorrelated"),
+        }
+    }
+}
+
+impl FromStr for JitterKind {
+    type Err = UntraceError;
+
+    fn from_str(value: &str) -> UntraceResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(JitterKind::None),
+            "full" => Ok(JitterKind::Full),
+            "decorrelated" => Ok(JitterKind::Decorrelated),
+            _ => Err(UntraceError::validation(format!(
+                "Unknown jitter kind {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Retry settings for failed exports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Number of retries after the first attempt, 0 to disable retries
+    pub max_retries: u32,
+
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+
+    /// Upper bound on any single delay
+    pub max_backoff: Duration,
+
+    /// Randomness applied to each delay
+    pub jitter: JitterKind,
+
+    /// Upper bound on the total time spent on one export, including retries
+    ///
+    /// No retry is scheduled that would end after the budget runs out.
+    pub total_budget: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: JitterKind::default(),
+            total_budget: Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Start a backoff sequence for one export
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            config: self.clone(),
+            attempt: 0,
+            previous: self.initial_backoff,
+            waited: Duration::ZERO,
+            deadline: Instant::now().checked_add(self.total_budget),
+        }
+    }
+}
+
+/// Sequence of delays between retries of a single export
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: RetryConfig,
+    attempt: u32,
+    previous: Duration,
+    waited: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Backoff {
+    /// Get the delay before the next retry, or `None` once retries are exhausted
+    ///
+    /// Retries are also exhausted once waiting the delay would overrun
+    /// [`RetryConfig::total_budget`].
+    pub fn next_delay(&mut self, rng: &dyn Rng) -> Option<Duration> {
+        if self.attempt >= self.config.max_retries {
+            return None;
+        }
+
+        let initial = self.config.initial_backoff;
+        let max = self.config.max_backoff;
+        let exponential = initial
+            .checked_mul(2u32.saturating_pow(self.attempt))
+            .unwrap_or(max)
+            .min(max);
+
+        let delay = match self.config.jitter {
+            JitterKind::None => exponential,
+            JitterKind::Full => exponential.mul_f64(rng.next_f64()),
+            JitterKind::Decorrelated => {
+                let upper = self.previous.saturating_mul(3).max(initial);
+                let spread = upper - initial;
+                (initial + spread.mul_f64(rng.next_f64())).min(max)
+            }
+        };
+
+        let waited = self.waited.saturating_add(delay);
+        if waited > self.config.total_budget
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() + delay > deadline)
+        {
+            return None;
+        }
+
+        self.waited = waited;
+        self.attempt += 1;
+        self.previous = delay;
+        Some(delay)
+    }
+}
+
+/// Check whether an export failure is worth retrying
+///
+/// Connection failures, timeouts, rate limiting and server errors are
+/// retried. Other client errors would fail again.
+pub(crate) fn is_retryable(err: &UntraceError) -> bool {
+    match err {
+        UntraceError::Http(err) if err.is_connect() || err.is_timeout() => true,
+        _ => matches!(err.status(), Some(429) | Some(500..=599)),
+    }
+}
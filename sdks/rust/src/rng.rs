@@ -0,0 +1,92 @@
+//! Random number sources for the Untrace SDK
+//!
+//! Production code always uses [`ThreadRng`]. With the `testing` feature
+//! enabled, a [`SeededRng`] can be injected into the tracer's body-capture
+//! sampling and the exporter's retry jitter to make them reproducible. Root
+//! span sampling is decided by trace ID instead, so it needs no RNG.
+
+use rand::Rng as _;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(feature = "testing")]
+use rand::{rngs::StdRng, SeedableRng};
+#[cfg(feature = "testing")]
+use std::sync::Arc;
+
+/// Source of random numbers
+pub trait Rng: fmt::Debug + Send + Sync {
+    /// Uniformly distributed value in `0.0..1.0`
+    fn next_f64(&self) -> f64;
+}
+
+/// Random number source backed by the thread-local RNG
+#[derive(Debug, Clone, Default)]
+pub struct ThreadRng;
+
+impl Rng for ThreadRng {
+    #[inline]
+    fn next_f64(&self) -> f64 {
+        rand::random::<f64>()
+    }
+}
+
+/// Any `rand` RNG behind a mutex, e.g. a seeded `StdRng`
+impl<R: rand::RngCore + fmt::Debug + Send> Rng for Mutex<R> {
+    fn next_f64(&self) -> f64 {
+        self.lock().unwrap_or_else(PoisonError::into_inner).gen::<f64>()
+    }
+}
+
+/// RNG handle stored by the tracer and exporters
+#[cfg(not(feature = "testing"))]
+pub(crate) type SharedRng = ThreadRng;
+
+/// RNG handle stored by the tracer and exporters
+#[cfg(feature = "testing")]
+pub(crate) type SharedRng = Arc<dyn Rng>;
+
+/// Get the default RNG handle
+pub(crate) fn default_rng() -> SharedRng {
+    #[cfg(not(feature = "testing"))]
+    {
+        ThreadRng
+    }
+    #[cfg(feature = "testing")]
+    {
+        Arc::new(ThreadRng)
+    }
+}
+
+/// Deterministic RNG for reproducible tests
+///
+/// Clones share the same sequence.
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    rng: Arc<Mutex<StdRng>>,
+}
+
+#[cfg(feature = "testing")]
+impl SeededRng {
+    /// Create an RNG that produces the same sequence for the same seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Rng for SeededRng {
+    fn next_f64(&self) -> f64 {
+        self.rng.as_ref().next_f64()
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Rng for Arc<dyn Rng> {
+    fn next_f64(&self) -> f64 {
+        self.as_ref().next_f64()
+    }
+}
@@ -0,0 +1,343 @@
+//! Sampling for the Untrace SDK
+
+use crate::attributes::{helpers, llm, workflow};
+use crate::error::{UntraceError, UntraceResult};
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::trace::{Sampler, ShouldSample};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// How spans are chosen for export
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingStrategy {
+    /// Sample root spans at `Config::sampling_rate`, children follow their parent
+    #[default]
+    Ratio,
+    /// Sample at `base_rate`, but also keep spans that take at least `threshold_ms`
+    ///
+    /// Every span is recorded, and traces that missed the base rate are only
+    /// dropped when all their spans end quickly enough. This approximates
+    /// tail-based sampling within the process: spans of such a trace are
+    /// held until its root span ends, and the whole trace is kept if any span
+    /// took at least `threshold_ms`. A span's duration is the longer of its
+    /// own timing and any recorded `llm.duration_ms`. Until then the trace
+    /// propagates as unsampled, so downstream services don't keep it.
+    KeepSlow { threshold_ms: u64, base_rate: f64 },
+}
+
+impl SamplingStrategy {
+    /// Get the rate at which root spans are sampled up front
+    pub fn rate(&self, sampling_rate: f64) -> f64 {
+        match self {
+            SamplingStrategy::Ratio => sampling_rate,
+            SamplingStrategy::KeepSlow { base_rate, .. } => *base_rate,
+        }
+    }
+
+    /// Get the duration from which spans are kept regardless of the rate
+    pub fn slow_threshold(&self) -> Option<Duration> {
+        match self {
+            SamplingStrategy::Ratio => None,
+            SamplingStrategy::KeepSlow { threshold_ms, .. } => {
+                Some(Duration::from_millis(*threshold_ms))
+            }
+        }
+    }
+}
+
+impl fmt::Display for SamplingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SamplingStrategy::Ratio => write!(f, "ratio"),
+            SamplingStrategy::KeepSlow {
+                threshold_ms,
+                base_rate,
+            } => write!(f, "keep_slow:{}:{}", threshold_ms, base_rate),
+        }
+    }
+}
+
+impl FromStr for SamplingStrategy {
+    type Err = UntraceError;
+
+    /// Parses `ratio` or `keep_slow:<threshold_ms>:<base_rate>`
+    fn from_str(value: &str) -> UntraceResult<Self> {
+        let invalid = || UntraceError::validation(format!("Unknown sampling strategy {:?}", value));
+        let mut parts = value.trim().split(':');
+
+        match parts.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("ratio") if parts.next().is_none() => Ok(SamplingStrategy::Ratio),
+            Some("keep_slow") => {
+                let threshold_ms = parts.next().and_then(|v| v.trim().parse().ok());
+                let base_rate = parts.next().and_then(|v| v.trim().parse().ok());
+                match (threshold_ms, base_rate, parts.next()) {
+                    (Some(threshold_ms), Some(base_rate), None) => Ok(SamplingStrategy::KeepSlow {
+                        threshold_ms,
+                        base_rate,
+                    }),
+                    _ => Err(invalid()),
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Context value marking a parent span context taken from a local span
+///
+/// Spans started under an explicit [`opentelemetry::trace::SpanContext`]
+/// have no live parent span to ask whether it is recording, so an unsampled
+/// local parent carrying this marker is treated as deferred.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LocalParent;
+
+/// Workflow run IDs and user IDs whose spans bypass the sampling rate
+#[derive(Debug, Clone, Default)]
+pub struct SamplingOverrides {
+    workflows: Arc<RwLock<HashSet<String>>>,
+    users: Arc<RwLock<HashSet<String>>>,
+}
+
+impl SamplingOverrides {
+    /// Create an empty set of overrides
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Always sample spans belonging to the given workflow run
+    pub fn force_workflow(&self, run_id: String) {
+        self.workflows.write().unwrap().insert(run_id);
+    }
+
+    /// Always sample spans belonging to the given user
+    pub fn force_user(&self, user_id: String) {
+        self.users.write().unwrap().insert(user_id);
+    }
+
+    /// Remove all workflow and user overrides
+    pub fn clear(&self) {
+        self.workflows.write().unwrap().clear();
+        self.users.write().unwrap().clear();
+    }
+
+    /// Check whether there are no overrides registered
+    pub fn is_empty(&self) -> bool {
+        self.workflows.read().unwrap().is_empty() && self.users.read().unwrap().is_empty()
+    }
+
+    /// Check whether a workflow run ID is forced
+    pub fn is_workflow_forced(&self, run_id: &str) -> bool {
+        self.workflows.read().unwrap().contains(run_id)
+    }
+
+    /// Check whether a user ID is forced
+    pub fn is_user_forced(&self, user_id: &str) -> bool {
+        self.users.read().unwrap().contains(user_id)
+    }
+
+    /// Check the span attributes and parent baggage for a forced workflow or user
+    fn matches(&self, parent_context: Option<&Context>, attributes: &[KeyValue]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        // Keys may carry the configured attribute prefix
+        let attribute_match = attributes.iter().any(|kv| {
+            let key = kv.key.as_str();
+            if helpers::matches_key(key, workflow::RUN_ID) {
+                self.is_workflow_forced(&kv.value.as_str())
+            } else if helpers::matches_key(key, workflow::USER_ID) {
+                self.is_user_forced(&kv.value.as_str())
+            } else {
+                false
+            }
+        });
+
+        attribute_match
+            || parent_context.is_some_and(|cx| {
+                let baggage = cx.baggage();
+                baggage
+                    .get(workflow::RUN_ID)
+                    .is_some_and(|v| self.is_workflow_forced(&v.as_str()))
+                    || baggage
+                        .get(workflow::USER_ID)
+                        .is_some_and(|v| self.is_user_forced(&v.as_str()))
+            })
+    }
+}
+
+/// Sampler used by the Untrace tracer provider
+///
+/// Spans whose `workflow.run_id` or `workflow.user_id` (as attributes or parent
+/// baggage) match a registered override are always sampled. Otherwise child spans
+/// follow their parent and root spans are sampled by trace ID ratio at the
+/// configured rate.
+///
+/// The rate is shared between clones and can be changed at runtime with
+/// [`UntraceSampler::set_rate`]. Root spans whose `llm.model` has a rate of
+/// its own, set with [`UntraceSampler::with_model_rates`], are sampled at that
+/// rate instead.
+///
+/// With deferred drops, root spans that miss the rate and their children
+/// are recorded without being sampled, leaving
+/// [`crate::processor::SlowSpanProcessor`] to decide once the trace's root
+/// span ends.
+#[derive(Debug, Clone)]
+pub struct UntraceSampler {
+    /// Sampling rate stored as the bit pattern of an `f64`
+    rate: Arc<AtomicU64>,
+    overrides: SamplingOverrides,
+    defer_drops: bool,
+    model_rates: Arc<HashMap<String, f64>>,
+}
+
+impl UntraceSampler {
+    /// Create a new sampler with the given rate and overrides
+    pub fn new(rate: f64, overrides: SamplingOverrides) -> Self {
+        Self {
+            rate: Arc::new(AtomicU64::new(rate.to_bits())),
+            overrides,
+            defer_drops: false,
+            model_rates: Arc::default(),
+        }
+    }
+
+    /// Create a sampler for the given strategy
+    pub fn with_strategy(
+        strategy: &SamplingStrategy,
+        sampling_rate: f64,
+        overrides: SamplingOverrides,
+    ) -> Self {
+        let mut sampler = Self::new(strategy.rate(sampling_rate), overrides);
+        sampler.defer_drops = strategy.slow_threshold().is_some();
+        sampler
+    }
+
+    /// Sample root spans of the listed models at their own rate
+    pub fn with_model_rates(mut self, rates: HashMap<String, f64>) -> Self {
+        self.model_rates = Arc::new(rates);
+        self
+    }
+
+    /// Get the current sampling rate
+    pub fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// Change the sampling rate for all clones of this sampler
+    pub fn set_rate(&self, rate: f64) -> UntraceResult<()> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(UntraceError::validation(
+                "Sampling rate must be between 0.0 and 1.0",
+            ));
+        }
+
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Get the sampling overrides
+    pub fn overrides(&self) -> &SamplingOverrides {
+        &self.overrides
+    }
+
+    /// Get the rate for a root span, using its model's rate when one is set
+    fn rate_for(&self, attributes: &[KeyValue]) -> f64 {
+        if !self.model_rates.is_empty() {
+            let model_rate = attributes
+                .iter()
+                .find(|kv| helpers::matches_key(kv.key.as_str(), llm::MODEL))
+                .and_then(|kv| self.model_rates.get(kv.value.as_str().as_ref()));
+            if let Some(rate) = model_rate {
+                return *rate;
+            }
+        }
+        self.rate()
+    }
+}
+
+impl ShouldSample for UntraceSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        links: &[Link],
+    ) -> SamplingResult {
+        let trace_state = parent_context
+            .map(|cx| cx.span().span_context().trace_state().clone())
+            .unwrap_or_default();
+
+        if self.overrides.matches(parent_context, attributes) {
+            return SamplingResult {
+                decision: SamplingDecision::RecordAndSample,
+                attributes: Vec::new(),
+                trace_state,
+            };
+        }
+
+        let has_parent = parent_context
+            .is_some_and(|cx| cx.has_active_span() && cx.span().span_context().is_valid());
+        if has_parent {
+            let is_deferred = parent_context.is_some_and(|cx| {
+                let parent = cx.span();
+                let parent_cx = parent.span_context();
+                !parent_cx.is_sampled()
+                    && !parent_cx.is_remote()
+                    && (parent.is_recording() || cx.get::<LocalParent>().is_some())
+            });
+            if self.defer_drops && is_deferred {
+                return SamplingResult {
+                    decision: SamplingDecision::RecordOnly,
+                    attributes: Vec::new(),
+                    trace_state,
+                };
+            }
+
+            // The root sampler is never consulted when there is a parent
+            return Sampler::ParentBased(Box::new(Sampler::AlwaysOff)).should_sample(
+                parent_context,
+                trace_id,
+                name,
+                span_kind,
+                attributes,
+                links,
+            );
+        }
+
+        let rate = self.rate_for(attributes);
+        let sampled = Sampler::TraceIdRatioBased(rate)
+            .should_sample(None, trace_id, name, span_kind, attributes, links)
+            .decision
+            == SamplingDecision::RecordAndSample;
+        if !sampled && self.defer_drops {
+            return SamplingResult {
+                decision: SamplingDecision::RecordOnly,
+                attributes: Vec::new(),
+                trace_state,
+            };
+        }
+
+        SamplingResult {
+            decision: if sampled {
+                SamplingDecision::RecordAndSample
+            } else {
+                SamplingDecision::Drop
+            },
+            attributes: Vec::new(),
+            trace_state,
+        }
+    }
+}
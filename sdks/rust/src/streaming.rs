@@ -0,0 +1,120 @@
+//! Streaming LLM responses
+
+use crate::attributes::{helpers, llm};
+use crate::clock::{Clock, SharedClock};
+use crate::tracer::UntraceTracer;
+use opentelemetry::global::BoxedSpan;
+use opentelemetry::trace::Span;
+use std::time::Instant;
+
+/// LLM span for a streamed response
+///
+/// Counts the chunks and bytes received and, when the span ends, records
+/// them as `llm.stream.chunk_count` and `llm.stream.bytes`. If the
+/// completion token count is known, throughput in tokens per second is
+/// recorded to the `untrace.stream.throughput` histogram.
+///
+/// The span ends when [`StreamingSpan::end`] is called or it is dropped.
+#[derive(Debug)]
+pub struct StreamingSpan {
+    span: BoxedSpan,
+    provider: String,
+    model: String,
+    chunk_count: u64,
+    bytes: u64,
+    completion_tokens: Option<u32>,
+    tracer: UntraceTracer,
+    clock: SharedClock,
+    started_at: Instant,
+    ended: bool,
+}
+
+impl StreamingSpan {
+    pub(crate) fn new(
+        span: BoxedSpan,
+        provider: String,
+        model: String,
+        tracer: UntraceTracer,
+        clock: SharedClock,
+    ) -> Self {
+        let started_at = clock.now();
+        Self {
+            span,
+            provider,
+            model,
+            chunk_count: 0,
+            bytes: 0,
+            completion_tokens: None,
+            tracer,
+            clock,
+            started_at,
+            ended: false,
+        }
+    }
+
+    /// Count a chunk of the response
+    pub fn record_chunk(&mut self, chunk: impl AsRef<[u8]>) {
+        self.chunk_count += 1;
+        self.bytes += chunk.as_ref().len() as u64;
+    }
+
+    /// Set the number of completion tokens, usually from the final chunk's usage
+    pub fn set_completion_tokens(&mut self, tokens: u32) {
+        self.completion_tokens = Some(tokens);
+    }
+
+    /// Get the number of chunks recorded so far
+    pub fn chunk_count(&self) -> u64 {
+        self.chunk_count
+    }
+
+    /// Get the number of bytes recorded so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Get the underlying span, e.g. to set extra attributes
+    pub fn span_mut(&mut self) -> &mut BoxedSpan {
+        &mut self.span
+    }
+
+    /// Record the stream totals and end the span
+    pub fn end(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        if self.ended {
+            return;
+        }
+        self.ended = true;
+
+        let mut attributes = vec![
+            helpers::int(llm::STREAM_CHUNK_COUNT, self.chunk_count as i64),
+            helpers::int(llm::STREAM_BYTES, self.bytes as i64),
+        ];
+        if let Some(tokens) = self.completion_tokens {
+            attributes.push(helpers::int(llm::COMPLETION_TOKENS, tokens as i64));
+        }
+        self.tracer.set_span_attributes(&mut self.span, attributes);
+
+        let elapsed = self.clock.now().saturating_duration_since(self.started_at);
+        if let (Some(metrics), Some(tokens)) = (self.tracer.metrics(), self.completion_tokens) {
+            if !elapsed.is_zero() {
+                metrics.record_stream_throughput(
+                    tokens as f64 / elapsed.as_secs_f64(),
+                    &self.provider,
+                    &self.model,
+                );
+            }
+        }
+
+        self.span.end();
+    }
+}
+
+impl Drop for StreamingSpan {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,217 @@
+//! Assertions over exported spans
+//!
+//! Available with the `testing` feature. Collect spans with an in-memory
+//! exporter, then check them fluently:
+//!
+//! ```
+//! use opentelemetry::trace::{Span as _, Status, Tracer as _, TracerProvider as _};
+//! use opentelemetry::KeyValue;
+//! use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+//! use opentelemetry_sdk::trace::TracerProvider;
+//! use untrace::testing::SpanAssert;
+//!
+//! let exporter = InMemorySpanExporter::default();
+//! let provider = TracerProvider::builder()
+//!     .with_simple_exporter(exporter.clone())
+//!     .build();
+//! let mut span = provider.tracer("test").start("chat");
+//! span.set_attribute(KeyValue::new("llm.model", "gpt-4"));
+//! span.set_status(Status::Ok);
+//! span.end();
+//! provider.force_flush();
+//!
+//! let spans = exporter.get_finished_spans().unwrap();
+//! SpanAssert::new(&spans)
+//!     .assert_span("chat")
+//!     .has_attribute("llm.model", "gpt-4")
+//!     .has_status_ok();
+//! ```
+//!
+//! Every assertion panics with the span's name and the expected and actual
+//! values on mismatch.
+
+use crate::attributes::helpers;
+use opentelemetry::trace::{SpanKind, Status};
+use opentelemetry::Value;
+use opentelemetry_sdk::export::trace::SpanData;
+
+/// Assertions over a set of collected spans
+#[derive(Debug, Clone, Copy)]
+pub struct SpanAssert<'a> {
+    spans: &'a [SpanData],
+}
+
+impl<'a> SpanAssert<'a> {
+    /// Assert against the given spans
+    pub fn new(spans: &'a [SpanData]) -> Self {
+        Self { spans }
+    }
+
+    /// Assert that exactly `count` spans were collected
+    #[track_caller]
+    pub fn has_span_count(self, count: usize) -> Self {
+        assert!(
+            self.spans.len() == count,
+            "expected {} span(s), found {}: {:?}",
+            count,
+            self.spans.len(),
+            self.names()
+        );
+        self
+    }
+
+    /// Assert that a span named `name` exists and start asserting on it
+    ///
+    /// If several spans share the name, the first one collected is used.
+    #[track_caller]
+    pub fn assert_span(self, name: &str) -> SpanAssertion<'a> {
+        match self.spans.iter().find(|span| span.name == name) {
+            Some(span) => SpanAssertion { span },
+            None => panic!("expected a span named {:?}, found {:?}", name, self.names()),
+        }
+    }
+
+    /// Assert that no span named `name` exists
+    #[track_caller]
+    pub fn has_no_span(self, name: &str) -> Self {
+        assert!(
+            !self.spans.iter().any(|span| span.name == name),
+            "expected no span named {:?}, found {:?}",
+            name,
+            self.names()
+        );
+        self
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.spans.iter().map(|span| span.name.as_ref()).collect()
+    }
+}
+
+/// Assertions on a single span
+///
+/// Attribute keys match with or without the configured attribute prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct SpanAssertion<'a> {
+    span: &'a SpanData,
+}
+
+impl<'a> SpanAssertion<'a> {
+    /// Get the span under test
+    pub fn span(&self) -> &'a SpanData {
+        self.span
+    }
+
+    /// Get the value of an attribute, if set
+    pub fn attribute(&self, key: &str) -> Option<&'a Value> {
+        self.span
+            .attributes
+            .iter()
+            .find(|kv| helpers::matches_key(kv.key.as_str(), key))
+            .map(|kv| &kv.value)
+    }
+
+    /// Assert that an attribute is set to `value`
+    #[track_caller]
+    pub fn has_attribute(self, key: &str, value: impl Into<Value>) -> Self {
+        let expected = value.into();
+        match self.attribute(key) {
+            Some(actual) if *actual == expected => {}
+            Some(actual) => panic!(
+                "span {:?}: expected attribute {:?} to be {:?}, found {:?}",
+                self.span.name, key, expected, actual
+            ),
+            None => panic!(
+                "span {:?}: expected attribute {:?} to be {:?}, but it is not set; \
+                 attributes: {:?}",
+                self.span.name,
+                key,
+                expected,
+                self.keys()
+            ),
+        }
+        self
+    }
+
+    /// Assert that an attribute is set, whatever its value
+    #[track_caller]
+    pub fn has_attribute_key(self, key: &str) -> Self {
+        assert!(
+            self.attribute(key).is_some(),
+            "span {:?}: expected attribute {:?} to be set; attributes: {:?}",
+            self.span.name,
+            key,
+            self.keys()
+        );
+        self
+    }
+
+    /// Assert that an attribute is not set
+    #[track_caller]
+    pub fn lacks_attribute(self, key: &str) -> Self {
+        if let Some(actual) = self.attribute(key) {
+            panic!(
+                "span {:?}: expected attribute {:?} not to be set, found {:?}",
+                self.span.name, key, actual
+            );
+        }
+        self
+    }
+
+    /// Assert that the span's status is `Ok`
+    #[track_caller]
+    pub fn has_status_ok(self) -> Self {
+        assert!(
+            self.span.status == Status::Ok,
+            "span {:?}: expected status Ok, found {:?}",
+            self.span.name,
+            self.span.status
+        );
+        self
+    }
+
+    /// Assert that the span's status is an error
+    #[track_caller]
+    pub fn has_status_error(self) -> Self {
+        assert!(
+            matches!(self.span.status, Status::Error { .. }),
+            "span {:?}: expected an error status, found {:?}",
+            self.span.name,
+            self.span.status
+        );
+        self
+    }
+
+    /// Assert that the span has the given kind
+    #[track_caller]
+    pub fn has_kind(self, kind: SpanKind) -> Self {
+        assert!(
+            self.span.span_kind == kind,
+            "span {:?}: expected kind {:?}, found {:?}",
+            self.span.name,
+            kind,
+            self.span.span_kind
+        );
+        self
+    }
+
+    /// Assert that the span is a direct child of `parent`
+    #[track_caller]
+    pub fn is_child_of(self, parent: &SpanAssertion<'_>) -> Self {
+        assert!(
+            self.span.parent_span_id == parent.span.span_context.span_id(),
+            "span {:?}: expected parent {:?}",
+            self.span.name,
+            parent.span.name
+        );
+        self
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.span
+            .attributes
+            .iter()
+            .map(|kv| kv.key.as_str())
+            .collect()
+    }
+}
@@ -1,26 +1,353 @@
 //! Tracer implementation for the Untrace SDK
 
-use crate::attributes::helpers;
-use crate::types::{LLMSpanOptions, SpanOptions, Workflow};
-use opentelemetry::trace::{Span, SpanKind, Tracer as OtelTracer};
-use opentelemetry::KeyValue;
-use std::collections::HashMap;
+use crate::attributes::{feedback, framework, helpers, llm, retrieval, vector_db, workflow};
+use crate::clock::default_clock;
+use crate::config::Config;
+use crate::context::UntraceContext;
+use crate::cost::CostTable;
+use crate::guard::SpanGuard;
+use crate::metrics::UntraceMetrics;
+use crate::processor::WorkflowSpanStart;
+use crate::provider;
+use crate::redaction::Redactor;
+use crate::rng::{default_rng, Rng, SharedRng};
+use crate::sampling::LocalParent;
+use crate::streaming::StreamingSpan;
+use crate::types::{
+    AttributeValue, Cost, Currency, Feedback, LLMOperationType, LLMSpanOptions,
+    ModerationSpanOptions, RetrievalSpanOptions, SpanOptions, VectorDbSpanOptions, Workflow,
+};
+use opentelemetry::trace::{
+    Link, SamplingDecision, SamplingResult, Span, SpanBuilder, SpanContext, SpanKind, Status,
+    TraceContextExt, TraceState, Tracer as OtelTracer,
+};
+use opentelemetry::{Context, Key, KeyValue, Value};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Once, OnceLock};
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of category scores recorded on a moderation span
+const MAX_MODERATION_CATEGORIES: usize = 5;
+
+/// Maximum number of scores recorded on a retrieval span without a top-k
+const MAX_RETRIEVAL_SCORES: usize = 100;
+
+/// Name given to spans started with an empty name
+pub const UNNAMED_SPAN: &str = "unnamed-span";
+
+/// Name of the spans recorded by [`UntraceTracer::record_feedback`]
+pub const FEEDBACK_SPAN_NAME: &str = "feedback";
+
+/// Ensures the empty span name warning is only logged once per process
+static EMPTY_SPAN_NAME_WARNING: Once = Once::new();
+
+/// Operation types the inapplicable LLM option warning has been logged for
+static OPERATION_ATTRIBUTES_WARNINGS: OnceLock<Mutex<HashSet<LLMOperationType>>> =
+    OnceLock::new();
+
+/// Map an HTTP status code to a span status
+///
+/// 2xx is `Ok`, 4xx and 5xx are errors described by the code and its reason
+/// phrase, e.g. `HTTP 404 Not Found`, and anything else leaves the status
+/// unset.
+pub fn status_from_http(code: u16) -> Status {
+    match code {
+        200..=299 => Status::Ok,
+        400..=599 => {
+            let reason = reqwest::StatusCode::from_u16(code)
+                .ok()
+                .and_then(|status| status.canonical_reason());
+            match reason {
+                Some(reason) => Status::error(format!("HTTP {} {}", code, reason)),
+                None => Status::error(format!("HTTP {}", code)),
+            }
+        }
+        _ => Status::Unset,
+    }
+}
 
 /// Untrace tracer wrapper
 #[derive(Debug)]
 pub struct UntraceTracer {
-    tracer: opentelemetry::global::BoxedTracer,
+    tracer: Arc<opentelemetry::global::BoxedTracer>,
+    config: Arc<Config>,
+    cost_table: Arc<CostTable>,
+    metrics: Option<Arc<UntraceMetrics>>,
+    context: Option<Arc<UntraceContext>>,
+    redactor: Arc<Redactor>,
+    rng: SharedRng,
 }
 
 impl UntraceTracer {
     /// Create a new Untrace tracer
     pub fn new(tracer: opentelemetry::global::BoxedTracer) -> Self {
-        Self { tracer }
+        Self::with_config(tracer, Arc::new(Config::default()))
+    }
+
+    /// Create a new Untrace tracer using the given SDK configuration
+    ///
+    /// Invalid redaction patterns, which [`Config::validate`] rejects, are
+    /// ignored with a warning.
+    pub fn with_config(tracer: opentelemetry::global::BoxedTracer, config: Arc<Config>) -> Self {
+        let redactor = Redactor::new(&config.redaction_patterns).unwrap_or_else(|err| {
+            tracing::warn!("Attributes will not be redacted before truncation: {}", err);
+            Redactor::default()
+        });
+        Self {
+            tracer: Arc::new(tracer),
+            config,
+            cost_table: Arc::new(CostTable::default()),
+            metrics: None,
+            context: None,
+            redactor: Arc::new(redactor),
+            rng: default_rng(),
+        }
+    }
+
+    /// Use the given cost table to compute LLM span costs
+    pub fn with_cost_table(mut self, cost_table: CostTable) -> Self {
+        self.cost_table = Arc::new(cost_table);
+        self
+    }
+
+    /// Record automatically computed costs to the given metrics
+    pub fn with_metrics(mut self, metrics: Arc<UntraceMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Read the current workflow from the given context
+    ///
+    /// Needed for [`Config::propagate_workflow_metadata`].
+    pub fn with_untrace_context(mut self, context: Arc<UntraceContext>) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Use the given RNG for body capture sampling
+    #[cfg(feature = "testing")]
+    pub fn with_rng(mut self, rng: Arc<dyn Rng>) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Get the cost table used to compute LLM span costs
+    pub fn cost_table(&self) -> &CostTable {
+        &self.cost_table
+    }
+
+    /// Decide whether a span should carry captured request/response bodies
+    pub(crate) fn should_capture_body(&self) -> bool {
+        if !self.config.capture_body {
+            return false;
+        }
+
+        let rate = self.config.body_capture_rate;
+        rate >= 1.0 || (rate > 0.0 && self.rng.next_f64() < rate)
+    }
+
+    /// Build a span with the global span attributes followed by the given ones
+    ///
+    /// Span-specific attributes come last so they win over a global attribute
+    /// with the same key.
+    ///
+    /// Empty names are replaced with [`UNNAMED_SPAN`], or the span is dropped
+    /// if [`Config::reject_empty_span_names`] is set, since some backends
+    /// reject whole batches containing unnamed spans.
+    fn span_builder(&self, name: String, kind: SpanKind, attributes: Vec<KeyValue>) -> SpanBuilder {
+        let empty_name = name.trim().is_empty();
+        if empty_name {
+            let reject = self.config.reject_empty_span_names;
+            EMPTY_SPAN_NAME_WARNING.call_once(|| {
+                if reject {
+                    tracing::warn!("Dropping span with an empty name");
+                } else {
+                    tracing::warn!("Span started with an empty name, using '{}'", UNNAMED_SPAN);
+                }
+            });
+        }
+        let name = if empty_name { UNNAMED_SPAN.to_string() } else { name };
+
+        let mut all_attributes: Vec<KeyValue> = self
+            .config
+            .global_span_attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        all_attributes.extend(attributes);
+        self.prepare_attributes(&mut all_attributes);
+
+        let mut builder = self
+            .tracer
+            .span_builder(name)
+            .with_kind(kind)
+            .with_attributes(all_attributes);
+        if empty_name && self.config.reject_empty_span_names {
+            builder.sampling_result = Some(SamplingResult {
+                decision: SamplingDecision::Drop,
+                attributes: Vec::new(),
+                trace_state: TraceState::default(),
+            });
+        }
+        builder
+    }
+
+    /// Apply [`Config::attribute_prefix`] and [`Config::max_attribute_length`]
+    ///
+    /// Values that are too long are redacted before they are cut, since a
+    /// secret split by the cut would no longer match its redaction pattern.
+    fn prepare_attributes(&self, attributes: &mut [KeyValue]) {
+        let max_len = self.config.max_attribute_length;
+        let prefix = self.config.attribute_prefix.as_deref();
+
+        for kv in attributes {
+            if let Some(prefix) = prefix {
+                if let Cow::Owned(key) = helpers::with_prefix(prefix, kv.key.as_str()) {
+                    kv.key = Key::from(key);
+                }
+            }
+
+            if max_len == 0 {
+                continue;
+            }
+            if let Value::String(value) = &kv.value {
+                if value.as_str().len() > max_len {
+                    let redacted = self.redactor.redact(value.as_str());
+                    let value = redacted.as_deref().unwrap_or(value.as_str());
+                    kv.value = Value::from(helpers::truncate(value, max_len).into_owned());
+                }
+            }
+        }
+    }
+
+    /// Set attributes on an already started span, applying the prefix and length limit
+    pub(crate) fn set_span_attributes<S: Span>(&self, span: &mut S, mut attributes: Vec<KeyValue>) {
+        self.prepare_attributes(&mut attributes);
+        span.set_attributes(attributes);
+    }
+
+    /// Record the HTTP status of a framework request on its span
+    ///
+    /// Sets `framework.status_code` and the span status from
+    /// [`status_from_http`].
+    pub fn set_http_status<S: Span>(&self, span: &mut S, code: u16) {
+        self.set_span_attributes(span, vec![helpers::int(framework::STATUS_CODE, code as i64)]);
+        span.set_status(status_from_http(code));
+    }
+
+    /// Get the metrics that automatically computed values are recorded to
+    pub(crate) fn metrics(&self) -> Option<&Arc<UntraceMetrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Get the current workflow's metadata as attributes, if propagation is enabled
+    fn workflow_metadata_attributes(&self) -> Vec<KeyValue> {
+        if !self.config.propagate_workflow_metadata {
+            return Vec::new();
+        }
+
+        self.context
+            .as_ref()
+            .and_then(|context| context.get_current_workflow())
+            .map(|current| {
+                current
+                    .metadata
+                    .iter()
+                    .map(|(key, value)| self.workflow_metadata_attribute(key, value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Build a `workflow.metadata.*` attribute, redacting string values
+    ///
+    /// Metadata often carries user details such as emails, so it is redacted
+    /// as it is attached rather than only on export.
+    fn workflow_metadata_attribute(&self, key: &str, value: &AttributeValue) -> KeyValue {
+        let key = format!("{}.{}", workflow::METADATA, key);
+        match value {
+            AttributeValue::String(text) => match self.redactor.redact(text) {
+                Some(redacted) => KeyValue::new(key, redacted),
+                None => KeyValue::new(key, text.clone()),
+            },
+            _ => KeyValue::new(key, value.clone()),
+        }
+    }
+
+    /// Tag an LLM span with the current workflow's run ID so it is rolled up
+    /// into the workflow span, if [`Config::workflow_summary_mode`] is enabled
+    fn workflow_summary_attributes(&self) -> Vec<KeyValue> {
+        if !self.config.workflow_summary_mode {
+            return Vec::new();
+        }
+
+        self.context
+            .as_ref()
+            .and_then(|context| context.get_current_workflow())
+            .map(|current| vec![helpers::string(workflow::RUN_ID, &current.run_id)])
+            .unwrap_or_default()
+    }
+
+    /// Start a span with the given kind and attributes
+    ///
+    /// Attributes are passed at creation time so the sampler can inspect them.
+    fn start_with_attributes(
+        &self,
+        name: String,
+        kind: SpanKind,
+        attributes: Vec<KeyValue>,
+    ) -> opentelemetry::global::BoxedSpan {
+        self.span_builder(name, kind, attributes)
+            .start(self.tracer.as_ref())
+    }
+
+    /// Start a span as a child of the given span context
+    fn start_with_parent(
+        &self,
+        name: String,
+        kind: SpanKind,
+        attributes: Vec<KeyValue>,
+        parent: &SpanContext,
+    ) -> opentelemetry::global::BoxedSpan {
+        self.span_builder(name, kind, attributes)
+            .start_with_context(self.tracer.as_ref(), &parent_context(parent))
     }
 
     /// Start a new span
     pub fn start_span(&self, name: &str) -> opentelemetry::global::BoxedSpan {
-        self.tracer.start(name.to_string())
+        self.start_with_attributes(name.to_string(), SpanKind::Internal, Vec::new())
+    }
+
+    /// Start a span named from a template, e.g. `"{llm.provider}.{llm.operation}"`
+    ///
+    /// Placeholders are filled in from `attributes`, which are also set on
+    /// the span, falling back to the global span attributes. Placeholders
+    /// with no matching attribute are kept literally.
+    pub fn start_span_templated(
+        &self,
+        template: &str,
+        attributes: Vec<KeyValue>,
+    ) -> opentelemetry::global::BoxedSpan {
+        let mut available: Vec<KeyValue> = self
+            .config
+            .global_span_attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+            .collect();
+        available.extend(attributes.iter().cloned());
+
+        let name = helpers::render_template(template, &available);
+        self.start_with_attributes(name, SpanKind::Internal, attributes)
+    }
+
+    /// Start a new span as a child of an explicit parent rather than the current span
+    pub fn start_span_with_parent(
+        &self,
+        name: &str,
+        parent: &SpanContext,
+    ) -> opentelemetry::global::BoxedSpan {
+        self.start_with_parent(name.to_string(), SpanKind::Internal, Vec::new(), parent)
     }
 
     /// Start a new span with options
@@ -28,154 +355,706 @@ impl UntraceTracer {
         &self,
         options: SpanOptions,
     ) -> opentelemetry::global::BoxedSpan {
-        let mut span = self.tracer.start(options.name.clone());
+        let attributes = options
+            .attributes
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value))
+            .chain(options.key_values)
+            .collect();
 
-        // Note: Span kind setting is not available on BoxedSpan
-        // span.set_span_kind(options.kind);
+        self.start_with_attributes(options.name, options.kind, attributes)
+    }
 
-        // Add attributes
-        for (key, value) in options.attributes {
-            span.set_attribute(KeyValue::new(key, value));
+    /// Start a new span with OpenTelemetry attributes, keeping their types
+    ///
+    /// Like [`UntraceTracer::start_span_with_options`] for callers that
+    /// already hold `KeyValue`s rather than strings.
+    pub fn start_span_with_key_values(
+        &self,
+        name: &str,
+        kind: SpanKind,
+        attributes: Vec<KeyValue>,
+    ) -> opentelemetry::global::BoxedSpan {
+        self.start_span_with_options(SpanOptions {
+            name: name.to_string(),
+            kind,
+            ..Default::default()
         }
+        .with_key_values(attributes))
+    }
 
-        span
+    /// Start a new span from borrowed attributes
+    ///
+    /// Like [`UntraceTracer::start_span_with_options`] without building a
+    /// map first. SDK attribute keys are not copied and other keys and
+    /// string values are copied once, into the span.
+    pub fn start_span_borrowed(
+        &self,
+        name: &str,
+        attributes: &[(&str, &AttributeValue)],
+    ) -> opentelemetry::global::BoxedSpan {
+        let attributes = attributes
+            .iter()
+            .map(|(key, value)| KeyValue::new(helpers::key(key), Value::from(*value)))
+            .collect();
+
+        self.start_with_attributes(name.to_string(), SpanKind::Internal, attributes)
     }
 
     /// Start an LLM span
+    ///
+    /// If token counts are given without a cost, the cost is computed from the
+    /// cost table. It is recorded to the metrics by
+    /// [`UntraceTracer::complete_llm_span`], so a span started and completed
+    /// with the same options is counted once.
     pub fn start_llm_span(
         &self,
         name: &str,
         options: LLMSpanOptions,
     ) -> opentelemetry::global::BoxedSpan {
-        let mut span = self.tracer.start(name.to_string());
+        let status = options.http_status.map(status_from_http);
+        let attributes = self.llm_attributes_without_metrics(options);
+        let mut span = self.start_with_attributes(name.to_string(), SpanKind::Client, attributes);
+        if let Some(status) = status {
+            span.set_status(status);
+        }
+        span
+    }
+
+    /// Start an LLM span for a streamed response
+    ///
+    /// Feed each chunk to [`StreamingSpan::record_chunk`]; the totals are
+    /// recorded when the span ends.
+    pub fn start_streaming_llm_span(&self, name: &str, mut options: LLMSpanOptions) -> StreamingSpan {
+        options.stream = Some(true);
+        options.provider = provider::normalize_provider_name(&options.provider);
+        let provider = options.provider.clone();
+        let model = options.model.clone();
+        let clock = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.clock().clone())
+            .unwrap_or_else(default_clock);
+        let span = self.start_llm_span(name, options);
+        StreamingSpan::new(span, provider, model, self.clone(), clock)
+    }
+
+    /// Start an LLM span that records its own token, cost and latency metrics
+    ///
+    /// The guard records to this tracer's metrics, if any, when it ends. Costs
+    /// computed for the span are left to the guard rather than recorded here,
+    /// so they are counted once.
+    pub fn start_llm_span_guard(&self, name: &str, options: LLMSpanOptions) -> SpanGuard {
+        self.llm_span_guard(name.to_string(), options, None)
+    }
+
+    /// Start LLM spans for calls made in parallel, as siblings under one parent span
+    ///
+    /// Useful for fan-out such as sending the same prompt to several models.
+    /// The parent span is named `parent_name` and records no metrics of its
+    /// own. Each child is named `{provider}.{operation}` and records its
+    /// metrics like [`UntraceTracer::start_llm_span_guard`]. End the children
+    /// before the parent.
+    pub fn start_parallel_llm_spans(
+        &self,
+        parent_name: &str,
+        calls: Vec<LLMSpanOptions>,
+    ) -> (SpanGuard, Vec<SpanGuard>) {
+        let clock = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.clock().clone())
+            .unwrap_or_else(default_clock);
+        let span =
+            self.start_with_attributes(parent_name.to_string(), SpanKind::Internal, Vec::new());
+        let parent_context = span.span_context().clone();
+        let parent = SpanGuard::new(span, LLMSpanOptions::default(), self.clone(), None, clock);
+
+        let children = calls
+            .into_iter()
+            .map(|options| {
+                let name = format!(
+                    "{}.{}",
+                    provider::normalize_provider_name(&options.provider),
+                    options.operation
+                );
+                self.llm_span_guard(name, options, Some(&parent_context))
+            })
+            .collect();
+
+        (parent, children)
+    }
+
+    fn llm_span_guard(
+        &self,
+        name: String,
+        mut options: LLMSpanOptions,
+        parent: Option<&SpanContext>,
+    ) -> SpanGuard {
+        options.provider = provider::normalize_provider_name(&options.provider);
+        let clock = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.clock().clone())
+            .unwrap_or_else(default_clock);
+        let attributes = self.llm_attributes_without_metrics(options.clone());
+        let mut span = match parent {
+            Some(parent) => self.start_with_parent(name, SpanKind::Client, attributes, parent),
+            None => self.start_with_attributes(name, SpanKind::Client, attributes),
+        };
+        if let Some(code) = options.http_status {
+            span.set_status(status_from_http(code));
+        }
+        SpanGuard::new(span, options, self.clone(), self.metrics.clone(), clock)
+    }
+
+    /// Start an LLM span as a child of an explicit parent rather than the current span
+    pub fn start_llm_span_with_parent(
+        &self,
+        name: &str,
+        options: LLMSpanOptions,
+        parent: &SpanContext,
+    ) -> opentelemetry::global::BoxedSpan {
+        let status = options.http_status.map(status_from_http);
+        let attributes = self.llm_attributes_without_metrics(options);
+        let mut span =
+            self.start_with_parent(name.to_string(), SpanKind::Client, attributes, parent);
+        if let Some(status) = status {
+            span.set_status(status);
+        }
+        span
+    }
+
+    /// Finish an LLM span once the response is available
+    ///
+    /// Sets the final token counts, cost and error attributes and ends the span.
+    /// When both token counts and the duration are known, the completion
+    /// throughput (`llm.tokens_per_second`) and the completion to prompt
+    /// token ratio (`llm.completion_ratio`) are set as well.
+    pub fn complete_llm_span(
+        &self,
+        mut span: opentelemetry::global::BoxedSpan,
+        mut options: LLMSpanOptions,
+    ) {
+        // Bodies would be discarded with the rest of an unrecorded span
+        if !span.is_recording() {
+            options.prompt = None;
+            options.completion = None;
+        }
+        let status = options.http_status.map(status_from_http);
+        let mut attributes = throughput_attributes(&options);
+        attributes.extend(self.llm_attributes(options));
+        self.set_span_attributes(&mut span, attributes);
+        if let Some(status) = status {
+            span.set_status(status);
+        }
+        span.end();
+    }
+
+    /// Record already-completed LLM calls, e.g. when replaying logs
+    ///
+    /// Each span ends now and starts `duration_ms` earlier (or ends immediately
+    /// if no duration is given).
+    pub fn record_llm_spans(&self, calls: Vec<(String, LLMSpanOptions)>) {
+        let end_time = SystemTime::now();
+
+        for (name, options) in calls {
+            let start_time = options
+                .duration_ms
+                .and_then(|ms| end_time.checked_sub(Duration::from_millis(ms)))
+                .unwrap_or(end_time);
+            let status = options.http_status.map(status_from_http);
+            let attributes = self.llm_attributes(options);
+
+            let mut builder = self
+                .span_builder(name, SpanKind::Client, attributes)
+                .with_start_time(start_time);
+            if let Some(status) = status {
+                builder = builder.with_status(status);
+            }
+            let mut span = builder.start(self.tracer.as_ref());
+            span.end_with_timestamp(end_time);
+        }
+    }
+
+    /// Record an operation that was timed elsewhere as a finished span
+    ///
+    /// The span starts at `start` and ends at `end`. An `end` before `start`
+    /// is treated as a zero-length span.
+    pub fn record_completed_span(
+        &self,
+        name: &str,
+        kind: SpanKind,
+        start: SystemTime,
+        end: SystemTime,
+        attributes: Vec<KeyValue>,
+        status: Status,
+    ) {
+        let mut span = self
+            .span_builder(name.to_string(), kind, attributes)
+            .with_start_time(start)
+            .with_status(status)
+            .start(self.tracer.as_ref());
+        span.end_with_timestamp(end.max(start));
+    }
+
+    /// Compute the cost from the token counts when the caller didn't provide one
+    ///
+    /// Returns `None` if a cost was given, no tokens were reported, or the
+    /// model isn't in the cost table. A cache hit costs nothing.
+    fn auto_cost(&self, options: &LLMSpanOptions) -> Option<Cost> {
+        if options.cost_total.is_some() {
+            return None;
+        }
+        let provider = provider::normalize_provider_name(&options.provider);
+
+        // Cached responses are served without calling the provider
+        if options.cache_hit == Some(true) {
+            return Some(Cost {
+                prompt: 0.0,
+                completion: 0.0,
+                total: 0.0,
+                currency: Currency::Usd,
+                model: options.model.clone(),
+                provider,
+            });
+        }
+
+        if options.prompt_tokens.is_none() && options.completion_tokens.is_none() {
+            return None;
+        }
 
-        // Note: Span kind setting is not available on BoxedSpan
-        // span.set_span_kind(SpanKind::Client);
+        self.cost_table.calculate(
+            &provider,
+            &options.model,
+            options.prompt_tokens.unwrap_or(0),
+            options.completion_tokens.unwrap_or(0),
+        )
+    }
+
+    /// Get the cost of an LLM call, as given or computed from the token counts
+    pub(crate) fn llm_cost(&self, options: &LLMSpanOptions) -> Option<Cost> {
+        match options.cost_total {
+            Some(total) => Some(Cost {
+                prompt: options.cost_prompt.unwrap_or(0.0),
+                completion: options.cost_completion.unwrap_or(0.0),
+                total,
+                currency: Currency::Usd,
+                model: options.model.clone(),
+                provider: provider::normalize_provider_name(&options.provider),
+            }),
+            None => self.auto_cost(options),
+        }
+    }
+
+    /// Handle span options that don't apply to the operation, e.g. `temperature` on an embedding
+    ///
+    /// They are dropped if [`Config::strict_operation_attributes`] is set, and
+    /// otherwise kept with a warning logged once per operation type.
+    fn check_operation_options(&self, options: &mut LLMSpanOptions) {
+        if options.operation.accepts_sampling_parameters() {
+            return;
+        }
+
+        let inapplicable: Vec<&str> = [
+            (llm::TEMPERATURE, options.temperature.is_some()),
+            (llm::TOP_P, options.top_p.is_some()),
+            (llm::MAX_TOKENS, options.max_tokens.is_some()),
+            (llm::TOOLS, options.tools.is_some()),
+            (llm::TOOL_CALLS, options.tool_calls.is_some()),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(key, _)| key)
+        .collect();
+        if inapplicable.is_empty() {
+            return;
+        }
+
+        if self.config.strict_operation_attributes {
+            options.temperature = None;
+            options.top_p = None;
+            options.max_tokens = None;
+            options.tools = None;
+            options.tool_calls = None;
+        } else {
+            let first = OPERATION_ATTRIBUTES_WARNINGS
+                .get_or_init(Mutex::default)
+                .lock()
+                .is_ok_and(|mut warned| warned.insert(options.operation.clone()));
+            if first {
+                tracing::warn!(
+                    "{} spans don't use {}; enable strict_operation_attributes to drop them",
+                    options.operation,
+                    inapplicable.join(", ")
+                );
+            }
+        }
+    }
+
+    /// Build the attributes for an LLM span, recording any computed cost
+    fn llm_attributes(&self, options: LLMSpanOptions) -> Vec<KeyValue> {
+        self.build_llm_attributes(options, true)
+    }
+
+    /// Build the attributes for an LLM span without touching the metrics
+    pub(crate) fn llm_attributes_without_metrics(&self, options: LLMSpanOptions) -> Vec<KeyValue> {
+        self.build_llm_attributes(options, false)
+    }
+
+    fn build_llm_attributes(
+        &self,
+        mut options: LLMSpanOptions,
+        record_cost: bool,
+    ) -> Vec<KeyValue> {
+        options.provider = provider::normalize_provider_name(&options.provider);
+        self.check_operation_options(&mut options);
+
+        if let Some(cost) = self.auto_cost(&options) {
+            options.cost_prompt = Some(cost.prompt);
+            options.cost_completion = Some(cost.completion);
+            options.cost_total = Some(cost.total);
+
+            if let (true, Some(metrics)) = (record_cost, &self.metrics) {
+                let _ = metrics.record_cost(cost);
+            }
+        }
 
         // Add LLM-specific attributes
-        span.set_attribute(helpers::string("llm.provider", &options.provider));
-        span.set_attribute(helpers::string("llm.model", &options.model));
-        span.set_attribute(helpers::string(
-            "llm.operation",
-            &options.operation.to_string(),
-        ));
+        let mut attributes = vec![
+            helpers::string("llm.provider", &options.provider),
+            helpers::string("llm.model", &options.model),
+            helpers::string("llm.operation", &options.operation.to_string()),
+        ];
+
+        if let Some(version) = provider::detected_sdk_version(&options.provider) {
+            attributes.push(helpers::string(llm::PROVIDER_SDK_VERSION, version));
+        }
+
+        if options.operation.is_multimodal() {
+            attributes.extend(multimodal_attributes(&options));
+        }
+
+        attributes.extend(self.workflow_metadata_attributes());
+        attributes.extend(self.workflow_summary_attributes());
 
         if let Some(prompt_tokens) = options.prompt_tokens {
-            span.set_attribute(helpers::int("llm.prompt_tokens", prompt_tokens as i64));
+            attributes.push(helpers::int("llm.prompt_tokens", prompt_tokens as i64));
         }
 
         if let Some(completion_tokens) = options.completion_tokens {
-            span.set_attribute(helpers::int(
+            attributes.push(helpers::int(
                 "llm.completion_tokens",
                 completion_tokens as i64,
             ));
         }
 
         if let Some(total_tokens) = options.total_tokens {
-            span.set_attribute(helpers::int("llm.total_tokens", total_tokens as i64));
+            attributes.push(helpers::int("llm.total_tokens", total_tokens as i64));
         }
 
         if let Some(temperature) = options.temperature {
-            span.set_attribute(helpers::float("llm.temperature", temperature));
+            attributes.push(helpers::float("llm.temperature", temperature));
         }
 
         if let Some(top_p) = options.top_p {
-            span.set_attribute(helpers::float("llm.top_p", top_p));
+            attributes.push(helpers::float("llm.top_p", top_p));
         }
 
         if let Some(max_tokens) = options.max_tokens {
-            span.set_attribute(helpers::int("llm.max_tokens", max_tokens as i64));
+            attributes.push(helpers::int("llm.max_tokens", max_tokens as i64));
         }
 
         if let Some(stream) = options.stream {
-            span.set_attribute(helpers::bool("llm.stream", stream));
+            attributes.push(helpers::bool("llm.stream", stream));
+        }
+
+        if let Some(cache_hit) = options.cache_hit {
+            attributes.push(helpers::bool(llm::CACHE_HIT, cache_hit));
+        }
+
+        let conversation_id = options.conversation_id.or_else(|| {
+            self.context
+                .as_ref()
+                .and_then(|context| context.get_current_workflow())
+                .and_then(|current| current.conversation_id)
+        });
+        if let Some(conversation_id) = conversation_id {
+            attributes.push(helpers::string(llm::CONVERSATION_ID, &conversation_id));
+        }
+
+        if let Some(message_index) = options.message_index {
+            attributes.push(helpers::int(
+                llm::CONVERSATION_MESSAGE_INDEX,
+                message_index as i64,
+            ));
         }
 
         if let Some(tools) = options.tools {
-            span.set_attribute(helpers::string("llm.tools", &tools));
+            attributes.push(helpers::string("llm.tools", &tools));
         }
 
         if let Some(tool_calls) = options.tool_calls {
-            span.set_attribute(helpers::string("llm.tool_calls", &tool_calls));
+            attributes.push(helpers::string("llm.tool_calls", &tool_calls));
         }
 
         if let Some(duration_ms) = options.duration_ms {
-            span.set_attribute(helpers::int("llm.duration_ms", duration_ms as i64));
+            attributes.push(helpers::int("llm.duration_ms", duration_ms as i64));
         }
 
         if let Some(cost_prompt) = options.cost_prompt {
-            span.set_attribute(helpers::float("llm.cost_prompt", cost_prompt));
+            attributes.push(helpers::float("llm.cost_prompt", cost_prompt));
         }
 
         if let Some(cost_completion) = options.cost_completion {
-            span.set_attribute(helpers::float("llm.cost_completion", cost_completion));
+            attributes.push(helpers::float("llm.cost_completion", cost_completion));
         }
 
         if let Some(cost_total) = options.cost_total {
-            span.set_attribute(helpers::float("llm.cost_total", cost_total));
+            attributes.push(helpers::float("llm.cost_total", cost_total));
         }
 
         if let Some(error) = options.error {
-            span.set_attribute(helpers::string("llm.error", &error));
+            attributes.push(helpers::string("llm.error", &error));
         }
 
         if let Some(error_type) = options.error_type {
-            span.set_attribute(helpers::string("llm.error_type", &error_type));
+            attributes.push(helpers::string("llm.error_type", &error_type));
         }
 
         if let Some(request_id) = options.request_id {
-            span.set_attribute(helpers::string("llm.request_id", &request_id));
+            attributes.push(helpers::string("llm.request_id", &request_id));
         }
 
         if let Some(usage_reason) = options.usage_reason {
-            span.set_attribute(helpers::string("llm.usage_reason", &usage_reason));
+            attributes.push(helpers::string("llm.usage_reason", &usage_reason));
+        }
+
+        if let Some(http_status) = options.http_status {
+            attributes.push(helpers::int(llm::HTTP_STATUS_CODE, http_status as i64));
+        }
+
+        // Add captured bodies for the configured fraction of spans
+        if (options.prompt.is_some() || options.completion.is_some()) && self.should_capture_body()
+        {
+            if let Some(prompt) = options.prompt {
+                attributes.push(helpers::string(llm::PROMPT, &prompt));
+            }
+
+            if let Some(completion) = options.completion {
+                attributes.push(helpers::string(llm::COMPLETION, &completion));
+            }
         }
 
         // Add custom attributes
         for (key, value) in options.attributes {
-            span.set_attribute(KeyValue::new(key, value));
+            attributes.push(KeyValue::new(key, value));
         }
 
-        span
+        attributes
     }
 
-    /// Start a workflow span
-    pub fn start_workflow_span(&self, workflow: &Workflow) -> opentelemetry::global::BoxedSpan {
-        let mut span = self.tracer.start(workflow.name.clone());
+    /// Set image and audio attributes on an existing LLM span
+    ///
+    /// Useful when the values are only known from the response. Does nothing
+    /// unless the operation is an image or audio operation.
+    pub fn set_multimodal_attributes<S: Span>(&self, span: &mut S, options: &LLMSpanOptions) {
+        if options.operation.is_multimodal() {
+            self.set_span_attributes(span, multimodal_attributes(options));
+        }
+    }
+
+    /// Start a moderation span
+    ///
+    /// Only the highest-scoring categories are recorded, to bound attribute
+    /// cardinality.
+    pub fn start_moderation_span(
+        &self,
+        provider: &str,
+        options: ModerationSpanOptions,
+    ) -> opentelemetry::global::BoxedSpan {
+        let mut attributes = vec![
+            helpers::string(llm::PROVIDER, &provider::normalize_provider_name(provider)),
+            helpers::string(llm::MODEL, &options.model),
+            helpers::string(llm::OPERATION, &LLMOperationType::Moderation.to_string()),
+            helpers::bool(llm::MODERATION_FLAGGED, options.flagged),
+        ];
+
+        let mut categories: Vec<(String, f64)> = options.categories.into_iter().collect();
+        categories.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (category, score) in categories.into_iter().take(MAX_MODERATION_CATEGORIES) {
+            attributes.push(helpers::float(
+                &format!("{}.{}", llm::MODERATION_CATEGORY, category),
+                score,
+            ));
+        }
 
-        // Note: Span kind setting is not available on BoxedSpan
-        // span.set_span_kind(SpanKind::Internal);
+        self.start_with_attributes("moderation".to_string(), SpanKind::Client, attributes)
+    }
+
+    /// Start a retrieval span
+    ///
+    /// Scores are recorded highest first, capped at `top_k` to bound
+    /// attribute size. The query is only recorded when bodies are captured.
+    pub fn start_retrieval_span(
+        &self,
+        options: RetrievalSpanOptions,
+    ) -> opentelemetry::global::BoxedSpan {
+        let mut attributes = vec![helpers::string(retrieval::INDEX, &options.index)];
+
+        if let Some(query) = options.query {
+            if self.should_capture_body() {
+                attributes.push(helpers::string(retrieval::QUERY, &query));
+            }
+        }
+
+        if let Some(top_k) = options.top_k {
+            attributes.push(helpers::int(retrieval::TOP_K, top_k as i64));
+        }
 
+        if let Some(retrieved_count) = options.retrieved_count {
+            attributes.push(helpers::int(
+                retrieval::RETRIEVED_COUNT,
+                retrieved_count as i64,
+            ));
+        }
+
+        if !options.scores.is_empty() {
+            let cap = options
+                .top_k
+                .map_or(MAX_RETRIEVAL_SCORES, |top_k| top_k as usize);
+            let mut scores = options.scores;
+            scores.sort_by(|a, b| b.total_cmp(a));
+            scores.truncate(cap);
+            attributes.push(helpers::float_slice(retrieval::SCORES, scores));
+        }
+
+        attributes.extend(self.workflow_metadata_attributes());
+
+        // Add custom attributes
+        for (key, value) in options.attributes {
+            attributes.push(KeyValue::new(key, value));
+        }
+
+        self.start_with_attributes("retrieval".to_string(), SpanKind::Client, attributes)
+    }
+
+    /// Start a vector database span
+    pub fn start_vector_db_span(
+        &self,
+        name: &str,
+        options: VectorDbSpanOptions,
+    ) -> opentelemetry::global::BoxedSpan {
+        let mut attributes = vec![
+            helpers::string(vector_db::PROVIDER, &options.provider),
+            helpers::string(vector_db::COLLECTION, &options.collection),
+            helpers::string(vector_db::OPERATION, &options.operation.to_string()),
+        ];
+
+        if let Some(dimensions) = options.dimensions {
+            attributes.push(helpers::int(vector_db::DIMENSIONS, dimensions as i64));
+        }
+
+        if let Some(vector_count) = options.vector_count {
+            attributes.push(helpers::int(vector_db::VECTOR_COUNT, vector_count as i64));
+        }
+
+        if let Some(query_vector_count) = options.query_vector_count {
+            attributes.push(helpers::int(
+                vector_db::QUERY_VECTOR_COUNT,
+                query_vector_count as i64,
+            ));
+        }
+
+        if let Some(result_count) = options.result_count {
+            attributes.push(helpers::int(vector_db::RESULT_COUNT, result_count as i64));
+        }
+
+        if let Some(similarity_threshold) = options.similarity_threshold {
+            attributes.push(helpers::float(
+                vector_db::SIMILARITY_THRESHOLD,
+                similarity_threshold,
+            ));
+        }
+
+        if let Some(filter) = options.filter {
+            attributes.push(helpers::string(vector_db::FILTER, &filter));
+        }
+
+        attributes.extend(self.workflow_metadata_attributes());
+
+        // Add custom attributes
+        for (key, value) in options.attributes {
+            attributes.push(KeyValue::new(key, value));
+        }
+
+        self.start_with_attributes(name.to_string(), SpanKind::Client, attributes)
+    }
+
+    /// Record feedback on an earlier span, such as a rated LLM response
+    ///
+    /// The feedback is recorded as a `feedback` span in the same trace, a child
+    /// of and linked to the rated span, with the score, label and comment as
+    /// `feedback.*` attributes. The rated span's IDs are recorded as
+    /// `feedback.trace_id` and `feedback.span_id` so backends that don't
+    /// follow links can still join the two.
+    pub fn record_feedback(&self, span_context: &SpanContext, feedback: Feedback) {
+        let mut attributes = vec![
+            helpers::float(feedback::SCORE, feedback.score),
+            helpers::string(feedback::TRACE_ID, &span_context.trace_id().to_string()),
+            helpers::string(feedback::SPAN_ID, &span_context.span_id().to_string()),
+        ];
+        if let Some(label) = &feedback.label {
+            attributes.push(helpers::string(feedback::LABEL, label));
+        }
+        if let Some(comment) = &feedback.comment {
+            attributes.push(helpers::string(feedback::COMMENT, comment));
+        }
+
+        self.span_builder(FEEDBACK_SPAN_NAME.to_string(), SpanKind::Internal, attributes)
+            .with_links(vec![Link::new(span_context.clone(), Vec::new())])
+            .start_with_context(self.tracer.as_ref(), &parent_context(span_context))
+            .end();
+    }
+
+    /// Start a workflow span
+    pub fn start_workflow_span(&self, workflow: &Workflow) -> opentelemetry::global::BoxedSpan {
         // Add workflow attributes
-        span.set_attribute(helpers::string("workflow.id", &workflow.id));
-        span.set_attribute(helpers::string("workflow.name", &workflow.name));
-        span.set_attribute(helpers::string("workflow.run_id", &workflow.run_id));
+        let mut attributes = vec![
+            helpers::string("workflow.id", &workflow.id),
+            helpers::string("workflow.name", &workflow.name),
+            helpers::string("workflow.run_id", &workflow.run_id),
+        ];
 
         if let Some(user_id) = &workflow.user_id {
-            span.set_attribute(helpers::string("workflow.user_id", user_id));
+            attributes.push(helpers::string("workflow.user_id", user_id));
         }
 
         if let Some(session_id) = &workflow.session_id {
-            span.set_attribute(helpers::string("workflow.session_id", session_id));
+            attributes.push(helpers::string("workflow.session_id", session_id));
+        }
+
+        if let Some(conversation_id) = &workflow.conversation_id {
+            attributes.push(helpers::string(workflow::CONVERSATION_ID, conversation_id));
         }
 
         if let Some(version) = &workflow.version {
-            span.set_attribute(helpers::string("workflow.version", version));
+            attributes.push(helpers::string("workflow.version", version));
         }
 
         if let Some(parent_id) = &workflow.parent_id {
-            span.set_attribute(helpers::string("workflow.parent_id", parent_id));
+            attributes.push(helpers::string("workflow.parent_id", parent_id));
         }
 
         // Add metadata as attributes
         for (key, value) in &workflow.metadata {
-            span.set_attribute(KeyValue::new(
-                format!("workflow.metadata.{}", key),
-                value.clone(),
-            ));
+            attributes.push(self.workflow_metadata_attribute(key, value));
         }
 
-        span
+        // Tell the workflow summary processor which run the span belongs to
+        let cx = Context::current().with_value(WorkflowSpanStart(workflow.run_id.clone()));
+        self.span_builder(workflow.name.clone(), SpanKind::Internal, attributes)
+            .start_with_context(self.tracer.as_ref(), &cx)
     }
 
     /// Get the underlying OpenTelemetry tracer
@@ -187,7 +1066,81 @@ impl UntraceTracer {
 impl Clone for UntraceTracer {
     fn clone(&self) -> Self {
         Self {
-            tracer: opentelemetry::global::tracer("untrace-sdk"),
+            tracer: Arc::clone(&self.tracer),
+            config: Arc::clone(&self.config),
+            cost_table: Arc::clone(&self.cost_table),
+            metrics: self.metrics.clone(),
+            context: self.context.clone(),
+            redactor: Arc::clone(&self.redactor),
+            rng: self.rng.clone(),
         }
     }
 }
+
+/// Build the throughput and token ratio attributes for a completed LLM call
+///
+/// Each value is skipped when its divisor is zero.
+/// Build a context with an explicit parent span context
+///
+/// Baggage from the current context is kept so sampling overrides still
+/// apply, and the parent is marked as local when it came from this process
+/// so that [`crate::sampling::UntraceSampler`] can defer its children.
+fn parent_context(parent: &SpanContext) -> Context {
+    let cx = Context::current().with_remote_span_context(parent.clone());
+    if parent.is_remote() {
+        cx
+    } else {
+        cx.with_value(LocalParent)
+    }
+}
+
+fn throughput_attributes(options: &LLMSpanOptions) -> Vec<KeyValue> {
+    let (Some(prompt_tokens), Some(completion_tokens), Some(duration_ms)) = (
+        options.prompt_tokens,
+        options.completion_tokens,
+        options.duration_ms,
+    ) else {
+        return Vec::new();
+    };
+
+    let mut attributes = Vec::new();
+
+    if duration_ms > 0 {
+        attributes.push(helpers::float(
+            llm::TOKENS_PER_SECOND,
+            completion_tokens as f64 * 1000.0 / duration_ms as f64,
+        ));
+    }
+
+    if prompt_tokens > 0 {
+        attributes.push(helpers::float(
+            llm::COMPLETION_RATIO,
+            completion_tokens as f64 / prompt_tokens as f64,
+        ));
+    }
+
+    attributes
+}
+
+/// Build the image and audio attributes for a multimodal LLM operation
+fn multimodal_attributes(options: &LLMSpanOptions) -> Vec<KeyValue> {
+    let mut attributes = Vec::new();
+
+    if let Some(image_count) = options.image_count {
+        attributes.push(helpers::int(llm::IMAGE_COUNT, image_count as i64));
+    }
+
+    if let Some(image_size) = &options.image_size {
+        attributes.push(helpers::string(llm::IMAGE_SIZE, image_size));
+    }
+
+    if let Some(duration) = options.audio_duration_seconds {
+        attributes.push(helpers::float(llm::AUDIO_DURATION_SECONDS, duration));
+    }
+
+    if let Some(audio_format) = &options.audio_format {
+        attributes.push(helpers::string(llm::AUDIO_FORMAT, audio_format));
+    }
+
+    attributes
+}
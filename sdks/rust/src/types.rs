@@ -1,6 +1,8 @@
 //! Type definitions for the Untrace SDK
 
-use opentelemetry::trace::SpanKind;
+use crate::clock::{default_clock, Clock, SharedClock};
+use opentelemetry::trace::{Span, SpanKind};
+use opentelemetry::{Key, KeyValue, Value};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -34,9 +36,183 @@ impl std::fmt::Display for LLMOperationType {
     }
 }
 
+impl LLMOperationType {
+    /// Check whether the operation produces or consumes images or audio
+    pub fn is_multimodal(&self) -> bool {
+        matches!(
+            self,
+            LLMOperationType::ImageGeneration
+                | LLMOperationType::AudioTranscription
+                | LLMOperationType::AudioGeneration
+        )
+    }
+
+    /// Check if the operation takes sampling parameters and tools
+    ///
+    /// Temperature, `top_p`, max tokens and tools only apply to operations
+    /// that generate text. Audio transcription accepts a temperature.
+    pub fn accepts_sampling_parameters(&self) -> bool {
+        matches!(
+            self,
+            LLMOperationType::Completion
+                | LLMOperationType::Chat
+                | LLMOperationType::ToolUse
+                | LLMOperationType::AudioTranscription
+        )
+    }
+}
+
+/// Vector database operation types
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VectorDbOperation {
+    Insert,
+    Upsert,
+    Query,
+    Delete,
+    Fetch,
+}
+
+impl std::fmt::Display for VectorDbOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VectorDbOperation::Insert => write!(f, "insert"),
+            VectorDbOperation::Upsert => write!(f, "upsert"),
+            VectorDbOperation::Query => write!(f, "query"),
+            VectorDbOperation::Delete => write!(f, "delete"),
+            VectorDbOperation::Fetch => write!(f, "fetch"),
+        }
+    }
+}
+
+/// Typed attribute value
+///
+/// Serializes as a plain JSON bool, number, string or array. Arrays hold
+/// values of one type, as OpenTelemetry requires; an empty JSON array is
+/// read as a `StringArray`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    StringArray(Vec<String>),
+    IntArray(Vec<i64>),
+    FloatArray(Vec<f64>),
+    BoolArray(Vec<bool>),
+}
+
+impl std::fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributeValue::Bool(value) => write!(f, "{}", value),
+            AttributeValue::Int(value) => write!(f, "{}", value),
+            AttributeValue::Float(value) => write!(f, "{}", value),
+            AttributeValue::String(value) => write!(f, "{}", value),
+            array => write!(f, "{}", opentelemetry::Value::from(array)),
+        }
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<u32> for AttributeValue {
+    fn from(value: u32) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<u64> for AttributeValue {
+    /// Values above `i64::MAX` are clamped
+    fn from(value: u64) -> Self {
+        AttributeValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<usize> for AttributeValue {
+    /// Values above `i64::MAX` are clamped
+    fn from(value: usize) -> Self {
+        AttributeValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<f32> for AttributeValue {
+    fn from(value: f32) -> Self {
+        AttributeValue::Float(value as f64)
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        AttributeValue::Float(value)
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<AttributeValue> for opentelemetry::Value {
+    fn from(value: AttributeValue) -> Self {
+        match value {
+            AttributeValue::Bool(value) => value.into(),
+            AttributeValue::Int(value) => value.into(),
+            AttributeValue::Float(value) => value.into(),
+            AttributeValue::String(value) => value.into(),
+            AttributeValue::StringArray(values) => opentelemetry::Value::Array(
+                values
+                    .into_iter()
+                    .map(opentelemetry::StringValue::from)
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            AttributeValue::IntArray(values) => opentelemetry::Value::Array(values.into()),
+            AttributeValue::FloatArray(values) => opentelemetry::Value::Array(values.into()),
+            AttributeValue::BoolArray(values) => opentelemetry::Value::Array(values.into()),
+        }
+    }
+}
+
+impl From<&AttributeValue> for opentelemetry::Value {
+    fn from(value: &AttributeValue) -> Self {
+        match value {
+            AttributeValue::Bool(value) => (*value).into(),
+            AttributeValue::Int(value) => (*value).into(),
+            AttributeValue::Float(value) => (*value).into(),
+            AttributeValue::String(value) => value.clone().into(),
+            array => array.clone().into(),
+        }
+    }
+}
+
 /// Options for creating LLM spans
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMSpanOptions {
+    /// Provider name, recorded lowercased so `OpenAI` and `openai` group together
     pub provider: String,
     pub model: String,
     pub operation: LLMOperationType,
@@ -57,6 +233,24 @@ pub struct LLMSpanOptions {
     pub error_type: Option<String>,
     pub request_id: Option<String>,
     pub usage_reason: Option<String>,
+    pub prompt: Option<String>,
+    pub completion: Option<String>,
+    /// Number of images generated
+    pub image_count: Option<u32>,
+    /// Image dimensions, e.g. "1024x1024"
+    pub image_size: Option<String>,
+    /// Length of the transcribed or generated audio
+    pub audio_duration_seconds: Option<f64>,
+    /// Audio encoding, e.g. "mp3" or "wav"
+    pub audio_format: Option<String>,
+    /// Whether the response came from a cache; a hit costs nothing
+    pub cache_hit: Option<bool>,
+    /// Conversation the call belongs to, defaulting to the current workflow's
+    pub conversation_id: Option<String>,
+    /// Position of the call's message within its conversation
+    pub message_index: Option<u32>,
+    /// HTTP status of the provider's response, which also sets the span status
+    pub http_status: Option<u16>,
     pub attributes: HashMap<String, String>,
 }
 
@@ -83,33 +277,91 @@ impl Default for LLMSpanOptions {
             error_type: None,
             request_id: None,
             usage_reason: None,
+            prompt: None,
+            completion: None,
+            image_count: None,
+            image_size: None,
+            audio_duration_seconds: None,
+            audio_format: None,
+            cache_hit: None,
+            conversation_id: None,
+            message_index: None,
+            http_status: None,
             attributes: HashMap::new(),
         }
     }
 }
 
-/// Options for creating workflows
+/// Options for creating moderation spans
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModerationSpanOptions {
+    pub model: String,
+    pub flagged: bool,
+    /// Score per moderation category, e.g. "violence" => 0.92
+    pub categories: HashMap<String, f64>,
+}
+
+/// Options for creating vector database spans
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WorkflowOptions {
-    pub user_id: Option<String>,
-    pub session_id: Option<String>,
-    pub version: Option<String>,
-    pub parent_id: Option<String>,
-    pub metadata: HashMap<String, String>,
+pub struct VectorDbSpanOptions {
+    pub provider: String,
+    pub collection: String,
+    pub operation: VectorDbOperation,
+    pub dimensions: Option<u32>,
+    pub vector_count: Option<u32>,
+    pub query_vector_count: Option<u32>,
+    pub result_count: Option<u32>,
+    pub similarity_threshold: Option<f64>,
+    pub filter: Option<String>,
+    pub attributes: HashMap<String, String>,
 }
 
-impl Default for WorkflowOptions {
+impl Default for VectorDbSpanOptions {
     fn default() -> Self {
         Self {
-            user_id: None,
-            session_id: None,
-            version: None,
-            parent_id: None,
-            metadata: HashMap::new(),
+            provider: String::new(),
+            collection: String::new(),
+            operation: VectorDbOperation::Query,
+            dimensions: None,
+            vector_count: None,
+            query_vector_count: None,
+            result_count: None,
+            similarity_threshold: None,
+            filter: None,
+            attributes: HashMap::new(),
         }
     }
 }
 
+/// Options for creating retrieval spans, e.g. the lookup step of a RAG pipeline
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetrievalSpanOptions {
+    /// Index, collection or store searched
+    pub index: String,
+    /// Query text, recorded only when bodies are captured
+    pub query: Option<String>,
+    /// Number of results requested
+    pub top_k: Option<u32>,
+    /// Number of results returned
+    pub retrieved_count: Option<u32>,
+    /// Relevance score of each returned result
+    pub scores: Vec<f64>,
+    pub attributes: HashMap<String, String>,
+}
+
+/// Options for creating workflows
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkflowOptions {
+    pub user_id: Option<String>,
+    pub session_id: Option<String>,
+    /// Conversation recorded on the workflow's LLM spans that don't name one
+    pub conversation_id: Option<String>,
+    pub version: Option<String>,
+    pub parent_id: Option<String>,
+    /// Typed metadata recorded as `workflow.metadata.*` attributes
+    pub metadata: HashMap<String, AttributeValue>,
+}
+
 /// Token usage information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenUsage {
@@ -120,23 +372,163 @@ pub struct TokenUsage {
     pub provider: String,
 }
 
+/// Currency of a cost, by ISO 4217 code
+///
+/// Serializes as the uppercase code, e.g. `"USD"`. Codes without a variant
+/// are kept as [`Currency::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Eur,
+    Gbp,
+    Jpy,
+    Cad,
+    Aud,
+    Chf,
+    Cny,
+    Inr,
+    /// Any other three-letter code, uppercase
+    Other(String),
+}
+
+impl Currency {
+    /// Get the ISO 4217 code
+    pub fn code(&self) -> &str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+            Currency::Jpy => "JPY",
+            Currency::Cad => "CAD",
+            Currency::Aud => "AUD",
+            Currency::Chf => "CHF",
+            Currency::Cny => "CNY",
+            Currency::Inr => "INR",
+            Currency::Other(code) => code,
+        }
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl std::str::FromStr for Currency {
+    type Err = crate::error::UntraceError;
+
+    /// Parse a three-letter ISO 4217 code, ignoring case
+    fn from_str(value: &str) -> crate::error::UntraceResult<Self> {
+        let code = value.trim().to_ascii_uppercase();
+        match code.as_str() {
+            "USD" => Ok(Currency::Usd),
+            "EUR" => Ok(Currency::Eur),
+            "GBP" => Ok(Currency::Gbp),
+            "JPY" => Ok(Currency::Jpy),
+            "CAD" => Ok(Currency::Cad),
+            "AUD" => Ok(Currency::Aud),
+            "CHF" => Ok(Currency::Chf),
+            "CNY" => Ok(Currency::Cny),
+            "INR" => Ok(Currency::Inr),
+            _ if code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase()) => {
+                Ok(Currency::Other(code))
+            }
+            _ => Err(crate::error::UntraceError::validation(format!(
+                "Invalid currency {:?}, expected a three-letter ISO 4217 code",
+                value
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = crate::error::UntraceError;
+
+    fn try_from(value: String) -> crate::error::UntraceResult<Self> {
+        value.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.code().to_string()
+    }
+}
+
 /// Cost information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cost {
     pub prompt: f64,
     pub completion: f64,
     pub total: f64,
-    pub currency: String,
+    pub currency: Currency,
     pub model: String,
     pub provider: String,
 }
 
+/// Feedback on an LLM response, given after the fact
+///
+/// For example a thumbs up or down from the user, or a quality score from an
+/// evaluator.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Feedback {
+    /// Score, e.g. 1.0 for a thumbs up and 0.0 for a thumbs down
+    pub score: f64,
+    /// Short category such as `helpful` or `hallucination`
+    pub label: Option<String>,
+    /// Free-text comment
+    pub comment: Option<String>,
+}
+
+/// Attribute naming and filtering in effect for an SDK instance
+///
+/// Describes how attribute keys and values will appear on the backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaInfo {
+    /// OpenTelemetry semantic conventions schema used for resource attributes
+    pub semconv_schema_url: String,
+    /// Namespaces of the attributes the SDK sets, e.g. `llm` and `workflow`
+    pub namespaces: Vec<String>,
+    /// Prefix added to custom attribute keys
+    pub attribute_prefix: Option<String>,
+    /// Maximum length in bytes of string attribute values, 0 for no limit
+    pub max_attribute_length: usize,
+    /// Regex patterns whose matches are redacted at export
+    pub redaction_patterns: Vec<String>,
+    /// Keys of the attributes added to every span, sorted
+    pub global_span_attribute_keys: Vec<String>,
+    /// Whether spans with empty names are dropped rather than renamed
+    pub reject_empty_span_names: bool,
+    /// Whether workflow metadata is copied onto child spans
+    pub propagate_workflow_metadata: bool,
+}
+
+/// Outcome of shutting down the SDK
+///
+/// Counts cover every span ended since the SDK was initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Spans successfully exported
+    pub flushed_spans: u64,
+    /// Spans that ended but were never exported, e.g. because an export failed
+    ///
+    /// LLM spans rolled up into a workflow summary are not counted.
+    pub dropped_spans: u64,
+    /// Time taken to shut down
+    pub duration: std::time::Duration,
+}
+
 /// Options for creating spans
 #[derive(Debug, Clone)]
 pub struct SpanOptions {
     pub name: String,
     pub kind: SpanKind,
     pub attributes: HashMap<String, String>,
+    /// Attributes recorded with their own types, after `attributes`
+    pub key_values: Vec<KeyValue>,
 }
 
 impl Default for SpanOptions {
@@ -145,6 +537,96 @@ impl Default for SpanOptions {
             name: String::new(),
             kind: SpanKind::Internal,
             attributes: HashMap::new(),
+            key_values: Vec::new(),
+        }
+    }
+}
+
+impl SpanOptions {
+    /// Add attributes from other OpenTelemetry instrumentation, keeping their types
+    pub fn with_key_values(mut self, key_values: Vec<KeyValue>) -> Self {
+        self.key_values.extend(key_values);
+        self
+    }
+}
+
+/// Parsing and naming of span kinds, e.g. for config-driven instrumentation
+///
+/// ```
+/// use untrace::{SpanKindExt, SpanOptions};
+/// use opentelemetry::trace::SpanKind;
+///
+/// let options = SpanOptions {
+///     name: "fetch".to_string(),
+///     kind: SpanKind::from_str("Client").unwrap(),
+///     ..Default::default()
+/// };
+/// assert_eq!(options.kind, SpanKind::Client);
+/// ```
+pub trait SpanKindExt: Sized {
+    /// Parse `client`, `server`, `internal`, `producer` or `consumer`, ignoring case
+    fn from_str(value: &str) -> crate::error::UntraceResult<Self>;
+
+    /// Get the lowercase name of the kind
+    fn as_str(&self) -> &'static str;
+}
+
+impl SpanKindExt for SpanKind {
+    fn from_str(value: &str) -> crate::error::UntraceResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "client" => Ok(SpanKind::Client),
+            "server" => Ok(SpanKind::Server),
+            "internal" => Ok(SpanKind::Internal),
+            "producer" => Ok(SpanKind::Producer),
+            "consumer" => Ok(SpanKind::Consumer),
+            _ => Err(crate::error::UntraceError::validation(format!(
+                "Unknown span kind {:?}, expected client, server, internal, producer or consumer",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SpanKind::Client => "client",
+            SpanKind::Server => "server",
+            SpanKind::Internal => "internal",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+        }
+    }
+}
+
+/// Attributes computed only for spans that are recorded
+///
+/// ```
+/// use untrace::{Span, SpanExt};
+///
+/// fn record_prompt(span: &mut impl Span, messages: &[String]) {
+///     span.set_attribute_lazy("llm.prompt", || messages.join("\n"));
+/// }
+/// ```
+pub trait SpanExt {
+    /// Set an attribute whose value is built by `value`
+    ///
+    /// `value` is only called if the span is recording, so expensive values
+    /// such as serialized prompts aren't built for sampled-out spans.
+    fn set_attribute_lazy<K, V, F>(&mut self, key: K, value: F)
+    where
+        K: Into<Key>,
+        V: Into<Value>,
+        F: FnOnce() -> V;
+}
+
+impl<S: Span> SpanExt for S {
+    fn set_attribute_lazy<K, V, F>(&mut self, key: K, value: F)
+    where
+        K: Into<Key>,
+        V: Into<Value>,
+        F: FnOnce() -> V,
+    {
+        if self.is_recording() {
+            self.set_attribute(KeyValue::new(key, value()));
         }
     }
 }
@@ -157,40 +639,95 @@ pub struct Workflow {
     pub run_id: String,
     pub user_id: Option<String>,
     pub session_id: Option<String>,
+    pub conversation_id: Option<String>,
     pub version: Option<String>,
     pub parent_id: Option<String>,
-    pub metadata: HashMap<String, String>,
+    pub metadata: HashMap<String, AttributeValue>,
     pub start_time: chrono::DateTime<chrono::Utc>,
+    /// Monotonic start used for durations, unaffected by wall-clock changes
+    started_at: std::time::Instant,
+    clock: SharedClock,
 }
 
 impl Workflow {
     /// Create a new workflow
     pub fn new(name: String, run_id: String, options: WorkflowOptions) -> Self {
+        Self::with_shared_clock(name, run_id, options, default_clock())
+    }
+
+    /// Create a new workflow that reads time from the given clock
+    #[cfg(feature = "testing")]
+    pub fn with_clock(
+        name: String,
+        run_id: String,
+        options: WorkflowOptions,
+        clock: std::sync::Arc<dyn Clock>,
+    ) -> Self {
+        Self::with_shared_clock(name, run_id, options, clock)
+    }
+
+    fn with_shared_clock(
+        name: String,
+        run_id: String,
+        options: WorkflowOptions,
+        clock: SharedClock,
+    ) -> Self {
         Self {
             id: uuid::Uuid::new_v4().to_string(),
             name,
             run_id,
             user_id: options.user_id,
             session_id: options.session_id,
+            conversation_id: options.conversation_id,
             version: options.version,
             parent_id: options.parent_id,
             metadata: options.metadata,
-            start_time: chrono::Utc::now(),
+            start_time: clock.utc_now(),
+            started_at: clock.now(),
+            clock,
         }
     }
 
     /// Get the duration since the workflow started
+    ///
+    /// Measured with a monotonic clock, so it is never negative even if the
+    /// system clock moves backward after the workflow starts.
     pub fn duration(&self) -> chrono::Duration {
-        chrono::Utc::now() - self.start_time
+        let elapsed = self.clock.now().saturating_duration_since(self.started_at);
+        chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+
+    /// Get the duration since the workflow started in milliseconds
+    pub fn duration_ms(&self) -> u64 {
+        self.duration().num_milliseconds().max(0) as u64
     }
 }
 
 /// Provider information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Provider {
+    /// Lowercase name recorded on spans, e.g. "openai"
     pub name: String,
+    /// Name as written by the vendor, e.g. "OpenAI"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
     pub version: String,
     pub enabled: bool,
+    /// Capability tags, e.g. "chat" or "embeddings"
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+impl Provider {
+    /// Get the display name, falling back to the name
+    pub fn label(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Check if the provider has the given capability
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
 }
 
 /// Instrumentation configuration
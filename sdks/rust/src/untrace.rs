@@ -1,98 +1,522 @@
 //! Main Untrace SDK implementation
 
+use crate::attributes::{helpers, panic, NAMESPACES};
 use crate::client::UntraceClient;
-use crate::config::Config;
+use crate::config::{Config, Environment, Region, ValidationPolicy};
 use crate::context::UntraceContext;
 use crate::error::{UntraceError, UntraceResult};
+use crate::exporter::{CountingExporter, FileExporter, UntraceExporter};
 use crate::instrumentation::Instrumentation;
-use crate::metrics::UntraceMetrics;
+use crate::metrics::{
+    global_meter_provider_installed, latency_view, set_global_meter_provider,
+    UntraceMetricExporter, UntraceMetrics, DEFAULT_LATENCY_BUCKETS,
+};
+use crate::otlp_json;
+#[cfg(feature = "prometheus")]
+use crate::prometheus::PrometheusExporter;
+use crate::processor::{
+    CountFlushProcessor, DroppedEventsProcessor, FlushPolicy, MinDurationProcessor,
+    SharedProcessor, SlowSpanProcessor, SpanCountProcessor, SpanCounts, SpanSink,
+    WorkflowSummaryProcessor,
+};
+use crate::redaction::{RedactingExporter, Redactor};
 use crate::provider::{ProviderRegistry, register_default_providers};
+use crate::retry::SHUTDOWN_RETRY_BUDGET;
+use crate::sampling::{SamplingOverrides, UntraceSampler};
 use crate::tracer::UntraceTracer;
-use opentelemetry::global;
-use opentelemetry::trace::TracerProvider;
+use crate::types::{Feedback, SchemaInfo, ShutdownReport};
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::metrics::MeterProvider as _;
+use opentelemetry::trace::{
+    SamplingDecision, SamplingResult, Span, SpanContext, Status, TraceState, Tracer as _,
+    TracerProvider as _,
+};
 use opentelemetry::KeyValue;
-use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
-use std::sync::{Arc, OnceLock};
+use opentelemetry_sdk::export::trace::SpanExporter;
+use opentelemetry_sdk::metrics::{MeterProvider as SdkMeterProvider, PeriodicReader};
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::{
+    self as sdktrace, BatchConfig, BatchSpanProcessor, SpanProcessor, TracerProvider,
+};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource::{
+    DEPLOYMENT_ENVIRONMENT, HOST_NAME, SERVICE_NAME, SERVICE_NAMESPACE, SERVICE_VERSION,
+};
+use std::borrow::Cow;
+use std::convert::identity;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Instant;
 use tracing::{debug, info, warn};
 
 /// Global Untrace instance
 static GLOBAL_INSTANCE: OnceLock<Untrace> = OnceLock::new();
 
+/// Serializes [`Untrace::init`] calls
+static GLOBAL_INIT: Mutex<()> = Mutex::new(());
+
+/// Whether a panic hook has been installed by any instance
+static PANIC_HOOK_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Tracer and meter name used by the global instance
+const GLOBAL_INSTRUMENTATION_NAME: &str = "untrace-sdk";
+
+/// Build the OpenTelemetry resource describing this service
+pub(crate) fn build_resource(config: &Config) -> Resource {
+    let mut resource_attributes = vec![
+        KeyValue::new(SERVICE_NAME.to_string(), config.service_name.clone()),
+        KeyValue::new(SERVICE_VERSION.to_string(), config.service_version.clone()),
+        KeyValue::new("service.environment", config.environment.to_string()),
+        KeyValue::new(DEPLOYMENT_ENVIRONMENT.to_string(), config.environment.to_string()),
+    ];
+
+    if let Some(namespace) = &config.service_namespace {
+        resource_attributes.push(KeyValue::new(SERVICE_NAMESPACE.to_string(), namespace.clone()));
+    }
+
+    if let Some(host_name) = config.host_name.clone().or_else(detect_host_name) {
+        resource_attributes.push(KeyValue::new(HOST_NAME.to_string(), host_name));
+    }
+
+    // Add custom resource attributes
+    for (key, value) in &config.resource_attributes {
+        resource_attributes.push(KeyValue::new(key.clone(), value.clone()));
+    }
+
+    Resource::new(resource_attributes)
+}
+
+/// Check whether a tracer provider other than the no-op default is installed globally
+///
+/// The probe span is dropped before sampling, so it never reaches the
+/// installed provider's processors. The no-op provider returns an invalid
+/// span context, while a real provider assigns IDs even to dropped spans.
+fn global_tracer_provider_installed() -> bool {
+    let tracer = global::tracer(GLOBAL_INSTRUMENTATION_NAME);
+    let mut builder = tracer.span_builder("untrace.global-provider-probe");
+    builder.sampling_result = Some(SamplingResult {
+        decision: SamplingDecision::Drop,
+        attributes: Vec::new(),
+        trace_state: TraceState::default(),
+    });
+    let span = tracer.build_with_context(builder, &opentelemetry::Context::new());
+    span.span_context().is_valid()
+}
+
+/// Build the provider registry from the defaults, minus any disabled providers
+pub(crate) fn build_provider_registry(config: &Config) -> ProviderRegistry {
+    let mut registry = ProviderRegistry::new();
+    register_default_providers(&mut registry);
+
+    for name in &config.disabled_providers {
+        if registry.disable(name).is_err() {
+            warn!("Cannot disable unknown provider '{}'", name);
+        }
+    }
+
+    registry
+}
+
+/// Detect the host name from the environment
+fn detect_host_name() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Configuration used in place of one that failed validation
+///
+/// Nothing is sampled unless forced through sampling overrides, and
+/// exports are only logged, never sent.
+fn disabled_config(config: &Config) -> Config {
+    let mut disabled = Config::new("disabled".to_string())
+        .with_dry_run(true)
+        .with_sampling_rate(0.0);
+    disabled.service_name = config.service_name.clone();
+    disabled.service_version = config.service_version.clone();
+    disabled.environment = config.environment.clone();
+    disabled.disable_auto_instrumentation = true;
+    disabled
+}
+
 /// Main Untrace SDK struct
+///
+/// Call [`Untrace::shutdown`] (or at least [`Untrace::flush`]) before the
+/// last handle is dropped; debug builds log a warning otherwise.
 #[derive(Debug)]
 pub struct Untrace {
     client: UntraceClient,
     instrumentation: Instrumentation,
     provider_registry: ProviderRegistry,
+    sampling_overrides: SamplingOverrides,
+    sampler: UntraceSampler,
+    flush_policy: FlushPolicy,
+    span_counts: SpanCounts,
+    span_sink: Arc<dyn SpanSink>,
+    exporter: UntraceExporter,
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    #[cfg(feature = "prometheus")]
+    prometheus_exporter: PrometheusExporter,
+    #[cfg(feature = "logs")]
+    logger_provider: opentelemetry_sdk::logs::LoggerProvider,
     config: Arc<Config>,
+    disabled: bool,
+    flush_check: Arc<FlushCheck>,
+}
+
+/// Warns in debug builds if the last handle to an instance is dropped
+/// without `flush` or `shutdown` having been called
+///
+/// Spans still queued in the batch processor at that point are lost, which
+/// otherwise shows up as spans silently missing from the backend.
+#[derive(Debug, Default)]
+struct FlushCheck {
+    flushed: AtomicBool,
+}
+
+impl FlushCheck {
+    fn mark_flushed(&self) {
+        self.flushed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for FlushCheck {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) && !self.flushed.load(Ordering::Relaxed) {
+            warn!(
+                "Untrace instance dropped without flush() or shutdown() being called; \
+                 queued spans may not have been exported"
+            );
+        }
+    }
 }
 
 impl Untrace {
+    /// Start configuring an instance in a single chain
+    ///
+    /// ```no_run
+    /// # async fn run() -> untrace::UntraceResult<()> {
+    /// let untrace = untrace::Untrace::builder("your-api-key")
+    ///     .service_name("chat-api")
+    ///     .sampling_rate(0.5)
+    ///     .init()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder(api_key: impl Into<String>) -> UntraceBuilder {
+        UntraceBuilder {
+            config: Config::new(api_key.into()),
+        }
+    }
+
     /// Initialize the Untrace SDK
+    ///
+    /// Installs the tracer and meter providers globally and registers the
+    /// instance returned by [`Untrace::get_instance`]. If another library
+    /// already installed a global tracer provider, it is left in place and
+    /// the instance keeps its provider to itself, unless
+    /// [`Config::use_global_provider`] is set; the same goes for a meter
+    /// provider installed by an earlier instance. Calling this again once an
+    /// instance is registered fails without touching the global providers.
+    /// Use [`Untrace::init_named`] to run more than one instance in a process.
     pub async fn init(config: Config) -> UntraceResult<Self> {
-        // Validate configuration
-        config.validate()?;
+        let already_initialized =
+            || UntraceError::initialization("Failed to set global Untrace instance");
+
+        // Check before any global provider is installed, and hold the lock so
+        // that concurrent calls can't both get past the check
+        let _init = GLOBAL_INIT.lock().unwrap_or_else(PoisonError::into_inner);
+        if GLOBAL_INSTANCE.get().is_some() {
+            return Err(already_initialized());
+        }
+        let untrace = Self::build_with_default_exporter(config, true, identity)?;
+
+        // Set global instance
+        GLOBAL_INSTANCE.set(untrace.clone()).map_err(|_| already_initialized())?;
+
+        info!("Untrace SDK initialized successfully");
+        Ok(untrace)
+    }
+
+    /// Initialize a non-global instance of the Untrace SDK
+    ///
+    /// The instance gets its own tracer and meter providers, named after
+    /// `config.service_name`, and leaves the global providers and
+    /// [`Untrace::get_instance`] untouched. Any number of named instances can
+    /// coexist with each other and with the global one.
+    pub async fn init_named(config: Config) -> UntraceResult<Self> {
+        let untrace = Self::build_with_default_exporter(config, false, identity)?;
+        info!("Untrace SDK instance '{}' initialized", untrace.config.service_name);
+        Ok(untrace)
+    }
+
+    /// Initialize a non-global instance that exports spans to a custom exporter
+    ///
+    /// Behaves like [`Untrace::init_named`], but spans are handed to
+    /// `span_exporter` (after redaction) instead of the Untrace API.
+    pub async fn init_named_with_exporter<E>(config: Config, span_exporter: E) -> UntraceResult<Self>
+    where
+        E: SpanExporter + 'static,
+    {
+        let untrace = Self::build(config, false, |_| span_exporter, identity)?;
+        info!("Untrace SDK instance '{}' initialized", untrace.config.service_name);
+        Ok(untrace)
+    }
+
+    /// Initialize a non-global instance with an additional span processor
+    ///
+    /// Behaves like [`Untrace::init_named`], but `span_processor` is also
+    /// registered on the tracer provider and sees every recorded span,
+    /// before redaction and regardless of Untrace's own export pipeline.
+    /// The tracer provider can't be changed once built, so this is the way
+    /// to attach extra processors or exporters. The same stability caveats
+    /// as [`Untrace::tracer_provider`] apply.
+    pub async fn init_named_with_span_processor<P>(
+        config: Config,
+        span_processor: P,
+    ) -> UntraceResult<Self>
+    where
+        P: SpanProcessor + 'static,
+    {
+        let untrace = Self::build_with_default_exporter(config, false, |builder| {
+            builder.with_span_processor(span_processor)
+        })?;
+        info!("Untrace SDK instance '{}' initialized", untrace.config.service_name);
+        Ok(untrace)
+    }
+
+    /// Set up an instance exporting spans to the API, or to a file if one is configured
+    fn build_with_default_exporter<P>(
+        config: Config,
+        install_global: bool,
+        configure_provider: P,
+    ) -> UntraceResult<Self>
+    where
+        P: FnOnce(sdktrace::Builder) -> sdktrace::Builder,
+    {
+        match &config.file_export_path {
+            Some(path) => {
+                let file_exporter = FileExporter::new(path, config.file_export_max_bytes)?;
+                Self::build(config, install_global, |_| file_exporter, configure_provider)
+            }
+            None => Self::build(config, install_global, UntraceExporter::clone, configure_provider),
+        }
+    }
+
+    /// Set up an instance, optionally installing its providers globally
+    ///
+    /// `configure_provider` can add to the tracer provider after Untrace's
+    /// own processors are registered.
+    fn build<E, F, P>(
+        config: Config,
+        install_global: bool,
+        span_exporter: F,
+        configure_provider: P,
+    ) -> UntraceResult<Self>
+    where
+        E: SpanExporter + 'static,
+        F: FnOnce(&UntraceExporter) -> E,
+        P: FnOnce(sdktrace::Builder) -> sdktrace::Builder,
+    {
+        // Validate configuration, falling back to a disabled instance if allowed
+        let (config, disabled) = match config.validate() {
+            Ok(()) => (config, false),
+            Err(err) => match config.on_validation_failure {
+                ValidationPolicy::Error => return Err(err),
+                ValidationPolicy::DisableAndWarn => {
+                    warn!("Invalid Untrace configuration, tracing is disabled: {}", err);
+                    (disabled_config(&config), true)
+                }
+            },
+        };
 
         // Set up logging if debug is enabled
         if config.debug {
-            tracing_subscriber::fmt()
+            let _ = tracing_subscriber::fmt()
                 .with_env_filter("untrace=debug")
-                .init();
+                .try_init();
             info!("Untrace SDK initialized with debug logging enabled");
         }
 
-        // Create resource
-        let mut resource_attributes = vec![
-            KeyValue::new(SERVICE_NAME.to_string(), config.service_name.clone()),
-            KeyValue::new("service.version", config.service_version.clone()),
-            KeyValue::new("service.environment", config.environment.clone()),
-        ];
-
-        // Add custom resource attributes
-        for (key, value) in &config.resource_attributes {
-            resource_attributes.push(KeyValue::new(key.clone(), value.clone()));
-        }
+        let resource = build_resource(&config);
 
         // Set up OpenTelemetry
-        let tracer = global::tracer("untrace-sdk");
-        let untrace_tracer = Arc::new(UntraceTracer::new(tracer));
+        let sampling_overrides = SamplingOverrides::new();
+        let sampler = UntraceSampler::with_strategy(
+            &config.sampling_strategy,
+            config.sampling_rate,
+            sampling_overrides.clone(),
+        )
+        .with_model_rates(config.model_sampling_rates.clone());
+        let exporter = UntraceExporter::new(&config)?;
+        let flush_policy = FlushPolicy::new().with_span_names(config.flush_on_span_names.clone());
+        let span_counts = SpanCounts::new();
+        let redacting_exporter =
+            RedactingExporter::new(span_exporter(&exporter), Redactor::new(&config.redaction_patterns)?);
+        let counting_exporter = CountingExporter::new(redacting_exporter, span_counts.clone());
+        let span_processor = BatchSpanProcessor::builder(counting_exporter, runtime::Tokio)
+            .with_batch_config(
+                BatchConfig::default()
+                    .with_max_export_batch_size(config.max_batch_size)
+                    .with_scheduled_delay(config.export_interval),
+            )
+            .build();
+        let span_processor = CountFlushProcessor::new(span_processor, flush_policy.clone());
+        let span_processor = DroppedEventsProcessor::new(span_processor);
+        let span_processor = SlowSpanProcessor::new(
+            span_processor,
+            config.sampling_strategy.slow_threshold(),
+            span_counts.clone(),
+        );
+        let span_processor = MinDurationProcessor::new(
+            span_processor,
+            config.min_span_duration,
+            span_counts.clone(),
+        );
+        let span_processor = SharedProcessor::new(span_processor);
+        let span_sink: Arc<dyn SpanSink> = Arc::new(span_processor.clone());
+        let tracer_provider = TracerProvider::builder()
+            .with_span_processor(SpanCountProcessor::new(span_counts.clone()));
+        let tracer_provider = if config.workflow_summary_mode {
+            tracer_provider.with_span_processor(WorkflowSummaryProcessor::new(
+                span_processor,
+                span_counts.clone(),
+            ))
+        } else {
+            tracer_provider.with_span_processor(span_processor)
+        };
+        let tracer_provider = configure_provider(tracer_provider)
+            .with_config(
+                sdktrace::config()
+                    .with_sampler(sampler.clone())
+                    .with_max_events_per_span(config.max_events_per_span)
+                    .with_resource(resource.clone()),
+            )
+            .build();
+
+        let config = Arc::new(config);
+        let instrumentation_name = if install_global {
+            Cow::Borrowed(GLOBAL_INSTRUMENTATION_NAME)
+        } else {
+            Cow::Owned(config.service_name.clone())
+        };
+
+        // Push metrics to the Untrace API on the export interval, and also
+        // make them available to Prometheus scrapes with that feature
+        let latency_buckets = config
+            .latency_buckets
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LATENCY_BUCKETS.to_vec());
+        let metric_reader =
+            PeriodicReader::builder(UntraceMetricExporter::new(exporter.clone()), runtime::Tokio)
+                .with_interval(config.export_interval)
+                .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(metric_reader)
+            .with_view(
+                latency_view(latency_buckets)
+                    .map_err(|err| UntraceError::initialization(err.to_string()))?,
+            )
+            .with_resource(resource.clone());
+        #[cfg(feature = "prometheus")]
+        let prometheus_exporter = PrometheusExporter::new();
+        #[cfg(feature = "prometheus")]
+        let meter_provider = meter_provider.with_reader(prometheus_exporter.clone());
+        let meter_provider = meter_provider.build();
+        let meter = meter_provider.meter(instrumentation_name.clone());
+        if install_global {
+            if config.use_global_provider || !global_meter_provider_installed() {
+                set_global_meter_provider(meter_provider.clone());
+            } else {
+                warn!(
+                    "A global meter provider is already installed; keeping it and using a \
+                     local provider for Untrace. Set use_global_provider to replace it"
+                );
+            }
+        }
 
         // Create metrics
-        let meter = global::meter("untrace-sdk");
         let metrics = Arc::new(UntraceMetrics::new(meter));
 
         // Create context
-        let context = Arc::new(UntraceContext::new());
+        let context = Arc::new(UntraceContext::with_run_id_format(config.run_id_format.clone()));
+
+        // Create tracer
+        let tracer = BoxedTracer::new(Box::new(tracer_provider.tracer(instrumentation_name)));
+        if install_global {
+            if config.use_global_provider || !global_tracer_provider_installed() {
+                global::set_tracer_provider(tracer_provider.clone());
+            } else {
+                warn!(
+                    "A global tracer provider is already installed; keeping it and using a \
+                     local provider for Untrace. Set use_global_provider to replace it"
+                );
+            }
+        }
+        let untrace_tracer = Arc::new(
+            UntraceTracer::with_config(tracer, Arc::clone(&config))
+                .with_metrics(Arc::clone(&metrics))
+                .with_untrace_context(Arc::clone(&context)),
+        );
 
         // Create client
         let client = UntraceClient::new(untrace_tracer, metrics, context);
 
+        // Send LLM inputs and outputs logged through the client to the Untrace API
+        #[cfg(feature = "logs")]
+        let (client, logger_provider) = {
+            use opentelemetry::logs::LoggerProvider as _;
+
+            let logger_provider = opentelemetry_sdk::logs::LoggerProvider::builder()
+                .with_batch_exporter(
+                    crate::logs::UntraceLogExporter::new(
+                        exporter.clone(),
+                        Redactor::new(&config.redaction_patterns)?,
+                    ),
+                    runtime::Tokio,
+                )
+                .with_config(opentelemetry_sdk::logs::config().with_resource(resource.clone()))
+                .build();
+            let logger = logger_provider.logger(config.service_name.clone());
+            (client.with_logger(logger), logger_provider)
+        };
+
         // Create instrumentation
         let mut instrumentation = Instrumentation::new(Default::default());
 
         // Create provider registry
-        let mut provider_registry = ProviderRegistry::new();
-        register_default_providers(&mut provider_registry);
+        let provider_registry = build_provider_registry(&config);
 
         // Enable instrumentation if not disabled
         if !config.disable_auto_instrumentation {
             instrumentation.enable()?;
         }
 
-        let untrace = Self {
+        Ok(Self {
             client,
             instrumentation,
             provider_registry,
-            config: Arc::new(config),
-        };
-
-        // Set global instance
-        GLOBAL_INSTANCE.set(untrace.clone()).map_err(|_| {
-            UntraceError::initialization("Failed to set global Untrace instance")
-        })?;
-
-        info!("Untrace SDK initialized successfully");
-        Ok(untrace)
+            sampling_overrides,
+            sampler,
+            flush_policy,
+            span_counts,
+            span_sink,
+            exporter,
+            tracer_provider,
+            meter_provider,
+            #[cfg(feature = "prometheus")]
+            prometheus_exporter,
+            #[cfg(feature = "logs")]
+            logger_provider,
+            config,
+            disabled,
+            flush_check: Arc::new(FlushCheck::default()),
+        })
     }
 
     /// Initialize from environment variables
@@ -116,6 +540,11 @@ impl Untrace {
         self.client.tracer()
     }
 
+    /// Record feedback on an earlier span, see [`UntraceTracer::record_feedback`]
+    pub fn record_feedback(&self, span_context: &SpanContext, feedback: Feedback) {
+        self.tracer().record_feedback(span_context, feedback);
+    }
+
     /// Get the metrics
     pub fn metrics(&self) -> &UntraceMetrics {
         self.client.metrics()
@@ -126,6 +555,28 @@ impl Untrace {
         self.client.context()
     }
 
+    /// Get the underlying OpenTelemetry tracer provider
+    ///
+    /// An escape hatch for advanced uses, such as creating tracers for other
+    /// instrumentation libraries or flushing span processors directly. To
+    /// attach span processors, use [`Untrace::init_named_with_span_processor`],
+    /// as the provider can't be changed once built.
+    ///
+    /// Stability: the type comes from the `opentelemetry_sdk` version this
+    /// crate depends on and changes when that dependency is upgraded, which
+    /// may happen in a minor release. Shutting the provider down directly
+    /// bypasses [`Untrace::shutdown`] and its export report.
+    pub fn tracer_provider(&self) -> &TracerProvider {
+        &self.tracer_provider
+    }
+
+    /// Get the underlying OpenTelemetry meter provider
+    ///
+    /// The same stability caveats as [`Untrace::tracer_provider`] apply.
+    pub fn meter_provider(&self) -> &SdkMeterProvider {
+        &self.meter_provider
+    }
+
     /// Get the instrumentation
     pub fn instrumentation(&self) -> &Instrumentation {
         &self.instrumentation
@@ -137,13 +588,146 @@ impl Untrace {
     }
 
     /// Get the configuration
+    ///
+    /// For a disabled instance this is the stand-in configuration, not the
+    /// one that failed validation. Values changed at runtime, such as the
+    /// sampling rate, are not reflected here.
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// Check whether the instance was disabled because its configuration was invalid
+    ///
+    /// See [`ValidationPolicy::DisableAndWarn`].
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Report the attribute naming and filtering rules in effect
+    ///
+    /// Useful for confirming which keys will appear on the backend when
+    /// debugging schema mismatches.
+    pub fn attribute_schema_info(&self) -> SchemaInfo {
+        let mut global_span_attribute_keys: Vec<String> =
+            self.config.global_span_attributes.keys().cloned().collect();
+        global_span_attribute_keys.sort();
+
+        SchemaInfo {
+            semconv_schema_url: opentelemetry_semantic_conventions::SCHEMA_URL.to_string(),
+            namespaces: NAMESPACES.iter().map(|namespace| namespace.to_string()).collect(),
+            attribute_prefix: self.config.attribute_prefix.clone(),
+            max_attribute_length: self.config.max_attribute_length,
+            redaction_patterns: self.config.redaction_patterns.clone(),
+            global_span_attribute_keys,
+            reject_empty_span_names: self.config.reject_empty_span_names,
+            propagate_workflow_metadata: self.config.propagate_workflow_metadata,
+        }
+    }
+
+    /// Always sample spans belonging to the given workflow run, regardless of the sampling rate
+    pub fn force_sample_workflow(&self, run_id: impl Into<String>) {
+        self.sampling_overrides.force_workflow(run_id.into());
+    }
+
+    /// Always sample spans belonging to the given user, regardless of the sampling rate
+    pub fn force_sample_user(&self, user_id: impl Into<String>) {
+        self.sampling_overrides.force_user(user_id.into());
+    }
+
+    /// Remove all forced workflow and user sampling overrides
+    pub fn clear_sampling_overrides(&self) {
+        self.sampling_overrides.clear();
+    }
+
+    /// Change the sampling rate without restarting the SDK
+    ///
+    /// Takes effect for spans started after the call. With the keep-slow
+    /// strategy this is the base rate. Returns a validation error if the rate
+    /// is outside `0.0..=1.0`.
+    ///
+    /// [`Untrace::config`] keeps the rate the instance was initialized with;
+    /// use [`Untrace::sampling_rate`] for the current one.
+    pub fn set_sampling_rate(&self, rate: f64) -> UntraceResult<()> {
+        self.sampler.set_rate(rate)?;
+        info!("Sampling rate set to {}", rate);
+        Ok(())
+    }
+
+    /// Get the current sampling rate
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampler.rate()
+    }
+
+    /// Get the sampling overrides
+    pub fn sampling_overrides(&self) -> &SamplingOverrides {
+        &self.sampling_overrides
+    }
+
+    /// Check that the Untrace API is reachable and accepts the configured API key
+    pub async fn health_check(&self) -> UntraceResult<()> {
+        self.exporter.health_check().await
+    }
+
+    /// Render the current metrics in the Prometheus text format
+    ///
+    /// Returns an empty string if the metrics could not be collected.
+    #[cfg(feature = "prometheus")]
+    pub fn metrics_text(&self) -> String {
+        self.prometheus_exporter.metrics_text().unwrap_or_else(|err| {
+            warn!("Failed to collect Prometheus metrics: {}", err);
+            String::new()
+        })
+    }
+
+    /// Record panics as error spans named `panic`
+    ///
+    /// The hook runs before the previously installed hook, which still prints
+    /// the panic as usual. Only the first call in a process installs a hook;
+    /// later calls, from this or any other instance, do nothing.
+    pub fn install_panic_hook(&self) {
+        if PANIC_HOOK_INSTALLED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let tracer = self.tracer().clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string());
+
+            let mut span = tracer.start_span("panic");
+            let mut attributes = vec![helpers::string(panic::MESSAGE, &message)];
+            if let Some(location) = info.location() {
+                attributes.push(helpers::string(panic::LOCATION, &location.to_string()));
+            }
+            if let Some(name) = std::thread::current().name() {
+                attributes.push(helpers::string(panic::THREAD, name));
+            }
+            tracer.set_span_attributes(&mut span, attributes);
+            span.set_status(Status::error(message));
+            span.end();
+
+            previous(info);
+        }));
+    }
+
     /// Shutdown the SDK
     pub async fn shutdown(&self) -> UntraceResult<()> {
+        self.shutdown_with_report().await.map(|_| ())
+    }
+
+    /// Shutdown the SDK and report how many spans were exported or dropped
+    ///
+    /// Pending spans are flushed first. A failed final flush doesn't fail the
+    /// shutdown; the affected spans are counted as dropped instead. Span and
+    /// metric export then stop, and spans ending afterwards are dropped.
+    pub async fn shutdown_with_report(&self) -> UntraceResult<ShutdownReport> {
         info!("Shutting down Untrace SDK");
+        let started = Instant::now();
 
         // Disable instrumentation
         if self.instrumentation.is_enabled() {
@@ -151,17 +735,123 @@ impl Untrace {
             warn!("Instrumentation is still enabled during shutdown");
         }
 
+        // Bound the final exports only; the limit is lifted when this returns
+        let _retry_limit = self
+            .exporter
+            .limit_retries(SHUTDOWN_RETRY_BUDGET.min(self.config.retry.total_budget));
+        if let Err(err) = self.flush_all().await {
+            warn!("Failed to flush spans during shutdown: {}", err);
+        }
+
+        // Stop the span pipeline and the metric reader, joining their workers
+        let span_sink = Arc::clone(&self.span_sink);
+        if let Err(err) = force_flush(move || span_sink.shutdown()).await? {
+            warn!("Failed to shut down span export: {}", err);
+        }
+        let meter_provider = self.meter_provider.clone();
+        if let Err(err) = force_flush(move || meter_provider.shutdown()).await? {
+            warn!("Failed to shut down metric export: {}", err);
+        }
+
         // Shutdown client
         self.client.shutdown().await?;
 
-        debug!("Untrace SDK shutdown complete");
-        Ok(())
+        let report = ShutdownReport {
+            flushed_spans: self.span_counts.exported(),
+            dropped_spans: self.span_counts.unexported(),
+            duration: started.elapsed(),
+        };
+        debug!("Untrace SDK shutdown complete: {:?}", report);
+        Ok(report)
+    }
+
+    /// Flush automatically once `n` spans have ended since the last flush
+    ///
+    /// Applies on top of the batch size and export interval. Pass 0 to turn
+    /// count-based flushing off again.
+    pub fn flush_every(&self, n: usize) {
+        self.flush_policy.set_every(n);
+    }
+
+    /// Export spans received as OTLP/JSON along with this instance's own
+    ///
+    /// For relaying spans recorded elsewhere. `bytes` is a trace export
+    /// request in the OTLP/JSON format, as sent by other OpenTelemetry SDKs
+    /// and written by [`Protocol::HttpJson`](crate::Protocol::HttpJson), e.g.
+    /// a line of a [`FileExporter`] file. The spans are queued like spans
+    /// ending in this process, so they are redacted and batched the same
+    /// way, but skip sampling. Spans whose trace flags show they weren't
+    /// sampled are left out. Returns the number of spans queued.
+    pub fn ingest_otlp_json(&self, bytes: &[u8]) -> UntraceResult<usize> {
+        let mut batch = otlp_json::decode_traces(bytes)?;
+        batch.retain(|span| span.span_context.is_sampled());
+        let count = batch.len();
+        for span in batch {
+            self.span_counts.record_ended();
+            self.span_sink.send(span);
+        }
+        Ok(count)
     }
 
     /// Flush any pending data
+    ///
+    /// Exports all spans that have ended but are still queued for this
+    /// instance, and pushes the metrics recorded so far.
     pub async fn flush(&self) -> UntraceResult<()> {
+        self.flush_all().await?;
         self.client.flush().await
     }
+
+    /// Export the spans that have ended but are still queued, without pushing metrics
+    pub async fn flush_spans(&self) -> UntraceResult<()> {
+        self.flush_check.mark_flushed();
+        let tracer_provider = self.tracer_provider.clone();
+        for result in force_flush(move || tracer_provider.force_flush()).await? {
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Push the metrics recorded so far, without exporting queued spans
+    ///
+    /// Useful to emit metrics right away, e.g. before a Prometheus scrape.
+    pub async fn flush_metrics(&self) -> UntraceResult<()> {
+        let meter_provider = self.meter_provider.clone();
+        force_flush(move || meter_provider.force_flush())
+            .await?
+            .map_err(|err| UntraceError::export(format!("Failed to flush metrics: {}", err)))
+    }
+
+    /// Export all queued spans and metrics, and log records with the `logs` feature
+    async fn flush_all(&self) -> UntraceResult<()> {
+        #[cfg(feature = "logs")]
+        {
+            let logger_provider = self.logger_provider.clone();
+            for result in force_flush(move || logger_provider.force_flush()).await? {
+                if let Err(err) = result {
+                    warn!("Failed to flush log records: {}", err);
+                }
+            }
+        }
+        if let Err(err) = self.flush_metrics().await {
+            warn!("{}", err);
+        }
+        self.flush_spans().await
+    }
+}
+
+/// Run a provider's flush or shutdown off the async runtime's threads
+///
+/// The batch processor and periodic reader block until their workers have
+/// exported.
+async fn force_flush<T, F>(flush: F) -> UntraceResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(flush)
+        .await
+        .map_err(|err| UntraceError::export(format!("Flush task failed: {}", err)))
 }
 
 impl Clone for Untrace {
@@ -170,7 +860,107 @@ impl Clone for Untrace {
             client: self.client.clone(),
             instrumentation: self.instrumentation.clone(),
             provider_registry: self.provider_registry.clone(),
+            sampling_overrides: self.sampling_overrides.clone(),
+            sampler: self.sampler.clone(),
+            flush_policy: self.flush_policy.clone(),
+            span_counts: self.span_counts.clone(),
+            span_sink: Arc::clone(&self.span_sink),
+            exporter: self.exporter.clone(),
+            tracer_provider: self.tracer_provider.clone(),
+            meter_provider: self.meter_provider.clone(),
+            #[cfg(feature = "prometheus")]
+            prometheus_exporter: self.prometheus_exporter.clone(),
+            #[cfg(feature = "logs")]
+            logger_provider: self.logger_provider.clone(),
             config: Arc::clone(&self.config),
+            disabled: self.disabled,
+            flush_check: Arc::clone(&self.flush_check),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Fluent configuration and initialization, from [`Untrace::builder`]
+///
+/// Each method delegates to the matching `Config::with_*` builder. Settings
+/// without a method here can be applied with [`UntraceBuilder::configure`].
+#[derive(Debug, Clone)]
+pub struct UntraceBuilder {
+    config: Config,
+}
+
+impl UntraceBuilder {
+    /// Set the service name
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.config = self.config.with_service_name(service_name.into());
+        self
+    }
+
+    /// Set the service version
+    pub fn service_version(mut self, service_version: impl Into<String>) -> Self {
+        self.config = self.config.with_service_version(service_version.into());
+        self
+    }
+
+    /// Set the environment
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.config = self.config.with_environment_typed(environment);
+        self
+    }
+
+    /// Set the region, which selects the default base URL
+    pub fn region(mut self, region: Region) -> Self {
+        self.config = self.config.with_region(region);
+        self
+    }
+
+    /// Set the base URL of the Untrace API
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.config = self.config.with_base_url(base_url.into());
+        self
+    }
+
+    /// Set the fraction of traces sampled
+    pub fn sampling_rate(mut self, sampling_rate: f64) -> Self {
+        self.config = self.config.with_sampling_rate(sampling_rate);
+        self
+    }
+
+    /// Set whether to capture request/response bodies
+    pub fn capture_body(mut self, capture_body: bool) -> Self {
+        self.config = self.config.with_capture_body(capture_body);
+        self
+    }
+
+    /// Enable debug mode
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config = self.config.with_debug(debug);
+        self
+    }
+
+    /// Apply any other configuration
+    pub fn configure(mut self, configure: impl FnOnce(Config) -> Config) -> Self {
+        self.config = configure(self.config);
+        self
+    }
+
+    /// Get the configuration built so far
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Initialize the global instance, like [`Untrace::init`]
+    pub async fn init(self) -> UntraceResult<Untrace> {
+        Untrace::init(self.config).await
+    }
+
+    /// Initialize a non-global instance, like [`Untrace::init_named`]
+    pub async fn init_named(self) -> UntraceResult<Untrace> {
+        Untrace::init_named(self.config).await
+    }
+}
+
+impl From<UntraceBuilder> for Config {
+    fn from(builder: UntraceBuilder) -> Self {
+        builder.config
+    }
+}